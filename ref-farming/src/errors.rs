@@ -4,24 +4,60 @@ pub const ERR11_INSUFFICIENT_STORAGE: &str = "E11: insufficient $NEAR storage de
 pub const ERR12_STORAGE_UNREGISTER_REWARDS_NOT_EMPTY: &str = "E12: still has rewards when unregister";
 pub const ERR13_STORAGE_UNREGISTER_SEED_NOT_EMPTY: &str = "E13: still has staked seed when unregister";
 pub const ERR14_ACC_ALREADY_REGISTERED: &str = "E14: account already registered";
+pub const ERR15_INVALID_BATCH_SIZE: &str = "E15: invalid batch size";
+pub const ERR16_NOT_CLAIM_DELEGATE: &str = "E16: caller is not the claim delegate";
+pub const ERR17_STAKER_NOT_REGISTERED: &str = "E17: account not registered, call storage_deposit on the farming contract before staking";
 
 // Reward errors //
 pub const ERR21_TOKEN_NOT_REG: &str = "E21: token not registered";
 pub const ERR22_NOT_ENOUGH_TOKENS: &str = "E22: not enough tokens in deposit";
+pub const ERR23_CLAIMED_BELOW_MIN_EXPECTED: &str = "E23: claimed reward below min_expected";
 
 pub const ERR25_CALLBACK_POST_WITHDRAW_INVALID: &str = "E25: expected 1 promise result from withdraw";
 
 // Seed errors //
+pub const ERR30_OUT_OF_STAKE_BOUNDS: &str = "E30: farmer stake out of seed bounds";
 pub const ERR31_SEED_NOT_EXIST: &str = "E31: seed not exist";
 pub const ERR32_NOT_ENOUGH_SEED: &str = "E32: not enough amount of seed";
 pub const ERR33_INVALID_SEED_ID: &str = "E33: invalid seed id";
 pub const ERR34_BELOW_MIN_SEED_DEPOSITED: &str = "E34: below min_deposit of this seed";
 pub const ERR35_ILLEGAL_TOKEN_ID: &str = "E35: illegal token_id in mft_transfer_call";
+pub const ERR36_INVALID_WEIGHT_SCALE: &str = "E36: invalid nft weight scale";
+pub const ERR37_NOT_FT_SEED: &str = "E37: not a FT seed, use withdraw_nft";
+pub const ERR38_NOT_NFT_SEED: &str = "E38: not a NFT seed, use withdraw_seed";
+pub const ERR39_INVALID_NFT_TIERS: &str = "E39: too many nft tiers or prefix in multiple tiers";
 
 // farm errors //
+pub const ERR40_REWARD_NOT_SEED_TOKEN: &str = "E40: no farm of this seed rewards in the seed token";
 pub const ERR41_FARM_NOT_EXIST: &str = "E41: farm not exist";
 pub const ERR42_INVALID_FARM_ID: &str = "E42: invalid farm id";
 pub const ERR43_INVALID_FARM_STATUS: &str = "E43: invalid farm status";
 pub const ERR44_INVALID_FARM_REWARD: &str = "E44: invalid reward token for this farm";
+pub const ERR45_INVALID_FEE_BPS: &str = "E45: invalid fee bps";
+pub const ERR46_NOT_FARM_CREATOR: &str = "E46: only farm creator can do this";
+pub const ERR47_FARM_DISTRIBUTION_STARTED: &str = "E47: farm has started distribution";
+pub const ERR48_INVALID_ACCRUAL_GRANULARITY: &str = "E48: invalid accrual granularity";
+pub const ERR49_TOO_MANY_REWARD_TOKENS: &str = "E49: too many reward tokens for this farm";
+pub const ERR50_INVALID_DECAY: &str = "E50: invalid decay factor";
+pub const ERR53_INVALID_BENEFICIARIES: &str = "E53: beneficiary weights must sum to 10000";
+pub const ERR54_BELOW_MIN_ROUNDS: &str = "E54: reward too small for the farm's min rounds";
+pub const ERR55_INVALID_END_AT: &str = "E55: end_at must be after start_at and now";
+pub const ERR56_FARM_NOT_ENDED_BY_DATE: &str = "E56: farm has no reward left past its end_at";
+pub const ERR57_INVALID_REWARD_RATE: &str = "E57: invalid reward rate";
+pub const ERR58_REWARD_TOKEN_CHANGED: &str = "E58: reward token of a farm can not change";
+pub const ERR59_INVALID_SEED_ALIAS: &str = "E59: invalid seed alias";
+pub const ERR60_INVALID_REFERRER: &str = "E60: referrer must be another registered account, set only once";
+pub const ERR61_INVALID_REFERRAL_BPS: &str = "E61: referral bps above max";
+pub const ERR62_ALIAS_AMOUNT_EXCEEDS_SEED: &str = "E62: seed staked through aliases above seed amount";
+pub const ERR63_ABOVE_DECAYED_TOTAL: &str = "E63: reward above what the decaying farm ever releases";
+pub const ERR64_NFT_WEIGHT_OVERFLOW: &str = "E64: scaled nft weight overflows";
+pub const ERR65_CALLBACK_POST_FT_METADATA_INVALID: &str = "E65: expected 1 promise result from ft_metadata";
+pub const ERR66_BLOCK_HEIGHT_OVERFLOW: &str = "E66: block height beyond u32 timeline";
+pub const ERR67_NO_STATE_TO_MIGRATE: &str = "E67: no state to migrate";
+pub const ERR68_MISSING_TOKEN_BALANCE: &str = "E68: balance of every seed and reward token is needed to migrate";
+pub const ERR69_TOO_MANY_FARMS: &str = "E69: too many farms in this seed";
+
+pub const ERR51_TOKEN_INSOLVENT: &str = "E51: token balance below liabilities";
+pub const ERR52_NOT_ENOUGH_RECOVERABLE: &str = "E52: not enough tokens beyond liabilities";
 
 pub const ERR500: &str = "E500: Internal ERROR!";
\ No newline at end of file
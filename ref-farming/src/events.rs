@@ -0,0 +1,111 @@
+//! Structured events for monitoring, logged in NEP-297 format:
+//! `EVENT_JSON:{"standard":"ref-farming","version":"1.0.0","event":..,"data":[..]}`
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::serde_json::json;
+use near_sdk::{env, AccountId};
+
+pub const EVENT_STANDARD: &str = "ref-farming";
+pub const EVENT_STANDARD_VERSION: &str = "1.0.0";
+
+/// machine-readable reasons of a failed withdraw
+pub const REASON_FT_TRANSFER_FAILED: &str = "ft_transfer_failed";
+pub const REASON_NFT_TRANSFER_FAILED: &str = "nft_transfer_failed";
+
+/// how ft_on_transfer handled a transfer
+pub const ROUTE_SEED: &str = "seed";
+pub const ROUTE_REWARD: &str = "reward";
+pub const ROUTE_REFUND: &str = "refund";
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtTransferRoutedData {
+    pub route: String,
+    pub token_id: AccountId,
+    pub sender_id: AccountId,
+    pub amount: U128,
+    /// part of amount returned to the token contract as unused
+    pub refund: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub farm_id: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct WithdrawFailedData {
+    pub reason: String,
+    pub account_id: AccountId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<AccountId>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub amount: Option<U128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nft_token_id: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmRateChangedData {
+    pub farm_id: String,
+    pub old_reward_per_session: U128,
+    pub new_reward_per_session: U128,
+    pub effective_round: u32,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SeedDepositData {
+    pub account_id: AccountId,
+    pub seed_id: String,
+    /// seed deposited, for nft its balance equivalent
+    pub amount: U128,
+    /// stake of the account in the seed after the deposit
+    pub farmer_seed: U128,
+    /// total staked in the seed after the deposit
+    pub seed_total: U128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nft_token_id: Option<String>,
+}
+
+/// a claim from a farm the account has no stake in, so it got nothing
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ClaimNoStakeData {
+    pub account_id: AccountId,
+    pub farm_id: String,
+}
+
+pub(crate) fn emit_event<T: Serialize>(event: &str, data: T) {
+    let log = json!({
+        "standard": EVENT_STANDARD,
+        "version": EVENT_STANDARD_VERSION,
+        "event": event,
+        "data": [data],
+    });
+    env::log(format!("EVENT_JSON:{}", log).as_bytes());
+}
+
+pub(crate) fn emit_withdraw_failed(data: WithdrawFailedData) {
+    emit_event("withdraw_failed", data);
+}
+
+pub(crate) fn emit_ft_transfer_routed(data: FtTransferRoutedData) {
+    emit_event("ft_transfer_routed", data);
+}
+
+pub(crate) fn emit_seed_deposit(data: SeedDepositData) {
+    emit_event("seed_deposit", data);
+}
+
+pub(crate) fn emit_claim_no_stake(data: ClaimNoStakeData) {
+    emit_event("claim_no_stake", data);
+}
+
+pub(crate) fn emit_farm_rate_changed(data: FarmRateChangedData) {
+    emit_event("farm_rate_changed", data);
+}
@@ -7,10 +7,15 @@
 //! token to the farm, after it was created.
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{UnorderedMap, Vector};
+use near_sdk::IntoStorageKey;
 use near_sdk::json_types::{U128, ValidAccountId};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{env, AccountId, Balance};
+use std::collections::HashMap;
+use std::convert::TryFrom;
 use crate::SeedId;
+use crate::farm_seed::FarmSeedMetadata;
 use crate::errors::*;
 use crate::utils::*;
 use uint::construct_uint;
@@ -31,6 +36,9 @@ pub type RPS = [u8; 32];
 // this value should be carefully choosen, now is 10**24.
 pub const DENOM: u128 = 1_000_000_000_000_000_000_000_000;
 
+// fee_bps is in basis points of this divisor
+pub const FEE_DIVISOR: u32 = 10_000;
+
 ///   The terms defines how the farm works.
 ///   In this version, we distribute reward token with a start height, a reward 
 /// session interval, and reward amount per session.  
@@ -44,6 +52,135 @@ pub struct FarmTerms {
     pub start_at: TimestampSec,
     pub reward_per_session: Balance,
     pub session_interval: TimestampSec,
+    /// if true, start_at and session_interval are in block height instead of seconds
+    pub by_block_height: bool,
+    /// reward accrues every accrual_granularity, pro rata of reward_per_session,
+    /// must divide session_interval, 0 means accrue per session.
+    pub accrual_granularity: TimestampSec,
+    /// reward per round is multiplied by decay_factor_bps / FEE_DIVISOR
+    /// every decay_period_rounds, 0 decay_period_rounds means no decay.
+    pub decay_factor_bps: u16,
+    pub decay_period_rounds: u32,
+    /// no reward accrues past end_at, on the same timeline as start_at, 0 means no end
+    pub end_at: TimestampSec,
+}
+
+impl FarmTerms {
+    /// reward tokens of the farm, only one for now.
+    pub fn reward_tokens(&self) -> Vec<AccountId> {
+        vec![self.reward_token.clone()]
+    }
+
+    /// length of a distribution round
+    pub fn round_interval(&self) -> u32 {
+        if self.accrual_granularity > 0 {
+            self.accrual_granularity
+        } else {
+            self.session_interval
+        }
+    }
+
+    /// reward released per distribution round, rounded down
+    pub fn reward_per_round(&self) -> Balance {
+        (U256::from(self.reward_per_session) * U256::from(self.round_interval())
+            / U256::from(self.session_interval))
+        .as_u128()
+    }
+
+    /// current point on the farm's timeline, in seconds or block height
+    pub fn now(&self) -> u32 {
+        if self.by_block_height {
+            u32::try_from(env::block_index()).expect(ERR66_BLOCK_HEIGHT_OVERFLOW)
+        } else {
+            to_sec(env::block_timestamp())
+        }
+    }
+
+    /// round at end_at, where rr stops growing, None if the farm has no end date
+    pub fn end_round(&self) -> Option<u32> {
+        if self.end_at > 0 {
+            Some(self.end_at.saturating_sub(self.start_at) / self.round_interval())
+        } else {
+            None
+        }
+    }
+
+    /// whether given round is at or past end_at
+    pub fn ended_by_date(&self, rr: u32) -> bool {
+        self.end_round().map_or(false, |end_rr| rr >= end_rr)
+    }
+
+    pub fn decays(&self) -> bool {
+        self.decay_period_rounds > 0 && (self.decay_factor_bps as u32) < FEE_DIVISOR
+    }
+
+    /// reward released in rounds [0, rounds) of a decaying farm,
+    /// each round of the k-th decay period releases reward_per_round * factor^k.
+    fn decayed_cumulative_reward(&self, rounds: u32) -> U256 {
+        let scale = U256::from(DENOM);
+        let factor = U256::from(self.decay_factor_bps) * scale / U256::from(FEE_DIVISOR);
+        let factor_pow = pow_scaled(factor, rounds / self.decay_period_rounds, scale);
+        // sum of factor^k over the full periods passed
+        let full_periods = (scale - factor_pow) * scale / (scale - factor);
+        let reward_per_round = U256::from(self.reward_per_round());
+        reward_per_round * U256::from(self.decay_period_rounds) * full_periods / scale
+            + reward_per_round * U256::from(rounds % self.decay_period_rounds) * factor_pow / scale
+    }
+
+    /// reward released in rounds [from, to)
+    pub fn reward_in_rounds(&self, from: u32, to: u32) -> Balance {
+        if self.decays() {
+            self.decayed_cumulative_reward(to)
+                .saturating_sub(self.decayed_cumulative_reward(from))
+                .as_u128()
+        } else {
+            (to - from) as u128 * self.reward_per_round()
+        }
+    }
+
+    /// all reward a decaying farm would ever release, None if it does not decay.
+    pub fn decayed_total_reward(&self) -> Option<Balance> {
+        if self.decays() {
+            Some(self.reward_in_rounds(0, u32::MAX))
+        } else {
+            None
+        }
+    }
+
+    /// least rounds from given round to release given amount, including a tail round
+    /// releasing less than its full reward, None if it would never be released.
+    pub fn rounds_to_release(&self, from: u32, amount: Balance) -> Option<u32> {
+        if !self.decays() {
+            let reward_per_round = self.reward_per_round();
+            return Some((amount / reward_per_round + (amount % reward_per_round > 0) as u128) as u32);
+        }
+        let (mut low, mut high) = (0, u32::MAX - from);
+        if self.reward_in_rounds(from, from + high) < amount {
+            return None;
+        }
+        while low < high {
+            let mid = low + (high - low) / 2;
+            if self.reward_in_rounds(from, from + mid) >= amount {
+                high = mid;
+            } else {
+                low = mid + 1;
+            }
+        }
+        Some(low)
+    }
+}
+
+/// base^exp, where base and the result are fixed point numbers of given scale.
+fn pow_scaled(mut base: U256, mut exp: u32, scale: U256) -> U256 {
+    let mut result = scale;
+    while exp > 0 && !result.is_zero() {
+        if exp & 1 == 1 {
+            result = result * base / scale;
+        }
+        exp >>= 1;
+        base = base * base / scale;
+    }
+    result
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -54,6 +191,30 @@ pub struct HRFarmTerms {
     pub start_at: u32,
     pub reward_per_session: U128,
     pub session_interval: u32,
+    #[serde(default)]
+    pub by_block_height: bool,
+    #[serde(default)]
+    pub accrual_granularity: u32,
+    #[serde(default)]
+    pub decay_factor_bps: u16,
+    #[serde(default)]
+    pub decay_period_rounds: u32,
+    #[serde(default)]
+    pub end_at: u32,
+}
+
+/// Arguments of create_farm, terms and options of the farm in one struct,
+/// options left out take the same defaults as in create_simple_farm.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct CreateFarmArgs {
+    pub terms: HRFarmTerms,
+    #[serde(default)]
+    pub min_deposit: Option<U128>,
+    #[serde(default)]
+    pub nft_balance: Option<HashMap<NFTTokenId, U128>>,
+    #[serde(default)]
+    pub metadata: Option<FarmSeedMetadata>,
 }
 
 impl From<&HRFarmTerms> for FarmTerms {
@@ -64,13 +225,18 @@ impl From<&HRFarmTerms> for FarmTerms {
             start_at: terms.start_at,
             reward_per_session: terms.reward_per_session.into(),
             session_interval: terms.session_interval,
+            by_block_height: terms.by_block_height,
+            accrual_granularity: terms.accrual_granularity,
+            decay_factor_bps: terms.decay_factor_bps,
+            decay_period_rounds: terms.decay_period_rounds,
+            end_at: terms.end_at,
         }
     }
 }
 
 #[derive(BorshSerialize, BorshDeserialize, Clone)]
 pub enum FarmStatus {
-    Created, Running, Ended, Cleared
+    Created, Running, Ended, Cleared, Paused
 }
 
 impl From<&FarmStatus> for String {
@@ -80,10 +246,31 @@ impl From<&FarmStatus> for String {
             FarmStatus::Running => { String::from("Running") },
             FarmStatus::Ended => { String::from("Ended") },
             FarmStatus::Cleared => { String::from("Cleared") },
+            FarmStatus::Paused => { String::from("Paused") },
         }
     }
 }
 
+/// A change of reward_per_session, kept in the farm's audit log.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RateChange {
+    pub old_reward_per_session: U128,
+    pub new_reward_per_session: U128,
+    /// first round released at the new rate
+    pub effective_round: u32,
+}
+
+/// State of a farm at some point in time, for APR charts.
+#[derive(BorshSerialize, BorshDeserialize, Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmSnapshot {
+    pub timestamp: TimestampSec,
+    /// seed counted by the farm
+    pub total_seed: U128,
+    pub cumulative_claimed: U128,
+}
+
 /// Reward Distribution Record
 #[derive(BorshSerialize, BorshDeserialize, Clone, Default)]
 pub struct FarmRewardDistribution {
@@ -95,7 +282,9 @@ pub struct FarmRewardDistribution {
     /// rps(cur) = rps(prev) + distributing_reward / total_seed_staked
     pub rps: RPS,
     /// Reward_Round
-    /// rr = (cur_block_timestamp in sec - start_at) / session_interval
+    /// rr = (cur_block_timestamp in sec - start_at) / round_interval
+    /// or (cur_block_height - start_at) / round_interval in block height mode,
+    /// round_interval is accrual_granularity if set, else session_interval
     pub rr: u32,
 }
 
@@ -105,6 +294,9 @@ pub struct FarmRewardDistribution {
 pub struct Farm {
 
     pub farm_id: FarmId,
+
+    /// who created this farm, can claw back reward before distribution starts
+    pub creator_id: AccountId,
     
     pub terms: FarmTerms,
 
@@ -119,18 +311,65 @@ pub struct Farm {
     pub amount_of_claimed: Balance,
     /// when there is no seed token staked, reward goes to beneficiary
     pub amount_of_beneficiary: Balance,
+    /// protocol cut of each claim, in bps of FEE_DIVISOR
+    pub fee_bps: u16,
+    /// if true, once the farm ended, the last staker takes all unclaimed dust
+    pub sweep_dust: bool,
+    /// seed staked by farmers who opted out of this farm
+    pub opted_out_seed: Balance,
+    /// when the farm was paused, on the farm's timeline
+    pub paused_at: u32,
+    /// claim from this farm below this amount is deferred, 0 means no limit
+    pub min_claim_amount: Balance,
+    /// reward deposited by each funder,
+    /// at most MAX_FARM_FUNDERS of them, the rest under OTHER_FUNDERS
+    pub funders: HashMap<AccountId, Balance>,
+    /// who reward goes to when there is no seed token staked, split by weight in bps
+    /// of FEE_DIVISOR, empty means all to the creator
+    pub beneficiaries: Vec<(AccountId, u16)>,
+    /// part of amount_of_beneficiary went to each beneficiary
+    pub beneficiary_rewards: HashMap<AccountId, Balance>,
+    /// part of beneficiary_rewards not yet withdrawn by each beneficiary
+    pub beneficiary_balances: HashMap<AccountId, Balance>,
+    /// max amount_of_beneficiary in bps of amount_of_reward, 0 means no cap,
+    /// once reached, rounds with nothing staked emit nothing
+    pub beneficiary_cap_bps: u16,
+    /// first deposit of reward must last at least this many rounds, 0 means no limit
+    pub min_rounds: u32,
+    /// decimals of the reward token, fetched on first deposit of reward, for UIs
+    pub reward_token_decimals: Option<u8>,
+    /// latest changes of reward_per_session, at most MAX_FARM_RATE_CHANGES of them
+    pub rate_changes: Vec<RateChange>,
+    /// latest snapshots taken by snapshot_farm, at most MAX_FARM_SNAPSHOTS of them
+    pub snapshots: Vec<FarmSnapshot>,
 }
 
 impl Farm {
     pub fn new(
         id: FarmId,
+        creator_id: AccountId,
         terms: FarmTerms,
     ) -> Self {
         Self {
             farm_id: id.clone(),
+            creator_id,
             amount_of_reward: 0,
             amount_of_claimed: 0,
             amount_of_beneficiary: 0,
+            fee_bps: 0,
+            sweep_dust: false,
+            opted_out_seed: 0,
+            paused_at: 0,
+            min_claim_amount: 0,
+            funders: HashMap::new(),
+            beneficiaries: vec![],
+            beneficiary_rewards: HashMap::new(),
+            beneficiary_balances: HashMap::new(),
+            beneficiary_cap_bps: 0,
+            min_rounds: 0,
+            reward_token_decimals: None,
+            rate_changes: vec![],
+            snapshots: vec![],
 
             status: FarmStatus::Created,
             last_distribution: FarmRewardDistribution::default(),
@@ -138,11 +377,20 @@ impl Farm {
         }
     }
 
+    /// current point on the farm's timeline,
+    /// block height in block height mode, else timestamp in sec.
+    fn now(&self) -> u32 {
+        self.terms.now()
+    }
+
     /// return None if the farm can not accept reward anymore
     /// else return amount of undistributed reward 
-    pub fn add_reward(&mut self, amount: &Balance) -> Option<Balance> {
+    pub fn add_reward(&mut self, sender_id: &AccountId, amount: &Balance) -> Option<Balance> {
+        if !self.can_accept_reward() {
+            return None;
+        }
 
-        match self.status {
+        let ret = match self.status {
             FarmStatus::Created => {
                 // When a farm gots first deposit of reward, it turns to Running state,
                 // but farming or not depends on `start_at` 
@@ -150,58 +398,129 @@ impl Farm {
                 if self.terms.start_at == 0 {
                     // for a farm without start time, the first deposit of reward 
                     // would trigger the farming
-                    self.terms.start_at = to_sec(env::block_timestamp());
+                    self.terms.start_at = self.now();
                 }
                 self.amount_of_reward += amount;
                 self.last_distribution.undistributed += amount;
                 Some(self.last_distribution.undistributed)
             },
             FarmStatus::Running => {
-                if let Some(dis) = self.try_distribute(&DENOM) {
-                    if dis.undistributed == 0 {
-                        // farm has ended actually
-                        return None;
-                    }
-                }
-                // For a running farm, can add reward to extend duration
+                // For a running farm, can add reward to extend duration,
+                // start_at is never touched once running.
                 self.amount_of_reward += amount;
                 self.last_distribution.undistributed += amount;
                 Some(self.last_distribution.undistributed)
             },
             _ => {None},
+        };
+        if ret.is_some() {
+            self.add_funder(sender_id, *amount);
+        }
+        ret
+    }
+
+    /// whether reward can be deposited into this farm now,
+    /// false once it has ended, even if not yet settled as Ended.
+    pub fn can_accept_reward(&self) -> bool {
+        match self.status {
+            FarmStatus::Created => self.terms.end_at == 0 || self.now() < self.terms.end_at,
+            FarmStatus::Running => match self.try_distribute(&DENOM) {
+                Some(dis) => dis.undistributed > 0 && !self.terms.ended_by_date(dis.rr),
+                // not started yet
+                None => true,
+            },
+            _ => false,
+        }
+    }
+
+    /// whether depositing amount of reward keeps the farm running for min_rounds,
+    /// only checked on the first deposit, when the farm is not running yet.
+    pub fn sustains_min_rounds(&self, amount: Balance) -> bool {
+        if let FarmStatus::Created = self.status {
+            self.last_distribution.undistributed + amount
+                >= self.terms.reward_per_round().saturating_mul(self.min_rounds as u128)
+        } else {
+            true
         }
-        
+    }
+
+    /// whether a decaying farm would ever release all its reward after depositing amount,
+    /// reward beyond that would be stuck in a farm never ending.
+    pub fn within_decayed_total(&self, amount: Balance) -> bool {
+        self.terms
+            .decayed_total_reward()
+            .map_or(true, |total| self.amount_of_reward + amount <= total)
+    }
+
+    fn add_funder(&mut self, sender_id: &AccountId, amount: Balance) {
+        let key = if self.funders.contains_key(sender_id) || self.funders.len() < MAX_FARM_FUNDERS - 1 {
+            sender_id.clone()
+        } else {
+            OTHER_FUNDERS.to_string()
+        };
+        *self.funders.entry(key).or_insert(0) += amount;
     }
 
 
+    /// Take back all undistributed reward and reset the farm to Created,
+    /// only works if no round has been distributed yet.
+    /// return None if the farm has started distribution.
+    pub fn clawback(&mut self) -> Option<Balance> {
+        if let FarmStatus::Running = self.status {
+            let cur_rr = self.try_distribute(&DENOM).map(|dis| dis.rr).unwrap_or(0);
+            if cur_rr == 0 
+                && self.last_distribution.rr == 0 
+                && self.last_distribution.unclaimed == 0 
+                && self.amount_of_claimed == 0 {
+                let amount = self.last_distribution.undistributed;
+                if self.now() >= self.terms.start_at {
+                    // let next deposit of reward trigger the farming again
+                    self.terms.start_at = 0;
+                }
+                self.amount_of_reward -= amount;
+                self.funders.clear();
+                self.last_distribution = FarmRewardDistribution::default();
+                self.status = FarmStatus::Created;
+                return Some(amount);
+            }
+        }
+        None
+    }
+
     /// Try to distribute reward according to current timestamp
     /// return None if farm is not in Running state or haven't start farming yet;
     /// return new dis :FarmRewardDistribution 
     /// Note, if total_seed is 0, the rps in new dis would be reset to 0 too.
     pub fn try_distribute(&self, total_seeds: &Balance) -> Option<FarmRewardDistribution> {
+        self.try_distribute_at(total_seeds, self.now())
+    }
+
+    /// Same as try_distribute, but as of given point on the farm's timeline,
+    /// which should not be earlier than the last distribution.
+    pub fn try_distribute_at(&self, total_seeds: &Balance, now: u32) -> Option<FarmRewardDistribution> {
 
         if let FarmStatus::Running = self.status {
-            if env::block_timestamp() < to_nano(self.terms.start_at) {
+            if now < self.terms.start_at {
                 // a farm haven't start yet
                 return None;
             }
             let mut dis = self.last_distribution.clone();
             // calculate rr according to cur_timestamp
-            dis.rr = (to_sec(env::block_timestamp()) - self.terms.start_at) / self.terms.session_interval;
-            let mut reward_added = (dis.rr - self.last_distribution.rr) as u128 
-                * self.terms.reward_per_session;
+            dis.rr = (now - self.terms.start_at) / self.terms.round_interval();
+            if let Some(end_rr) = self.terms.end_round() {
+                // nothing accrues past end_at
+                dis.rr = std::cmp::min(dis.rr, std::cmp::max(end_rr, self.last_distribution.rr));
+            }
+            let mut reward_added = self.terms.reward_in_rounds(self.last_distribution.rr, dis.rr);
             if self.last_distribution.undistributed < reward_added {
                 // all undistribution would be distributed this time
                 reward_added = self.last_distribution.undistributed;
-                // recalculate rr according to undistributed
-                let increased_rr = (reward_added / self.terms.reward_per_session) as u32;
-                dis.rr = self.last_distribution.rr + increased_rr;
-                let reward_caculated = increased_rr as u128 * self.terms.reward_per_session;
-                if reward_caculated < reward_added {
-                    // add the tail round
-                    dis.rr += 1;
-
-                }
+                // recalculate rr according to undistributed, with the tail round
+                dis.rr = self.last_distribution.rr
+                    + self
+                        .terms
+                        .rounds_to_release(self.last_distribution.rr, reward_added)
+                        .unwrap();
                 // env::log(
                 //     format!(
                 //         "Farm ends at Round #{}, unclaimed reward: {}.",
@@ -231,6 +550,30 @@ impl Farm {
 
     }
 
+    /// Projected end of a running farm on the farm's timeline,
+    /// that is when all reward deposited so far would be distributed, or end_at if earlier.
+    /// return None if the farm is not in Running state,
+    /// or its decaying reward would never be all distributed and it has no end_at.
+    pub fn projected_end_at(&self) -> Option<u32> {
+        if let FarmStatus::Running = self.status {
+            let rounds_left = self
+                .terms
+                .rounds_to_release(self.last_distribution.rr, self.last_distribution.undistributed);
+            let reward_end_at = rounds_left.and_then(|rounds_left| {
+                (self.last_distribution.rr.checked_add(rounds_left)?)
+                    .checked_mul(self.terms.round_interval())?
+                    .checked_add(self.terms.start_at)
+            });
+            match (reward_end_at, self.terms.end_at) {
+                (_, 0) => reward_end_at,
+                (Some(reward_end_at), end_at) => Some(std::cmp::min(reward_end_at, end_at)),
+                (None, end_at) => Some(end_at),
+            }
+        } else {
+            None
+        }
+    }
+
     /// Return how many reward token that the user hasn't claimed yet.
     /// return (cur_rps - last_user_rps) * user_seeds / DENOM
     pub fn view_farmer_unclaimed_reward(
@@ -238,6 +581,18 @@ impl Farm {
         user_rps: &RPS,
         user_seeds: &Balance,
         total_seeds: &Balance,
+    ) -> Balance {
+        self.view_farmer_unclaimed_reward_at(user_rps, user_seeds, total_seeds, self.now())
+    }
+
+    /// Same as view_farmer_unclaimed_reward, but as of given point on the farm's timeline,
+    /// assuming user_seeds and total_seeds stay till then.
+    pub fn view_farmer_unclaimed_reward_at(
+        &self,
+        user_rps: &RPS,
+        user_seeds: &Balance,
+        total_seeds: &Balance,
+        at: u32,
     ) -> Balance {
         if total_seeds == &0 {
             return 0;
@@ -245,15 +600,32 @@ impl Farm {
         if user_seeds == &0 {
             return 0;
         }
-        if let Some(dis) = self.try_distribute(total_seeds) {
-            (U256::from(*user_seeds) 
-            * (U256::from_little_endian(&dis.rps) - U256::from_little_endian(user_rps))
-            / U256::from(DENOM)).as_u128()
+        if let Some(dis) = self.try_distribute_at(total_seeds, std::cmp::max(at, self.now())) {
+            let ended = dis.undistributed == 0 || self.terms.ended_by_date(dis.rr);
+            self.user_reward(&dis, ended, user_rps, user_seeds, total_seeds)
         } else {
-            (U256::from(*user_seeds) 
-            * (U256::from_little_endian(&self.last_distribution.rps) - U256::from_little_endian(user_rps))
-            / U256::from(DENOM)).as_u128()
+            let ended = matches!(self.status, FarmStatus::Ended);
+            self.user_reward(&self.last_distribution, ended, user_rps, user_seeds, total_seeds)
+        }
+    }
+
+    /// reward of user_seeds since user_rps under given distribution,
+    /// the same rounding for claims and views of it.
+    fn user_reward(
+        &self,
+        dis: &FarmRewardDistribution,
+        ended: bool,
+        user_rps: &RPS,
+        user_seeds: &Balance,
+        total_seeds: &Balance,
+    ) -> Balance {
+        if self.sweep_dust && ended && user_seeds > &0 && user_seeds == total_seeds {
+            // the only staker left takes the rounding dust of others too
+            return dis.unclaimed;
         }
+        (U256::from(*user_seeds)
+        * (U256::from_little_endian(&dis.rps) - U256::from_little_endian(user_rps))
+        / U256::from(DENOM)).as_u128()
     }
 
     /// Distribute reward generated from previous distribution to now,
@@ -266,9 +638,13 @@ impl Farm {
                 self.last_distribution = dis.clone();
                 if total_seeds == &0 {
                     // if total_seeds == &0, reward goes to beneficiary,
-                    self.amount_of_claimed += self.last_distribution.unclaimed;
-                    self.amount_of_beneficiary += self.last_distribution.unclaimed;
-                    self.last_distribution.unclaimed = 0;
+                    // the part beyond its cap stays undistributed for future stakers
+                    let room = self.beneficiary_room();
+                    if self.last_distribution.unclaimed > room {
+                        self.last_distribution.undistributed += self.last_distribution.unclaimed - room;
+                        self.last_distribution.unclaimed = room;
+                    }
+                    self.sweep_to_beneficiary();
                 }   
                 if !silent {
                     env::log(
@@ -281,13 +657,81 @@ impl Farm {
                 }
                 
             }
-            if self.last_distribution.undistributed == 0 {
+            if self.last_distribution.undistributed == 0
+                || self.terms.ended_by_date(self.last_distribution.rr) {
                 self.status = FarmStatus::Ended;
             }
         } 
     }
 
+    /// beneficiaries with their weights, the creator takes all if none set.
+    pub fn get_beneficiaries(&self) -> Vec<(AccountId, u16)> {
+        if self.beneficiaries.is_empty() {
+            vec![(self.creator_id.clone(), FEE_DIVISOR as u16)]
+        } else {
+            self.beneficiaries.clone()
+        }
+    }
+
+    /// the first beneficiary, who also takes the rounding dust of a split.
+    pub fn get_beneficiary_id(&self) -> AccountId {
+        self.get_beneficiaries()[0].0.clone()
+    }
+
+    /// how much more reward beneficiary can take under beneficiary_cap_bps.
+    pub fn beneficiary_room(&self) -> Balance {
+        if self.beneficiary_cap_bps == 0 {
+            return Balance::MAX;
+        }
+        let cap = (U256::from(self.amount_of_reward) * U256::from(self.beneficiary_cap_bps)
+            / U256::from(FEE_DIVISOR))
+        .as_u128();
+        cap.saturating_sub(self.amount_of_beneficiary)
+    }
+
+    /// all unclaimed reward goes to current beneficiaries by weight,
+    /// the first one takes the rounding dust.
+    fn sweep_to_beneficiary(&mut self) {
+        let amount = self.last_distribution.unclaimed;
+        self.amount_of_claimed += amount;
+        self.amount_of_beneficiary += amount;
+        if amount > 0 {
+            let beneficiaries = self.get_beneficiaries();
+            let mut shares: Vec<Balance> = beneficiaries
+                .iter()
+                .map(|(_, bps)| {
+                    (U256::from(amount) * U256::from(*bps) / U256::from(FEE_DIVISOR)).as_u128()
+                })
+                .collect();
+            shares[0] += amount - shares.iter().sum::<Balance>();
+            for ((beneficiary_id, _), share) in beneficiaries.into_iter().zip(shares) {
+                if share > 0 {
+                    *self.beneficiary_rewards.entry(beneficiary_id.clone()).or_insert(0) += share;
+                    *self.beneficiary_balances.entry(beneficiary_id).or_insert(0) += share;
+                }
+            }
+        }
+        self.last_distribution.unclaimed = 0;
+    }
+
+    /// take all reward not yet withdrawn by given beneficiary.
+    pub fn take_beneficiary_balance(&mut self, beneficiary_id: &AccountId) -> Balance {
+        self.beneficiary_balances.remove(beneficiary_id).unwrap_or(0)
+    }
+
+    /// reward gone to beneficiaries but not yet withdrawn by them.
+    pub fn beneficiary_balance_total(&self) -> Balance {
+        self.beneficiary_balances.values().sum()
+    }
+
+    /// Reward this farm still owes to farmers, beneficiaries or its creator.
+    pub fn reserved_reward(&self) -> Balance {
+        self.amount_of_reward - self.amount_of_claimed + self.beneficiary_balance_total()
+    }
+
     /// Claim user's unclaimed reward in this farm,
+    /// if apply_min_claim and the reward is below min_claim_amount,
+    /// nothing is claimed and user RPS stays, so it accumulates.
     /// return the new user RPS (reward per seed),  
     /// and amount of reward 
     pub fn claim_user_reward(
@@ -296,6 +740,7 @@ impl Farm {
         user_seeds: &Balance, 
         total_seeds: &Balance, 
         silent: bool,
+        apply_min_claim: bool,
     ) -> (RPS, Balance) {
 
         self.distribute(total_seeds, silent);
@@ -303,25 +748,139 @@ impl Farm {
         //     return (self.last_distribution.rps, 0);
         // }
 
-        let claimed = (
-            U256::from(*user_seeds) 
-            * (U256::from_little_endian(&self.last_distribution.rps) - U256::from_little_endian(user_rps))
-            / U256::from(DENOM)
-        ).as_u128();
+        let ended = matches!(self.status, FarmStatus::Ended);
+        let mut claimed = self.user_reward(&self.last_distribution, ended, user_rps, user_seeds, total_seeds);
+
+        if apply_min_claim && claimed > 0 && claimed < self.min_claim_amount {
+            return (*user_rps, 0);
+        }
 
         if claimed > 0 {
-            assert!(
-                self.last_distribution.unclaimed >= claimed, 
-                "{} unclaimed:{}, cur_claim:{}", 
-                ERR500, self.last_distribution.unclaimed, claimed
-            );
-            self.last_distribution.unclaimed -= claimed;
-            self.amount_of_claimed += claimed;
+            claimed = self.take_unclaimed(claimed);
         }
 
         (self.last_distribution.rps, claimed)
     }
 
+    /// Move claimed out of unclaimed reward, a shortfall within CLAIM_DUST_TOLERANCE
+    /// is rounding dust, so the claim is clamped to what is left rather than failing.
+    /// return amount actually claimed.
+    fn take_unclaimed(&mut self, claimed: Balance) -> Balance {
+        let unclaimed = self.last_distribution.unclaimed;
+        assert!(
+            unclaimed + CLAIM_DUST_TOLERANCE >= claimed,
+            "{} unclaimed:{}, cur_claim:{}",
+            ERR500, unclaimed, claimed
+        );
+        let claimed = if unclaimed < claimed {
+            env::log(
+                format!(
+                    "claim of {} from {} clamped to unclaimed {}",
+                    claimed, self.farm_id, unclaimed
+                )
+                .as_bytes(),
+            );
+            unclaimed
+        } else {
+            claimed
+        };
+        self.last_distribution.unclaimed -= claimed;
+        self.amount_of_claimed += claimed;
+        claimed
+    }
+
+    /// Same as claim_user_reward, but claims at most max_amount,
+    /// user RPS only advances by the part claimed, rounded up against the user,
+    /// so the rest stays claimable later.
+    pub fn claim_user_reward_partial(
+        &mut self,
+        user_rps: &RPS,
+        user_seeds: &Balance,
+        total_seeds: &Balance,
+        max_amount: Balance,
+        silent: bool,
+    ) -> (RPS, Balance) {
+        self.distribute(total_seeds, silent);
+        let farm_rps = U256::from_little_endian(&self.last_distribution.rps);
+        let user_rps = U256::from_little_endian(user_rps);
+        let pending = (U256::from(*user_seeds) * (farm_rps - user_rps) / U256::from(DENOM)).as_u128();
+        if pending <= max_amount {
+            let mut cur_user_rps: RPS = [0; 32];
+            user_rps.to_little_endian(&mut cur_user_rps);
+            return self.claim_user_reward(&cur_user_rps, user_seeds, total_seeds, silent, false);
+        }
+
+        // pending > max_amount, so user_seeds > 0
+        let rps_claimed = (U256::from(max_amount) * U256::from(DENOM) + U256::from(*user_seeds) - 1)
+            / U256::from(*user_seeds);
+        let mut new_user_rps: RPS = [0; 32];
+        std::cmp::min(user_rps + rps_claimed, farm_rps).to_little_endian(&mut new_user_rps);
+        let claimed = self.take_unclaimed(max_amount);
+        (new_user_rps, claimed)
+    }
+
+    /// Take reward left undistributed in a farm ended by its end_at,
+    /// return None if the farm has not ended that way.
+    pub fn take_leftover(&mut self, total_seeds: &Balance) -> Option<Balance> {
+        if let FarmStatus::Running = self.status {
+            self.distribute(total_seeds, true);
+        }
+        match self.status {
+            FarmStatus::Ended | FarmStatus::Cleared if self.last_distribution.undistributed > 0 => {
+                let amount = self.last_distribution.undistributed;
+                self.last_distribution.undistributed = 0;
+                self.amount_of_reward -= amount;
+                Some(amount)
+            }
+            _ => None,
+        }
+    }
+
+    /// Put back leftover failed to be sent out by take_leftover.
+    pub fn restore_leftover(&mut self, amount: Balance) {
+        self.last_distribution.undistributed += amount;
+        self.amount_of_reward += amount;
+    }
+
+    /// Release reward at a new reward_per_session from the current round on,
+    /// rounds passed are settled at the old rate first.
+    /// return None if the farm has ended.
+    pub fn change_reward_rate(&mut self, total_seeds: &Balance, reward_per_session: Balance) -> Option<RateChange> {
+        if let FarmStatus::Running = self.status {
+            self.distribute(total_seeds, true);
+        }
+        match self.status {
+            FarmStatus::Created | FarmStatus::Running | FarmStatus::Paused => {
+                let change = RateChange {
+                    old_reward_per_session: self.terms.reward_per_session.into(),
+                    new_reward_per_session: reward_per_session.into(),
+                    effective_round: self.last_distribution.rr,
+                };
+                self.terms.reward_per_session = reward_per_session;
+                if self.rate_changes.len() >= MAX_FARM_RATE_CHANGES {
+                    self.rate_changes.remove(0);
+                }
+                self.rate_changes.push(change.clone());
+                Some(change)
+            }
+            _ => None,
+        }
+    }
+
+    /// Record current state of the farm, dropping the oldest snapshot when full.
+    pub fn take_snapshot(&mut self, total_seeds: Balance) -> FarmSnapshot {
+        let snapshot = FarmSnapshot {
+            timestamp: to_sec(env::block_timestamp()),
+            total_seed: total_seeds.into(),
+            cumulative_claimed: self.amount_of_claimed.into(),
+        };
+        if self.snapshots.len() >= MAX_FARM_SNAPSHOTS {
+            self.snapshots.remove(0);
+        }
+        self.snapshots.push(snapshot.clone());
+        snapshot
+    }
+
     /// Move an Ended farm to Cleared, if any unclaimed reward exists, go to beneficiary
     pub fn move_to_clear(&mut self, total_seeds: &Balance) -> bool {
         if let FarmStatus::Running = self.status {
@@ -329,9 +888,7 @@ impl Farm {
         }
         if let FarmStatus::Ended = self.status {
             if self.last_distribution.unclaimed > 0 {
-                self.amount_of_claimed += self.last_distribution.unclaimed;
-                self.amount_of_beneficiary += self.last_distribution.unclaimed;
-                self.last_distribution.unclaimed = 0;
+                self.sweep_to_beneficiary();
             }
             self.status = FarmStatus::Cleared;
             true
@@ -340,22 +897,26 @@ impl Farm {
         }
     }
 
-    pub fn can_be_removed(&self, total_seeds: &Balance) -> bool {
-        match self.status {
-            FarmStatus::Ended => true,
+    /// A farm can be removed once it has ended for grace_sec,
+    /// grace_sec is on the farm's timeline, i.e. in blocks for by_block_height farms.
+    pub fn can_be_removed(&self, total_seeds: &Balance, grace_sec: u32) -> bool {
+        let last_rr = match self.status {
+            FarmStatus::Ended => self.last_distribution.rr,
             FarmStatus::Running => {
                 if let Some(dis) = self.try_distribute(total_seeds) {
-                    if dis.undistributed == 0 {
-                        true
+                    if dis.undistributed == 0 || self.terms.ended_by_date(dis.rr) {
+                        dis.rr
                     } else {
-                        false
+                        return false;
                     }
                 } else {
-                    false
+                    return false;
                 }
             },
-            _ => false,
-        }
+            _ => return false,
+        };
+        let ended_at = self.terms.start_at + last_rr * self.terms.round_interval();
+        self.now() >= ended_at.saturating_add(grace_sec)
     }
 
     /// Returns seed id this farm accepted.
@@ -371,4 +932,133 @@ impl Farm {
     pub fn get_farm_id(&self) -> FarmId {
         return self.farm_id.clone();
     }
+
+    /// Settle a Running farm and stop its reward accrual,
+    /// return false if the farm is not Running or has ended.
+    pub fn pause(&mut self, total_seeds: &Balance) -> bool {
+        if let FarmStatus::Running = self.status {
+            self.distribute(total_seeds, true);
+            if let FarmStatus::Running = self.status {
+                self.status = FarmStatus::Paused;
+                self.paused_at = self.now();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Resume a Paused farm, the paused period is skipped by shifting start_at,
+    /// return false if the farm is not Paused.
+    pub fn resume(&mut self) -> bool {
+        if let FarmStatus::Paused = self.status {
+            self.terms.start_at += self.now() - self.paused_at;
+            self.status = FarmStatus::Running;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// whether reward of this farm goes to beneficiary right now, for nothing is staked.
+    pub fn is_idle(&self, total_seeds: &Balance) -> bool {
+        matches!(self.status, FarmStatus::Running)
+            && self.now() >= self.terms.start_at
+            && *total_seeds == 0
+    }
+
+    /// Describe each broken accounting invariant of this farm, empty if healthy,
+    /// note amount_of_claimed already includes amount_of_beneficiary.
+    pub fn invariant_violations(&self) -> Vec<String> {
+        let mut violations = vec![];
+        let accounted = self.amount_of_claimed
+            + self.last_distribution.unclaimed
+            + self.last_distribution.undistributed;
+        if self.amount_of_reward != accounted {
+            violations.push(format!(
+                "{}: amount_of_reward {} != claimed {} + unclaimed {} + undistributed {}",
+                self.farm_id,
+                self.amount_of_reward,
+                self.amount_of_claimed,
+                self.last_distribution.unclaimed,
+                self.last_distribution.undistributed,
+            ));
+        }
+        if self.amount_of_beneficiary > self.amount_of_claimed {
+            violations.push(format!(
+                "{}: amount_of_beneficiary {} > claimed {}",
+                self.farm_id, self.amount_of_beneficiary, self.amount_of_claimed,
+            ));
+        }
+        if self.beneficiary_balance_total() > self.amount_of_beneficiary {
+            violations.push(format!(
+                "{}: beneficiary balances {} > amount_of_beneficiary {}",
+                self.farm_id,
+                self.beneficiary_balance_total(),
+                self.amount_of_beneficiary,
+            ));
+        }
+        violations
+    }
+
+    /// Returns seed staked in this farm out of total staked in its seed.
+    pub fn effective_seeds(&self, seed_amount: Balance) -> Balance {
+        seed_amount - self.opted_out_seed
+    }
+
+    /// Split claimed reward into (farmer part, protocol fee part).
+    pub fn split_fee(&self, claimed: Balance) -> (Balance, Balance) {
+        let fee = (U256::from(claimed) * U256::from(self.fee_bps)
+            / U256::from(FEE_DIVISOR)).as_u128();
+        (claimed - fee, fee)
+    }
+}
+
+/// Versioned Farm, farms stored before it are converted by migrate,
+/// later versions upgrade on read.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VersionedFarm {
+    V102(Farm),
+}
+
+impl VersionedFarm {
+    /// Returns the farm, upgraded to the currently used version.
+    pub fn get(self) -> Farm {
+        match self {
+            VersionedFarm::V102(farm) => farm,
+        }
+    }
+}
+
+/// Farms stored as VersionedFarm, read and written as the current Farm.
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FarmMap(UnorderedMap<FarmId, VersionedFarm>);
+
+impl FarmMap {
+    pub fn new<S: IntoStorageKey>(prefix: S) -> Self {
+        Self(UnorderedMap::new(prefix))
+    }
+
+    pub fn get(&self, farm_id: &FarmId) -> Option<Farm> {
+        self.0.get(farm_id).map(VersionedFarm::get)
+    }
+
+    /// Saves farm as the current version, without cloning it into a VersionedFarm.
+    pub fn insert(&mut self, farm_id: &FarmId, farm: &Farm) {
+        // tag of VersionedFarm::V102, followed by the farm
+        let mut value = vec![0u8];
+        farm.serialize(&mut value).unwrap();
+        self.0.insert_raw(&farm_id.try_to_vec().unwrap(), &value);
+    }
+
+    pub fn remove(&mut self, farm_id: &FarmId) -> Option<Farm> {
+        self.0.remove(farm_id).map(VersionedFarm::get)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.0.len()
+    }
+
+    pub fn keys_as_vector(&self) -> &Vector<FarmId> {
+        self.0.keys_as_vector()
+    }
 }
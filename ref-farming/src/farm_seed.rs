@@ -3,15 +3,16 @@
 
 use std::collections::HashSet;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{Balance};
+use near_sdk::{AccountId, Balance};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::json_types::{U128};
 use crate::errors::*;
 use crate::{FarmId};
-use crate::utils::parse_seed_id;
+use crate::utils::{parse_seed_id, TimestampSec};
 use std::collections::HashMap;
 use near_sdk::collections::LookupMap;
 use crate::{Contract, StorageKeys};
+use crate::legacy::FarmSeedV101;
 
 
 /// and token's inner_id in that contract. 
@@ -22,6 +23,15 @@ pub(crate) type NFTTokenId = String; //paras-comic-dev.testnet@6
 
 pub(crate) type NftBalance = HashMap<NFTTokenId, U128>; //paras-comic-dev.testnet@6
 
+/// A named group of nft prefixes sharing the same balance equivalent.
+#[derive(Serialize, Deserialize, BorshSerialize, BorshDeserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct NftTier {
+    /// token ids, paras series or nft contracts in this tier
+    pub prefixes: Vec<NFTTokenId>,
+    pub weight: U128,
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Clone, PartialEq, Debug)]
 pub enum SeedType {
     FT,
@@ -35,24 +45,6 @@ pub struct FarmSeedMetadata {
     pub media: Option<String>,
 }
 
-// #[derive(BorshSerialize, BorshDeserialize)]
-// #[cfg_attr(feature = "test", derive(Clone))]
-// pub struct FarmSeedV1 {
-//     /// The Farming Token this FarmSeed represented for
-//     pub seed_id: SeedId,
-//     /// The seed is a FT or NFT
-//     pub seed_type: SeedType,
-//     /// all farms that accepted this seed
-//     /// FarmId = {seed_id}#{next_index}
-//     pub farms: HashSet<FarmId>,
-//     pub next_index: u32,
-//     /// total (staked) balance of this seed (Farming Token)
-//     pub amount: Balance,
-//     pub min_deposit: Balance,
-//     pub nft_balance: Option<HashMap<NFTTokenId, U128>>,
-//     pub metadata: Option<FarmSeedMetadata>
-// }
-
 #[derive(BorshSerialize, BorshDeserialize)]
 #[cfg_attr(feature = "test", derive(Clone))]
 pub struct FarmSeed {
@@ -67,7 +59,25 @@ pub struct FarmSeed {
     /// total (staked) balance of this seed (Farming Token)
     pub amount: Balance,
     pub min_deposit: Balance,
-    pub metadata: Option<FarmSeedMetadata>
+    pub metadata: Option<FarmSeedMetadata>,
+    /// for NFT seed, seconds for a staked nft to reach its full weight
+    pub nft_ramp_sec: TimestampSec,
+    /// cumulative seed deposited by far, never decreases
+    pub total_seed_deposited: Balance,
+    /// for NFT seed, weights in nft balance are multiplied by 10^weight_scale
+    pub weight_scale: u8,
+    /// no new deposit accepted if paused, claims and withdrawals go on
+    pub paused: bool,
+    /// for FT seed, least stake a farmer may hold after deposit, 0 means no limit
+    pub min_farmer_stake: Balance,
+    /// for FT seed, most stake a farmer may hold, excess deposit is refunded, 0 means no limit
+    pub max_farmer_stake: Balance,
+    /// number of farmers staking in this seed
+    pub farmer_count: u64,
+    /// for FT seed, other tokens staked as this seed, with the part of amount staked in each
+    pub aliases: HashMap<AccountId, Balance>,
+    /// for NFT seed, number of nfts staked in it
+    pub nft_count: u64,
 }
 
 impl FarmSeed {
@@ -92,7 +102,40 @@ impl FarmSeed {
             next_index: 0,
             amount: 0,
             min_deposit,
-            metadata
+            metadata,
+            nft_ramp_sec: 0,
+            total_seed_deposited: 0,
+            weight_scale: 0,
+            paused: false,
+            min_farmer_stake: 0,
+            max_farmer_stake: 0,
+            farmer_count: 0,
+            aliases: HashMap::new(),
+            nft_count: 0,
+        }
+    }
+
+    /// count a farmer in or out, as their stake in this seed starts or ends.
+    pub fn update_farmer_count(&mut self, was_staking: bool, is_staking: bool) {
+        if !was_staking && is_staking {
+            self.farmer_count += 1;
+        } else if was_staking && !is_staking {
+            self.farmer_count = self.farmer_count.saturating_sub(1);
+        }
+    }
+
+    /// part of amount a farmer holding current stake can deposit,
+    /// None if the resulting stake is still below min_farmer_stake.
+    pub fn bound_farmer_stake(&self, current: Balance, amount: Balance) -> Option<Balance> {
+        let accepted = if self.max_farmer_stake > 0 {
+            std::cmp::min(amount, self.max_farmer_stake.saturating_sub(current))
+        } else {
+            amount
+        };
+        if current + accepted >= self.min_farmer_stake {
+            Some(accepted)
+        } else {
+            None
         }
     }
 
@@ -106,73 +149,65 @@ impl FarmSeed {
         self.amount -= amount;
         self.amount
     }
+}
+
+/// Versioned FarmSeed, used for lazy upgrade.
+/// Which means this structure would upgrade automatically when used.
+/// To achieve that, each time the new version comes in,
+/// each function of this enum should be carefully re-code!
+#[derive(BorshSerialize, BorshDeserialize)]
+pub enum VersionedFarmSeed {
+    V101(FarmSeedV101),
+    V102(FarmSeed),
+}
+
+impl VersionedFarmSeed {
+
+    pub fn new(
+        seed_id: &SeedId,
+        min_deposit: Balance,
+        is_nft_balance: bool,
+        metadata: Option<FarmSeedMetadata>,
+    ) -> Self {
+        VersionedFarmSeed::V102(FarmSeed::new(seed_id, min_deposit, is_nft_balance, metadata))
+    }
+
+    /// Upgrades from other versions to the currently used version.
+    pub fn upgrade(self) -> Self {
+        match self {
+            VersionedFarmSeed::V101(farm_seed) => VersionedFarmSeed::V102(farm_seed.into()),
+            VersionedFarmSeed::V102(farm_seed) => VersionedFarmSeed::V102(farm_seed),
+        }
+    }
+
+    #[inline]
+    #[allow(unreachable_patterns)]
+    pub fn need_upgrade(&self) -> bool {
+        match self {
+            VersionedFarmSeed::V102(_) => false,
+            _ => true,
+        }
+    }
 
     #[inline]
     #[allow(unreachable_patterns)]
     pub fn get_ref(&self) -> &FarmSeed {
-        return self;
+        match self {
+            VersionedFarmSeed::V102(farm_seed) => farm_seed,
+            _ => unimplemented!(),
+        }
     }
 
     #[inline]
     #[allow(unreachable_patterns)]
     pub fn get_ref_mut(&mut self) -> &mut FarmSeed {
-        return self;
+        match self {
+            VersionedFarmSeed::V102(farm_seed) => farm_seed,
+            _ => unimplemented!(),
+        }
     }
 }
 
-/// Versioned FarmSeed, used for lazy upgrade.
-/// Which means this structure would upgrade automatically when used.
-/// To achieve that, each time the new version comes in, 
-/// each function of this enum should be carefully re-code!
-// #[derive(BorshSerialize, BorshDeserialize)]
-// pub enum VersionedFarmSeed {
-//     V101(FarmSeedV1),
-//     V102(FarmSeed),
-// }
-
-// impl VersionedFarmSeed {
-
-//     pub fn new(
-//         seed_id: &SeedId,
-//         min_deposit: Balance,
-//         is_nft_balance: bool,
-//         metadata: Option<FarmSeedMetadata>,
-//     ) -> Self {
-//         VersionedFarmSeed::V102(FarmSeed::new(seed_id, min_deposit, is_nft_balance, metadata))
-//     }
-
-//     /// Upgrades from other versions to the currently used version.
-//     pub fn upgrade(self, contract: &mut Contract) -> Self {
-//         match self {
-//             VersionedFarmSeed::V102(farm_seed) => VersionedFarmSeed::V102(farm_seed),
-//             VersionedFarmSeed::V101(farm_seed) => {
-//                 if let Some(nft_balance) = farm_seed.nft_balance {
-//                     contract.data_mut().nft_balance_seeds.insert(&farm_seed.seed_id, &nft_balance);
-//                 }
-//                 return VersionedFarmSeed::V102(FarmSeed {
-//                     seed_id: farm_seed.seed_id,
-//                     seed_type: farm_seed.seed_type,
-//                     farms: farm_seed.farms,
-//                     next_index: farm_seed.next_index,
-//                     amount: farm_seed.amount,
-//                     min_deposit: farm_seed.min_deposit,
-//                     metadata: farm_seed.metadata,
-//                 })
-//             }
-//         }
-//     }
-
-//     #[inline]
-//     #[allow(unreachable_patterns)]
-//     pub fn need_upgrade(&self) -> bool {
-//         match self {
-//             VersionedFarmSeed::V102(_) => false,
-//             _ => true,
-//         }
-//     }
-// }
-
-
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 #[serde(crate = "near_sdk::serde")]
 pub struct SeedInfo {
@@ -184,7 +219,13 @@ pub struct SeedInfo {
     pub min_deposit: U128,
     pub nft_balance: Option<NftBalance>,
     pub title: Option<String>,
-    pub media: Option<String>
+    pub media: Option<String>,
+    pub nft_ramp_sec: u32,
+    pub total_seed_deposited: U128,
+    pub weight_scale: u8,
+    pub paused: bool,
+    pub min_farmer_stake: U128,
+    pub max_farmer_stake: U128,
 }
 
 impl From<&FarmSeed> for SeedInfo {
@@ -204,6 +245,12 @@ impl From<&FarmSeed> for SeedInfo {
                 title: Some(seed_metadata.title.unwrap_or("".to_string())),
                 media: Some(seed_metadata.media.unwrap_or("".to_string())),
                 nft_balance: None,
+                nft_ramp_sec: fs.nft_ramp_sec,
+                total_seed_deposited: fs.total_seed_deposited.into(),
+                weight_scale: fs.weight_scale,
+                paused: fs.paused,
+                min_farmer_stake: fs.min_farmer_stake.into(),
+                max_farmer_stake: fs.max_farmer_stake.into(),
             }
         } else {
             Self {
@@ -215,7 +262,13 @@ impl From<&FarmSeed> for SeedInfo {
                 farms: fs.farms.iter().map(|key| key.clone()).collect(),
                 title: Some("".to_string()),
                 media: Some("".to_string()),
-                nft_balance: None
+                nft_balance: None,
+                nft_ramp_sec: fs.nft_ramp_sec,
+                total_seed_deposited: fs.total_seed_deposited.into(),
+                weight_scale: fs.weight_scale,
+                paused: fs.paused,
+                min_farmer_stake: fs.min_farmer_stake.into(),
+                max_farmer_stake: fs.max_farmer_stake.into(),
             }
         }
     }
@@ -5,26 +5,44 @@
 //! and the deposited near amount prepaid as storage fee
 
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use near_sdk::collections::LookupMap;
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
 use near_sdk::{env, AccountId, Balance};
 use crate::{SeedId, FarmId, RPS, Contract};
 use crate::farm::{ContractNFTTokenId, NFTTokenId};
 use crate::errors::*;
-use crate::utils::{MAX_ACCOUNT_LENGTH, PARAS_SERIES_DELIMETER};
+use crate::utils::{gen_farm_id, parse_farm_id, ramped_nft_weight, TimestampSec, MAX_ACCOUNT_LENGTH, PARAS_SERIES_DELIMETER};
 use crate::StorageKeys;
 use crate::utils::NFT_DELIMETER;
 
 use near_sdk::collections::UnorderedSet;
 use near_sdk::json_types::U128;
 use crate::farm_seed::FarmSeed;
+use crate::legacy::FarmerV101;
 
 /// each entry cost MAX_ACCOUNT_LENGTH bytes,
 /// amount: Balance cost 16 bytes
 /// each empty hashmap cost 4 bytes
 pub const MIN_FARMER_LENGTH: u128 = MAX_ACCOUNT_LENGTH + 16 + 4 * 3;
 
+/// entry of the farmer in the contract's farmer_ids set,
+/// a vector slot (prefix + u64 index, account) and an index map record (prefix + account, u64 index)
+pub const FARMER_ID_ENTRY_LENGTH: u128 = (1 + 8 + 4 + MAX_ACCOUNT_LENGTH) + (1 + 4 + MAX_ACCOUNT_LENGTH + 8);
+
+/// Record of a staked NFT,
+/// its weight ramps up from deposited_at to the full weight.
+#[derive(BorshSerialize, BorshDeserialize, Clone)]
+pub struct NftStake {
+    pub deposited_at: TimestampSec,
+    /// full balance equivalent of this NFT when deposited
+    pub weight: Balance,
+    /// part of weight currently counted in farmer's seed
+    pub applied: Balance,
+    /// tier of the seed this NFT fell into when deposited
+    pub tier: Option<String>,
+}
+
 /// Account deposits information and storage cost.
 #[derive(BorshSerialize, BorshDeserialize)]
 #[cfg_attr(feature = "test", derive(Clone))]
@@ -39,8 +57,32 @@ pub struct Farmer {
     pub seeds: HashMap<SeedId, Balance>,
     /// record user_last_rps of farms
     pub user_rps: LookupMap<FarmId, RPS>,
+    /// farm round at which user_rps of farms was last set
+    pub user_claim_rounds: LookupMap<FarmId, u32>,
     pub rps_count: u32,
+    /// number of entries in user_claim_rounds, charged apart from rps entries
+    pub claim_round_count: u32,
+    /// farms of each seed having user_rps recorded since this index was added,
+    /// so stale rps can be found without walking every farm index of the seed
+    pub rps_farms: HashMap<SeedId, HashSet<u32>>,
     pub nft_seeds: HashMap<SeedId, UnorderedSet<ContractNFTTokenId>>,
+    /// weight record of each staked NFT
+    pub nft_stakes: HashMap<ContractNFTTokenId, NftStake>,
+    /// account allowed to claim and withdraw on behalf of this farmer
+    pub claim_delegate: Option<AccountId>,
+    /// farms this farmer does not farm on, though staked in their seeds
+    pub opted_out_farms: HashSet<FarmId>,
+    /// contract notified with the claimed amounts after each claim
+    pub on_claim_receiver: Option<AccountId>,
+    /// part of seeds staked through alias tokens of the seed, by alias token,
+    /// withdrawals of it go back in that token
+    pub alias_seeds: HashMap<AccountId, Balance>,
+    /// account getting referral_bps of each claim of this farmer
+    pub referrer: Option<AccountId>,
+    /// referral reward accrued by far from farmers this farmer referred, by token
+    pub referral_rewards: HashMap<AccountId, Balance>,
+    /// whether a storage shortfall on claims is covered from the NEAR reward just claimed
+    pub storage_top_up: bool,
 }
 
 impl Farmer {
@@ -97,34 +139,98 @@ impl Farmer {
         self.user_rps.get(farm_id).unwrap_or(RPS::default()).clone()
     }
 
-    pub fn set_rps(&mut self, farm_id: &FarmId, rps: RPS) {
-        if !self.user_rps.contains_key(farm_id) {
-            self.rps_count += 1;
-        } 
+    /// round is the farm round the rps is settled up to.
+    pub fn set_rps(&mut self, farm_id: &FarmId, rps: RPS, round: u32) {
+        match self.user_rps.get(farm_id) {
+            // no need to write the same rps again
+            Some(cur) if cur == rps => return,
+            Some(_) => {}
+            None => {
+                self.rps_count += 1;
+                let (seed_id, index) = parse_farm_id(farm_id);
+                self.rps_farms.entry(seed_id).or_default().insert(index as u32);
+            }
+        }
         self.user_rps.insert(farm_id, &rps);
+        match self.user_claim_rounds.get(farm_id) {
+            Some(cur) if cur == round => {}
+            Some(_) => {
+                self.user_claim_rounds.insert(farm_id, &round);
+            }
+            None => {
+                self.claim_round_count += 1;
+                self.user_claim_rounds.insert(farm_id, &round);
+            }
+        }
+    }
+
+    pub fn get_claim_round(&self, farm_id: &FarmId) -> u32 {
+        self.user_claim_rounds.get(farm_id).unwrap_or(0)
     }
 
     pub fn remove_rps(&mut self, farm_id: &FarmId) {
         if self.user_rps.contains_key(farm_id) {
             self.user_rps.remove(farm_id);
+            if self.user_claim_rounds.remove(farm_id).is_some() {
+                self.claim_round_count -= 1;
+            }
             self.rps_count -= 1;
+            let (seed_id, index) = parse_farm_id(farm_id);
+            if let Some(indexes) = self.rps_farms.get_mut(&seed_id) {
+                indexes.remove(&(index as u32));
+                if indexes.is_empty() {
+                    self.rps_farms.remove(&seed_id);
+                }
+            }
         }
     }
 
+    /// farm ids of given seed known to have rps of this farmer.
+    pub fn get_rps_farms(&self, seed_id: &SeedId) -> Vec<FarmId> {
+        self.rps_farms.get(seed_id).map_or(vec![], |indexes| {
+            indexes.iter().map(|index| gen_farm_id(seed_id, *index as usize)).collect()
+        })
+    }
+
     /// Returns amount of yocto near necessary to cover storage used by this data structure.
     pub fn storage_usage(&self) -> Balance {
         (
             MIN_FARMER_LENGTH 
+            + FARMER_ID_ENTRY_LENGTH
             + self.rewards.len() as u128 * (4 + MAX_ACCOUNT_LENGTH + 16)
             + self.seeds.len() as u128 * (4 + MAX_ACCOUNT_LENGTH + 16)
             + self.rps_count as u128 * (4 + 1 + 2 * MAX_ACCOUNT_LENGTH + 32)
+            + self.claim_round_count as u128 * (4 + 1 + 2 * MAX_ACCOUNT_LENGTH + 4)
+            + self.rps_farms.values().map(|indexes| 4 + MAX_ACCOUNT_LENGTH + 4 + indexes.len() as u128 * 4).sum::<u128>()
+            + self.nft_stakes.len() as u128 * (4 + 2 * MAX_ACCOUNT_LENGTH + 4 + 16 + 16 + 1)
+            + self.nft_stakes.values().filter_map(|stake| stake.tier.as_ref()).map(|tier| 4 + tier.len() as u128).sum::<u128>()
+            + self.claim_delegate.as_ref().map_or(0, |_| 4 + MAX_ACCOUNT_LENGTH)
+            + self.opted_out_farms.len() as u128 * (4 + MAX_ACCOUNT_LENGTH + 1 + 10)
+            + self.on_claim_receiver.as_ref().map_or(0, |_| 4 + MAX_ACCOUNT_LENGTH)
+            + self.alias_seeds.len() as u128 * (4 + MAX_ACCOUNT_LENGTH + 16)
+            + self.referrer.as_ref().map_or(0, |_| 4 + MAX_ACCOUNT_LENGTH)
+            + self.referral_rewards.len() as u128 * (4 + MAX_ACCOUNT_LENGTH + 16)
         )
         * env::storage_byte_cost()
     }
 
-    pub fn add_nft(&mut self, seed_id: &SeedId, contract_nft_token_id: ContractNFTTokenId) {
+    /// storage_usage after given number of reward tokens and rps entries are added.
+    pub fn projected_storage_usage(&self, new_rewards: usize, new_rps: usize) -> Balance {
+        self.storage_usage()
+            + (
+                new_rewards as u128 * (4 + MAX_ACCOUNT_LENGTH + 16)
+                + new_rps as u128 * (4 + 1 + 2 * MAX_ACCOUNT_LENGTH + 32 + 4 + 1 + 2 * MAX_ACCOUNT_LENGTH + 4)
+                + new_rps as u128 * 4
+            )
+            * env::storage_byte_cost()
+    }
+
+    /// return false if the nft is already staked, and nothing changes.
+    pub fn add_nft(&mut self, seed_id: &SeedId, contract_nft_token_id: ContractNFTTokenId, stake: NftStake) -> bool {
         if let Some(nft_contract_seed) = self.nft_seeds.get_mut(seed_id) {
-            nft_contract_seed.insert(&contract_nft_token_id);
+            if !nft_contract_seed.insert(&contract_nft_token_id) {
+                return false;
+            }
         } else {
             let mut new_nft_contract_seeds = UnorderedSet::new(StorageKeys::AccountSeedId {
                 account_seed_id: format!("{}:{}", self.farmer_id, seed_id)
@@ -132,19 +238,83 @@ impl Farmer {
             new_nft_contract_seeds.insert(&contract_nft_token_id);
             self.nft_seeds.insert(seed_id.clone(), new_nft_contract_seeds);
         }
+        self.nft_stakes.insert(contract_nft_token_id, stake);
+        true
     }
 
-    pub fn sub_nft(&mut self, seed_id: &SeedId, contract_nft_token_id: ContractNFTTokenId ) -> Option<ContractNFTTokenId> {
+    /// return the stake record of removed nft
+    pub fn sub_nft(&mut self, seed_id: &SeedId, contract_nft_token_id: ContractNFTTokenId ) -> Option<NftStake> {
         let mut nft_token_id_exist: bool = false;
         if let Some(nft_contract_seed) = self.nft_seeds.get_mut(seed_id) {
             nft_token_id_exist = nft_contract_seed.remove(&contract_nft_token_id);
         }
         if nft_token_id_exist {
-            Some(contract_nft_token_id)
+            self.nft_stakes.remove(&contract_nft_token_id)
         } else {
             None
         }
     }
+
+    /// whether the farmer holds seed or nfts in given seed,
+    /// a just staked nft may apply no seed yet while ramping up.
+    pub fn is_staking(&self, seed_id: &SeedId) -> bool {
+        self.seeds.contains_key(seed_id)
+            || self.nft_seeds.get(seed_id).map_or(false, |nfts| !nfts.is_empty())
+    }
+
+    /// Move stake and rps of farms in old seed to the same farms under new seed id,
+    /// rps already under the new farm id was set with no stake, so the old one wins.
+    /// return false if the farmer has nothing in old seed.
+    pub fn remap_seed(&mut self, old_seed_id: &SeedId, new_seed_id: &SeedId, next_index: u32) -> bool {
+        let mut moved = false;
+        if let Some(amount) = self.seeds.remove(old_seed_id) {
+            self.add_seed(new_seed_id, amount);
+            moved = true;
+        }
+        if let Some(nft_contract_seed) = self.nft_seeds.remove(old_seed_id) {
+            self.nft_seeds.insert(new_seed_id.clone(), nft_contract_seed);
+            moved = true;
+        }
+        for index in 0..next_index as usize {
+            let old_farm_id = gen_farm_id(old_seed_id, index);
+            let new_farm_id = gen_farm_id(new_seed_id, index);
+            if let Some(rps) = self.user_rps.get(&old_farm_id) {
+                let round = self.get_claim_round(&old_farm_id);
+                self.remove_rps(&old_farm_id);
+                self.set_rps(&new_farm_id, rps, round);
+                moved = true;
+            }
+            if self.opted_out_farms.remove(&old_farm_id) {
+                self.opted_out_farms.insert(new_farm_id);
+            }
+        }
+        moved
+    }
+
+    /// Re-evaluate applied weight of staked nfts in given seed and 
+    /// update seed balance accordingly.
+    /// return (previous applied weight, current applied weight)
+    pub fn refresh_nft_weight(&mut self, seed_id: &SeedId, ramp_sec: TimestampSec) -> (Balance, Balance) {
+        let token_ids = match self.nft_seeds.get(seed_id) {
+            Some(nft_contract_seed) => nft_contract_seed.to_vec(),
+            None => return (0, 0),
+        };
+        let mut prev: Balance = 0;
+        let mut cur: Balance = 0;
+        for token_id in token_ids.iter() {
+            if let Some(stake) = self.nft_stakes.get_mut(token_id) {
+                prev += stake.applied;
+                stake.applied = ramped_nft_weight(stake.weight, stake.deposited_at, ramp_sec);
+                cur += stake.applied;
+            }
+        }
+        if cur > prev {
+            self.add_seed(seed_id, cur - prev);
+        } else if prev > cur {
+            self.sub_seed(seed_id, prev - cur);
+        }
+        (prev, cur)
+    }
 }
 
 
@@ -154,13 +324,14 @@ impl Farmer {
 /// each function of this enum should be carefully re-code!
 #[derive(BorshSerialize, BorshDeserialize)]
 pub enum VersionedFarmer {
-    V101(Farmer),
+    V101(FarmerV101),
+    V102(Farmer),
 }
 
 impl VersionedFarmer {
 
     pub fn new(farmer_id: AccountId, amount: Balance) -> Self {
-        VersionedFarmer::V101(Farmer {
+        VersionedFarmer::V102(Farmer {
             farmer_id: farmer_id.clone(),
             amount: amount,
             rewards: HashMap::new(),
@@ -168,15 +339,29 @@ impl VersionedFarmer {
             user_rps: LookupMap::new(StorageKeys::UserRps {
                 account_id: farmer_id.clone(),
             }),
+            user_claim_rounds: LookupMap::new(StorageKeys::UserClaimRound {
+                account_id: farmer_id.clone(),
+            }),
             rps_count: 0,
+            claim_round_count: 0,
+            rps_farms: HashMap::new(),
             nft_seeds: HashMap::new(),
+            nft_stakes: HashMap::new(),
+            claim_delegate: None,
+            opted_out_farms: HashSet::new(),
+            on_claim_receiver: None,
+            alias_seeds: HashMap::new(),
+            referrer: None,
+            referral_rewards: HashMap::new(),
+            storage_top_up: false,
         })
     }
 
     /// Upgrades from other versions to the currently used version.
-    pub fn upgrade(self) -> Self {
+    pub fn upgrade(self, contract: &Contract) -> Self {
         match self {
-            VersionedFarmer::V101(farmer) => VersionedFarmer::V101(farmer),
+            VersionedFarmer::V101(farmer) => VersionedFarmer::V102(farmer.upgrade(contract)),
+            VersionedFarmer::V102(farmer) => VersionedFarmer::V102(farmer),
         }
     }
 
@@ -184,7 +369,7 @@ impl VersionedFarmer {
     #[allow(unreachable_patterns)]
     pub fn need_upgrade(&self) -> bool {
         match self {
-            VersionedFarmer::V101(_) => false,
+            VersionedFarmer::V102(_) => false,
             _ => true,
         }
     }
@@ -193,7 +378,7 @@ impl VersionedFarmer {
     #[allow(unreachable_patterns)]
     pub fn get_ref(&self) -> &Farmer {
         match self {
-            VersionedFarmer::V101(farmer) => farmer,
+            VersionedFarmer::V102(farmer) => farmer,
             _ => unimplemented!(),
         }
     }
@@ -202,7 +387,7 @@ impl VersionedFarmer {
     #[allow(unreachable_patterns)]
     pub fn get(self) -> Farmer {
         match self {
-            VersionedFarmer::V101(farmer) => farmer,
+            VersionedFarmer::V102(farmer) => farmer,
             _ => unimplemented!(),
         }
     }
@@ -211,7 +396,7 @@ impl VersionedFarmer {
     #[allow(unreachable_patterns)]
     pub fn get_ref_mut(&mut self) -> &mut Farmer {
         match self {
-            VersionedFarmer::V101(farmer) => farmer,
+            VersionedFarmer::V102(farmer) => farmer,
             _ => unimplemented!(),
         }
     }
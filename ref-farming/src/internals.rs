@@ -1,7 +1,13 @@
 use near_sdk::{env, Balance};
 
-use crate::utils::{get_nft_balance_equivalent};
-use crate::farm_seed::SeedType;
+use crate::utils::{
+    ext_claim_receiver, get_nft_balance_equivalent, FT_INDEX_TAG, nft_balance_keys, ramped_nft_weight, scale_nft_weight, to_sec,
+    GAS_FOR_CLAIM_NOTIFY, MAX_FARMS_PER_SEED, MAX_REWARD_TOKENS_PER_FARM, NEAR_DECIMALS,
+};
+use crate::farm::{FarmStatus, FarmTerms, FEE_DIVISOR};
+use crate::farmer::NftStake;
+use crate::events::{emit_claim_no_stake, emit_seed_deposit, ClaimNoStakeData, SeedDepositData};
+use crate::farm_seed::{SeedType, VersionedFarmSeed};
 use crate::*;
 use uint::construct_uint;
 
@@ -15,10 +21,19 @@ fn claim_user_reward_from_farm(
     farmer: &mut Farmer, 
     total_seeds: &Balance,
     silent: bool,
-) {
-    let user_seeds = farmer.seeds.get(&farm.get_seed_id()).unwrap_or(&0_u128);
+    apply_min_claim: bool,
+    max_amount: Option<Balance>,
+) -> (Balance, Balance) {
+    let user_seeds = if farmer.opted_out_farms.contains(&farm.get_farm_id()) {
+        &0_u128
+    } else {
+        farmer.seeds.get(&farm.get_seed_id()).unwrap_or(&0_u128)
+    };
     let user_rps = farmer.get_rps(&farm.get_farm_id());
-    let (new_user_rps, reward_amount) = farm.claim_user_reward(&user_rps, user_seeds, total_seeds, silent);
+    let (new_user_rps, reward_amount) = match max_amount {
+        Some(max_amount) => farm.claim_user_reward_partial(&user_rps, user_seeds, total_seeds, max_amount, silent),
+        None => farm.claim_user_reward(&user_rps, user_seeds, total_seeds, silent, apply_min_claim),
+    };
     if !silent {
         env::log(
             format!(
@@ -29,7 +44,8 @@ fn claim_user_reward_from_farm(
         );
     }
         
-    farmer.set_rps(&farm.get_farm_id(), new_user_rps);
+    farmer.set_rps(&farm.get_farm_id(), new_user_rps, farm.last_distribution.rr);
+    let (reward_amount, fee_amount) = farm.split_fee(reward_amount);
     if reward_amount > 0 {
         farmer.add_reward(&farm.get_reward_token(), reward_amount);
         if !silent {
@@ -42,6 +58,7 @@ fn claim_user_reward_from_farm(
             );
         }
     }
+    (reward_amount, fee_amount)
 }
 
 impl Contract {
@@ -65,8 +82,29 @@ impl Contract {
         metadata: Option<FarmSeedMetadata>
     ) -> FarmId {
         
+        if terms.accrual_granularity > 0 {
+            let farm_terms: FarmTerms = terms.into();
+            assert!(
+                terms.session_interval % terms.accrual_granularity == 0
+                    && farm_terms.reward_per_round() > 0,
+                "{}",
+                ERR48_INVALID_ACCRUAL_GRANULARITY
+            );
+        }
+
+        assert!(terms.decay_factor_bps as u32 <= FEE_DIVISOR, "{}", ERR50_INVALID_DECAY);
+        self.assert_reward_tokens(&FarmTerms::from(terms).reward_tokens());
+        if terms.end_at > 0 {
+            let farm_terms: FarmTerms = terms.into();
+            assert!(
+                terms.end_at > std::cmp::max(terms.start_at, farm_terms.now()),
+                "{}",
+                ERR55_INVALID_END_AT
+            );
+        }
+
         // let mut farm_seed = self.get_seed_default(&terms.seed_id, min_deposit);
-        let mut farm_seed: FarmSeed;
+        let mut farm_seed: VersionedFarmSeed;
         if let Some(fs) = self.get_seed_wrapped(&terms.seed_id) {
             farm_seed = fs;
             env::log(
@@ -78,10 +116,10 @@ impl Contract {
             );
         } else {
             if let Some(nft_balance) = nft_balance {
-                farm_seed = FarmSeed::new(&terms.seed_id, min_deposit, true, metadata);
+                farm_seed = VersionedFarmSeed::new(&terms.seed_id, min_deposit, true, metadata);
                 self.data_mut().nft_balance_seeds.insert(&terms.seed_id, &nft_balance);
             } else {
-                farm_seed = FarmSeed::new(&terms.seed_id, min_deposit, false, metadata);
+                farm_seed = VersionedFarmSeed::new(&terms.seed_id, min_deposit, false, metadata);
             }
             env::log(
                 format!(
@@ -92,34 +130,48 @@ impl Contract {
             );
         }
 
+        // deposits and withdraws settle all farms of the seed
+        assert!(
+            farm_seed.get_ref().farms.len() < MAX_FARMS_PER_SEED,
+            "{}",
+            ERR69_TOO_MANY_FARMS
+        );
+
         let farm_id: FarmId = gen_farm_id(&terms.seed_id, farm_seed.get_ref().next_index as usize);
 
-        let farm = Farm::new(
+        let mut farm = Farm::new(
             farm_id.clone(),
+            env::predecessor_account_id(),
             terms.into()
         );
+        farm.min_rounds = self.data().default_min_rounds;
         
         farm_seed.get_ref_mut().farms.insert(farm_id.clone());
         farm_seed.get_ref_mut().next_index += 1;
         self.data_mut().seeds.insert(&terms.seed_id, &farm_seed);
-        self.data_mut().farms.insert(&farm_id.clone(), &farm);
+        self.internal_save_farm(&farm_id, &farm);
         farm_id
     }
 
-    pub(crate) fn internal_remove_farm_by_farm_id(&mut self, farm_id: &FarmId) -> bool {
+    pub(crate) fn internal_remove_farm_by_farm_id(&mut self, farm_id: &FarmId, grace_sec: u32) -> bool {
         let (seed_id, _) = parse_farm_id(farm_id);
         let mut removable = false;
         if let Some(mut farm_seed) = self.get_seed_wrapped(&seed_id) {
             let seed_amount = farm_seed.get_ref().amount;
             if let Some(farm) = self.data().farms.get(farm_id) {
-                if farm.can_be_removed(&seed_amount) {
+                if farm.can_be_removed(&farm.effective_seeds(seed_amount), grace_sec) {
                     removable = true;
                 }
             }
             if removable {
                 let mut farm = self.data_mut().farms.remove(farm_id).expect(ERR41_FARM_NOT_EXIST);
-                farm.move_to_clear(&seed_amount);
-                self.data_mut().outdated_farms.insert(farm_id, &farm);
+                self.internal_ledger_sub_farm_unclaimed(
+                    &farm.get_reward_token(),
+                    farm.last_distribution.unclaimed,
+                );
+                self.internal_ledger_update_farm_reserved(&farm.get_reward_token(), farm.reserved_reward(), 0);
+                farm.move_to_clear(&farm.effective_seeds(seed_amount));
+                self.internal_save_outdated_farm(farm_id, &farm);
                 farm_seed.get_ref_mut().farms.remove(farm_id);
                 self.data_mut().seeds.insert(&seed_id, &farm_seed);
                 return true;
@@ -128,32 +180,115 @@ impl Contract {
         false
     }
 
+    /// claim reward from all farms of given seed and notify farmer's on_claim_receiver if any,
+    /// seed deposits and withdraws claim through here too. return claimed rewards.
+    /// All farms must be settled before the farmer's seed changes, so this is not paged,
+    /// the walk is bounded by MAX_FARMS_PER_SEED instead.
     pub(crate) fn internal_claim_user_reward_by_seed_id(
         &mut self, 
         sender_id: &AccountId,
-        seed_id: &SeedId) {
+        seed_id: &SeedId) -> HashMap<AccountId, Balance> {
+        let (claimed_rewards, next_index) =
+            self.internal_claim_user_reward_by_seed_range(sender_id, seed_id, 0, MAX_FARMS_PER_SEED as u32);
+        assert!(next_index.is_none(), "{}", ERR69_TOO_MANY_FARMS);
+        self.internal_finish_seed_claim(sender_id, seed_id);
+        self.internal_notify_claim(sender_id, &claimed_rewards);
+        claimed_rewards
+    }
+
+    /// Claim reward of the farmer from farms of given seed, by farm index from from_index on,
+    /// at most max_farms of them, return claimed rewards and the index to continue from,
+    /// None if farms of the seed are all claimed.
+    pub(crate) fn internal_claim_user_reward_by_seed_range(
+        &mut self,
+        sender_id: &AccountId,
+        seed_id: &SeedId,
+        from_index: u32,
+        max_farms: u32,
+    ) -> (HashMap<AccountId, Balance>, Option<u32>) {
+        let mut claimed_rewards: HashMap<AccountId, Balance> = HashMap::new();
+        let mut farmer = self.get_farmer(sender_id);
+        let farm_seed = match self.get_seed_wrapped(seed_id) {
+            Some(farm_seed) => farm_seed,
+            None => return (claimed_rewards, None),
+        };
+        let amount = farm_seed.get_ref().amount;
+        // by index, as the order of the farms set is not stable between calls,
+        // only farms still in the seed, so removed ones cost nothing.
+        let mut indexes: Vec<u32> = farm_seed
+            .get_ref()
+            .farms
+            .iter()
+            .map(|farm_id| parse_farm_id(farm_id).1 as u32)
+            .filter(|index| *index >= from_index)
+            .collect();
+        indexes.sort_unstable();
+        let next_index = indexes.get(max_farms as usize).copied();
+        for index in indexes.into_iter().take(max_farms as usize) {
+            let farm_id = &gen_farm_id(seed_id, index as usize);
+            let mut farm = self.data().farms.get(farm_id).unwrap();
+            let total_seeds = farm.effective_seeds(amount);
+            let (claimed, fee) = claim_user_reward_from_farm(
+                &mut farm, 
+                farmer.get_ref_mut(),  
+                &total_seeds,
+                true,
+                // seed of the farmer may change next, must settle all
+                false,
+                None,
+            );
+            self.internal_ledger_add_farmer_rewards(&farm.get_reward_token(), claimed);
+            self.internal_add_protocol_fee(&farm.get_reward_token(), fee);
+            let claimed = claimed - self.internal_pay_referral(farmer.get_ref_mut(), &farm.get_reward_token(), claimed);
+            if claimed > 0 {
+                *claimed_rewards.entry(farm.get_reward_token()).or_insert(0) += claimed;
+            }
+            self.internal_save_farm(farm_id, &farm);
+        }
+        self.data_mut().farmers.insert(sender_id, &farmer);
+        (claimed_rewards, next_index)
+    }
+
+    /// Apply ramped nft weight and free rps of removed farms,
+    /// only once all farms of the seed are claimed at the same time.
+    pub(crate) fn internal_finish_seed_claim(&mut self, sender_id: &AccountId, seed_id: &SeedId) {
         let mut farmer = self.get_farmer(sender_id);
         if let Some(mut farm_seed) = self.get_seed_wrapped(seed_id) {
-            let amount = farm_seed.get_ref().amount;
-            for farm_id in &mut farm_seed.get_ref_mut().farms.iter() {
-                let mut farm = self.data().farms.get(farm_id).unwrap();
-                claim_user_reward_from_farm(
-                    &mut farm, 
-                    farmer.get_ref_mut(),  
-                    &amount,
-                    true,
-                );
-                self.data_mut().farms.insert(farm_id, &farm);
+            if farm_seed.get_ref().seed_type == SeedType::NFT {
+                // all farms of this seed are settled now,
+                // so it is safe to apply the ramped nft weight from here on.
+                let ramp_sec = farm_seed.get_ref().nft_ramp_sec;
+                let (prev, cur) = farmer.get_ref_mut().refresh_nft_weight(seed_id, ramp_sec);
+                if cur > prev {
+                    farm_seed.get_ref_mut().add_amount(cur - prev);
+                } else if prev > cur {
+                    farm_seed.get_ref_mut().sub_amount(prev - cur);
+                }
+                self.internal_sync_opted_out_seed(farmer.get_ref(), seed_id, cur, prev);
             }
+            // rps of farms removed from this seed are of no use, free their storage,
+            // only farms the farmer has a record of are looked at.
+            for farm_id in farmer.get_ref().get_rps_farms(seed_id) {
+                if !farm_seed.get_ref().farms.contains(&farm_id) {
+                    farmer.get_ref_mut().remove_rps(&farm_id);
+                }
+            }
+            let prefix = format!("{}#", seed_id);
+            farmer.get_ref_mut().opted_out_farms.retain(|farm_id| {
+                !farm_id.starts_with(&prefix) || farm_seed.get_ref().farms.contains(farm_id)
+            });
             self.data_mut().seeds.insert(seed_id, &farm_seed);
             self.data_mut().farmers.insert(sender_id, &farmer);
         }
     }
 
+    /// return amount of reward claimed from this farm,
+    /// at most max_amount of pending reward is claimed if given.
     pub(crate) fn internal_claim_user_reward_by_farm_id(
         &mut self, 
         sender_id: &AccountId, 
-        farm_id: &FarmId) {
+        farm_id: &FarmId,
+        max_amount: Option<Balance>) -> Balance {
         let mut farmer = self.get_farmer(sender_id);
 
         let (seed_id, _) = parse_farm_id(farm_id);
@@ -161,18 +296,369 @@ impl Contract {
         if let Some(farm_seed) = self.get_seed_wrapped(&seed_id) {
             let amount = farm_seed.get_ref().amount;
             if let Some(mut farm) = self.data().farms.get(farm_id) {
-                claim_user_reward_from_farm(
+                let total_seeds = farm.effective_seeds(amount);
+                let prev_rr = farm.last_distribution.rr;
+                let prev_status: String = (&farm.status).into();
+                let prev_rps = farmer.get_ref().get_rps(farm_id);
+                let (claimed, fee) = claim_user_reward_from_farm(
                     &mut farm, 
                     farmer.get_ref_mut(), 
-                    &amount,
+                    &total_seeds,
                     false,
+                    true,
+                    max_amount,
                 );
-                self.data_mut().farms.insert(farm_id, &farm);
-                self.data_mut().farmers.insert(sender_id, &farmer);
+                self.internal_ledger_add_farmer_rewards(&farm.get_reward_token(), claimed);
+                self.internal_add_protocol_fee(&farm.get_reward_token(), fee);
+                let claimed = claimed - self.internal_pay_referral(farmer.get_ref_mut(), &farm.get_reward_token(), claimed);
+                // skip writes of a no-op claim
+                let status: String = (&farm.status).into();
+                if claimed > 0 || fee > 0 || farm.last_distribution.rr != prev_rr || status != prev_status {
+                    self.internal_save_farm(farm_id, &farm);
+                }
+                if claimed > 0 || farmer.get_ref().get_rps(farm_id) != prev_rps {
+                    self.data_mut().farmers.insert(sender_id, &farmer);
+                }
+                return claimed;
+            }
+        }
+        0
+    }
+
+    /// unclaimed reward of given farmer in given farm, before protocol fee,
+    /// 0 for unknown farmer or farm.
+    pub(crate) fn internal_unclaimed_reward(&self, farmer_id: &AccountId, farm_id: &FarmId) -> Balance {
+        let (seed_id, _) = parse_farm_id(farm_id);
+
+        if let (Some(farmer), Some(farm_seed), Some(farm)) = (
+            self.get_farmer_wrapped(farmer_id),
+            self.get_seed_wrapped(&seed_id),
+            self.data().farms.get(farm_id),
+        ) {
+            if farmer.get_ref().opted_out_farms.contains(farm_id) {
+                return 0;
+            }
+            farm.view_farmer_unclaimed_reward(
+                &farmer.get_ref().get_rps(farm_id),
+                farmer.get_ref().seeds.get(&seed_id).unwrap_or(&0_u128),
+                &farm.effective_seeds(farm_seed.get_ref().amount),
+            )
+        } else {
+            0
+        }
+    }
+
+    /// claim reward from this farm and notify farmer's on_claim_receiver if any,
+    /// return amount of reward claimed.
+    pub(crate) fn internal_claim_and_notify_by_farm(
+        &mut self,
+        sender_id: &AccountId,
+        farm_id: &FarmId,
+        max_amount: Option<Balance>) -> Balance {
+        let claimed = self.internal_claim_user_reward_by_farm_id(sender_id, farm_id, max_amount);
+        if claimed > 0 {
+            let reward_token = self.data().farms.get(farm_id).unwrap().get_reward_token();
+            let mut claimed_rewards = HashMap::new();
+            claimed_rewards.insert(reward_token, claimed);
+            self.internal_notify_claim(sender_id, &claimed_rewards);
+        } else if let Some(farm) = self.data().farms.get(farm_id) {
+            // tell why nothing came, rather than a silent success
+            if !self.get_farmer(sender_id).get_ref().is_staking(&farm.get_seed_id()) {
+                env::log(format!("{} has no stake in farm {}", sender_id, farm_id).as_bytes());
+                emit_claim_no_stake(ClaimNoStakeData {
+                    account_id: sender_id.clone(),
+                    farm_id: farm_id.clone(),
+                });
+            }
+        }
+        claimed
+    }
+
+
+    /// Best-effort notification of claimed rewards to farmer's on_claim_receiver,
+    /// no callback is attached so a failing receiver never reverts the claim.
+    pub(crate) fn internal_notify_claim(
+        &self,
+        sender_id: &AccountId,
+        claimed_rewards: &HashMap<AccountId, Balance>,
+    ) {
+        if claimed_rewards.is_empty() {
+            return;
+        }
+        let farmer = self.get_farmer(sender_id);
+        if let Some(receiver_id) = farmer.get_ref().on_claim_receiver.as_ref() {
+            ext_claim_receiver::on_reward_claimed(
+                sender_id.clone(),
+                claimed_rewards.iter().map(|(k, v)| (k.clone(), U128(*v))).collect(),
+                receiver_id,
+                0,
+                GAS_FOR_CLAIM_NOTIFY,
+            );
+        }
+    }
+
+    /// Referral share of reward the farmer claims, 0 if the referrer is gone
+    /// or has no storage left for it.
+    pub(crate) fn internal_referral_share(&self, farmer: &Farmer, token_id: &AccountId, claimed: Balance) -> Balance {
+        let referral_bps = self.data().referral_bps;
+        let referrer_id = match &farmer.referrer {
+            Some(referrer_id) if referral_bps > 0 && claimed > 0 => referrer_id,
+            _ => return 0,
+        };
+        let referrer = match self.get_farmer_wrapped(referrer_id) {
+            Some(referrer) => referrer,
+            None => return 0,
+        };
+        let new_entries = !referrer.get_ref().rewards.contains_key(token_id) as usize
+            + !referrer.get_ref().referral_rewards.contains_key(token_id) as usize;
+        // both maps take entries of the same size
+        if referrer.get_ref().projected_storage_usage(new_entries, 0) > referrer.get_ref().amount {
+            return 0;
+        }
+        (U256::from(claimed) * U256::from(referral_bps) / U256::from(FEE_DIVISOR)).as_u128()
+    }
+
+    /// Move referral_bps of reward the farmer just claimed into their referrer's reward balance,
+    /// skipped if the referrer is gone or has no storage left for it.
+    /// return amount moved.
+    pub(crate) fn internal_pay_referral(
+        &mut self,
+        farmer: &mut Farmer,
+        token_id: &AccountId,
+        claimed: Balance,
+    ) -> Balance {
+        let amount = self.internal_referral_share(farmer, token_id, claimed);
+        if amount == 0 {
+            return 0;
+        }
+        let referrer_id = farmer.referrer.clone().unwrap();
+        let mut referrer = self.get_farmer(&referrer_id);
+        farmer.sub_reward(token_id, amount);
+        referrer.get_ref_mut().add_reward(token_id, amount);
+        *referrer.get_ref_mut().referral_rewards.entry(token_id.clone()).or_insert(0) += amount;
+        self.data_mut().farmers.insert(&referrer_id, &referrer);
+        amount
+    }
+
+    pub(crate) fn internal_add_protocol_fee(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let prev = self.data().protocol_fees.get(token_id).unwrap_or(0);
+            self.data_mut().protocol_fees.insert(token_id, &(prev + amount));
+        }
+    }
+
+    /// Keep opted_out_seed of farms the farmer opted out of in line with
+    /// the change of farmer's seed from removed to added,
+    /// those farms should have been settled before.
+    pub(crate) fn internal_sync_opted_out_seed(
+        &mut self,
+        farmer: &Farmer,
+        seed_id: &SeedId,
+        added: Balance,
+        removed: Balance,
+    ) {
+        for farm_id in farmer.opted_out_farms.iter() {
+            let (farm_seed_id, _) = parse_farm_id(farm_id);
+            if &farm_seed_id == seed_id {
+                if let Some(mut farm) = self.data().farms.get(farm_id) {
+                    farm.opted_out_seed = farm.opted_out_seed + added - removed;
+                    self.internal_save_farm(farm_id, &farm);
+                }
+            }
+        }
+    }
+
+    /// Panics if a farm would pay more reward tokens than a claim can go through.
+    pub(crate) fn assert_reward_tokens(&self, reward_tokens: &[AccountId]) {
+        assert!(reward_tokens.len() <= MAX_REWARD_TOKENS_PER_FARM, "{}", ERR49_TOO_MANY_REWARD_TOKENS);
+    }
+
+    /// Panics if caller is neither the farmer nor its claim delegate.
+    pub(crate) fn assert_claim_delegate(&self, account_id: &AccountId) {
+        let caller = env::predecessor_account_id();
+        if &caller != account_id {
+            let farmer = self.get_farmer(account_id);
+            assert_eq!(
+                farmer.get_ref().claim_delegate.as_ref(),
+                Some(&caller),
+                "{}",
+                ERR16_NOT_CLAIM_DELEGATE
+            );
+        }
+    }
+
+    /// Move given seed, its farms and per seed records to new seed id,
+    /// the seed stays paused till the owner resumes it once all farmers are moved.
+    pub(crate) fn internal_remap_seed(&mut self, old_seed_id: &SeedId, new_seed_id: &SeedId) {
+        assert!(
+            old_seed_id != new_seed_id && self.get_seed_wrapped(new_seed_id).is_none(),
+            "{}",
+            ERR33_INVALID_SEED_ID
+        );
+        let mut farm_seed = self.data_mut().seeds.remove(old_seed_id).expect(ERR31_SEED_NOT_EXIST).upgrade();
+        // alias tokens are held apart from the seed token, nothing to move them along with
+        assert!(farm_seed.get_ref().aliases.is_empty(), "{}", ERR59_INVALID_SEED_ALIAS);
+        let old_farm_ids: Vec<FarmId> = farm_seed.get_ref().farms.iter().cloned().collect();
+        farm_seed.get_ref_mut().farms.clear();
+        for old_farm_id in old_farm_ids {
+            let (_, index) = parse_farm_id(&old_farm_id);
+            let new_farm_id = gen_farm_id(new_seed_id, index);
+            let mut farm = self.data_mut().farms.remove(&old_farm_id).unwrap();
+            farm.farm_id = new_farm_id.clone();
+            farm.terms.seed_id = new_seed_id.clone();
+            // same farm moved, so no change to unclaimed in the ledger
+            self.data_mut().farms.insert(&new_farm_id, &farm);
+            farm_seed.get_ref_mut().farms.insert(new_farm_id);
+        }
+        farm_seed.get_ref_mut().seed_id = new_seed_id.clone();
+        farm_seed.get_ref_mut().paused = true;
+
+        if farm_seed.get_ref().seed_type == SeedType::FT {
+            let mut ledger = self.data().token_ledger.get(old_seed_id).unwrap_or_default();
+            let amount = std::cmp::min(ledger.held, farm_seed.get_ref().amount);
+            ledger.held -= amount;
+            self.data_mut().token_ledger.insert(old_seed_id, &ledger);
+            self.internal_ledger_add_held(new_seed_id, amount);
+        }
+        if let Some(nft_balance) = self.data_mut().nft_balance_seeds.remove(old_seed_id) {
+            self.data_mut().nft_balance_seeds.insert(new_seed_id, &nft_balance);
+        }
+        if let Some(tiers) = self.data_mut().nft_tiers.remove(old_seed_id) {
+            self.data_mut().nft_tiers.insert(new_seed_id, &tiers);
+        }
+        if let Some(tier_counts) = self.data_mut().nft_tier_counts.remove(old_seed_id) {
+            self.data_mut().nft_tier_counts.insert(new_seed_id, &tier_counts);
+        }
+        self.data_mut().seeds.insert(new_seed_id, &farm_seed);
+        self.data_mut().seed_remaps.insert(old_seed_id, new_seed_id);
+    }
+
+    pub(crate) fn internal_ledger_add_held(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            ledger.held += amount;
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    pub(crate) fn internal_ledger_sub_held(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            assert!(ledger.held >= amount, "{}", ERR51_TOKEN_INSOLVENT);
+            ledger.held -= amount;
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    pub(crate) fn internal_ledger_add_pending_out(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            ledger.pending_out += amount;
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    /// Called by transfer callbacks, whether the transfer succeeded or not.
+    pub(crate) fn internal_ledger_sub_pending_out(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            ledger.pending_out = ledger.pending_out.saturating_sub(amount);
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    pub(crate) fn internal_ledger_add_farmer_rewards(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            ledger.farmer_rewards += amount;
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    pub(crate) fn internal_ledger_sub_farmer_rewards(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            ledger.farmer_rewards = ledger.farmer_rewards.saturating_sub(amount);
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    /// Save given farm, keeping the aggregated unclaimed reward of its token in line.
+    /// Reward token of a farm never changes once created, as farmers' rewards and the ledger
+    /// are keyed by it, moving a farm to another token would need to convert them too.
+    pub(crate) fn internal_save_farm(&mut self, farm_id: &FarmId, farm: &Farm) {
+        let prev_farm = self.data().farms.get(farm_id);
+        if let Some(prev_farm) = &prev_farm {
+            assert_eq!(
+                prev_farm.get_reward_token(),
+                farm.get_reward_token(),
+                "{}",
+                ERR58_REWARD_TOKEN_CHANGED
+            );
+        }
+        let prev_reserved = prev_farm.as_ref().map_or(0, |f| f.reserved_reward());
+        self.internal_ledger_update_farm_reserved(&farm.get_reward_token(), prev_reserved, farm.reserved_reward());
+        let prev = prev_farm.map_or(0, |f| f.last_distribution.unclaimed);
+        let cur = farm.last_distribution.unclaimed;
+        if cur > prev {
+            let mut ledger = self.data().token_ledger.get(&farm.get_reward_token()).unwrap_or_default();
+            ledger.farm_unclaimed += cur - prev;
+            self.data_mut().token_ledger.insert(&farm.get_reward_token(), &ledger);
+        } else if prev > cur {
+            self.internal_ledger_sub_farm_unclaimed(&farm.get_reward_token(), prev - cur);
+        }
+        self.data_mut().farms.insert(farm_id, farm);
+    }
+
+    /// Save given outdated farm, keeping the aggregated reserved reward of its token in line.
+    pub(crate) fn internal_save_outdated_farm(&mut self, farm_id: &FarmId, farm: &Farm) {
+        let prev_reserved = self.data().outdated_farms.get(farm_id).map_or(0, |f| f.reserved_reward());
+        self.internal_ledger_update_farm_reserved(&farm.get_reward_token(), prev_reserved, farm.reserved_reward());
+        self.data_mut().outdated_farms.insert(farm_id, farm);
+    }
+
+    pub(crate) fn internal_ledger_update_farm_reserved(&mut self, token_id: &AccountId, prev: Balance, cur: Balance) {
+        if prev != cur {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            ledger.farm_reserved = (ledger.farm_reserved + cur).saturating_sub(prev);
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    pub(crate) fn internal_ledger_sub_farm_unclaimed(&mut self, token_id: &AccountId, amount: Balance) {
+        if amount > 0 {
+            let mut ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+            ledger.farm_unclaimed = ledger.farm_unclaimed.saturating_sub(amount);
+            self.data_mut().token_ledger.insert(token_id, &ledger);
+        }
+    }
+
+    /// Returns (held, owed) of given token,
+    /// owed = staked seed + reward left in farms + farmers' reward + protocol fee.
+    pub(crate) fn internal_token_solvency(&self, token_id: &AccountId) -> (Balance, Balance) {
+        let ledger = self.data().token_ledger.get(token_id).unwrap_or_default();
+        let mut owed = ledger.farmer_rewards + ledger.farm_reserved;
+        owed += self.data().protocol_fees.get(token_id).unwrap_or(0);
+        if let Some(farm_seed) = self.get_seed_wrapped(token_id) {
+            if farm_seed.get_ref().seed_type == SeedType::FT {
+                // part staked through alias tokens is held in those tokens
+                owed += farm_seed
+                    .get_ref()
+                    .amount
+                    .checked_sub(farm_seed.get_ref().aliases.values().sum())
+                    .expect(ERR62_ALIAS_AMOUNT_EXCEEDS_SEED);
             }
         }
+        (ledger.held, owed)
     }
 
+    /// Only tokens that are both seed and reward can leak between the two accountings.
+    pub(crate) fn assert_token_solvency(&self, token_id: &AccountId) {
+        if self.get_seed_wrapped(token_id).is_some() {
+            let (held, owed) = self.internal_token_solvency(token_id);
+            assert!(held >= owed, "{}", ERR51_TOKEN_INSOLVENT);
+        }
+    }
 
     #[inline]
     pub(crate) fn get_farmer(&self, from: &AccountId) -> VersionedFarmer {
@@ -180,7 +666,7 @@ impl Contract {
             .get(from)
             .expect(ERR10_ACC_NOT_REGISTERED);
         if orig.need_upgrade() {
-                orig.upgrade()
+                orig.upgrade(self)
             } else {
                 orig
             }
@@ -190,7 +676,7 @@ impl Contract {
     pub(crate) fn get_farmer_default(&self, from: &AccountId) -> VersionedFarmer {
         let orig = self.data().farmers.get(from).unwrap_or(VersionedFarmer::new(from.clone(), 0));
         if orig.need_upgrade() {
-            orig.upgrade()
+            orig.upgrade(self)
         } else {
             orig
         }
@@ -200,7 +686,7 @@ impl Contract {
     pub(crate) fn get_farmer_wrapped(&self, from: &AccountId) -> Option<VersionedFarmer> {
         if let Some(farmer) = self.data().farmers.get(from) {
             if farmer.need_upgrade() {
-                Some(farmer.upgrade())
+                Some(farmer.upgrade(self))
             } else {
                 Some(farmer)
             }
@@ -222,19 +708,35 @@ impl Contract {
     }
 
     #[inline]
-    pub(crate) fn get_seed_and_upgrade(&mut self, seed_id: &String) -> FarmSeed {
-        return self.data().seeds.get(seed_id).expect(&format!("{}", ERR31_SEED_NOT_EXIST));
+    pub(crate) fn get_seed_and_upgrade(&mut self, seed_id: &String) -> VersionedFarmSeed {
+        let farm_seed = self.data().seeds.get(seed_id).expect(&format!("{}", ERR31_SEED_NOT_EXIST));
+        if farm_seed.need_upgrade() {
+            let farm_seed = farm_seed.upgrade();
+            self.data_mut().seeds.insert(seed_id, &farm_seed);
+            farm_seed
+        } else {
+            farm_seed
+        }
     }
 
     #[inline]
-    pub(crate) fn get_seed(&self, seed_id: &String) -> FarmSeed {
-        return self.data().seeds.get(seed_id).expect(&format!("{}", ERR31_SEED_NOT_EXIST)); 
+    pub(crate) fn get_seed(&self, seed_id: &String) -> VersionedFarmSeed {
+        let orig = self.data().seeds.get(seed_id).expect(&format!("{}", ERR31_SEED_NOT_EXIST));
+        if orig.need_upgrade() {
+            orig.upgrade()
+        } else {
+            orig
+        }
     }
 
     #[inline]
-    pub(crate) fn get_seed_wrapped(&self, seed_id: &String) -> Option<FarmSeed> {
+    pub(crate) fn get_seed_wrapped(&self, seed_id: &String) -> Option<VersionedFarmSeed> {
         if let Some(farm_seed) = self.data().seeds.get(seed_id) {
-            Some(farm_seed)
+            if farm_seed.need_upgrade() {
+                Some(farm_seed.upgrade())
+            } else {
+                Some(farm_seed)
+            }
         } else {
             None
         }
@@ -251,16 +753,16 @@ impl Contract {
         // to update user reward_per_seed in each farm
         self.internal_claim_user_reward_by_seed_id(sender_id, seed_id);
 
-        let mut farm_seed = self.get_seed(seed_id);
-
-        let mut farmer = self.get_farmer(sender_id);
-
-        // **** update seed (new version)
-        farm_seed.get_ref_mut().add_amount(amount);
-        self.data_mut().seeds.insert(&seed_id, &farm_seed);
-
-        farmer.get_ref_mut().add_seed(&seed_id, amount);
-        self.data_mut().farmers.insert(sender_id, &farmer);
+        let farm_seed = self.internal_add_farmer_seed(seed_id, sender_id, amount);
+        let farmer = self.get_farmer(sender_id);
+        emit_seed_deposit(SeedDepositData {
+            account_id: sender_id.clone(),
+            seed_id: seed_id.clone(),
+            amount: amount.into(),
+            farmer_seed: farmer.get_ref().seeds.get(seed_id).copied().unwrap_or(0).into(),
+            seed_total: farm_seed.get_ref().amount.into(),
+            nft_token_id: None,
+        });
 
         let mut reward_tokens: Vec<AccountId> = vec![];
         for farm_id in farm_seed.get_ref().farms.iter() {
@@ -274,11 +776,39 @@ impl Contract {
         };
     }
 
+    /// add amount to given seed and farmer's stake in it,
+    /// reward of the farmer in this seed must have been claimed.
+    pub(crate) fn internal_add_farmer_seed(
+        &mut self,
+        seed_id: &SeedId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) -> VersionedFarmSeed {
+        let mut farm_seed = self.get_seed(seed_id);
+
+        let mut farmer = self.get_farmer(sender_id);
+        let was_staking = farmer.get_ref().is_staking(seed_id);
+        farmer.get_ref_mut().add_seed(seed_id, amount);
+
+        // **** update seed (new version)
+        farm_seed.get_ref_mut().add_amount(amount);
+        farm_seed.get_ref_mut().total_seed_deposited += amount;
+        farm_seed.get_ref_mut().update_farmer_count(was_staking, farmer.get_ref().is_staking(seed_id));
+        self.data_mut().seeds.insert(seed_id, &farm_seed);
+
+        self.data_mut().farmers.insert(sender_id, &farmer);
+        self.internal_sync_opted_out_seed(farmer.get_ref(), seed_id, amount, 0);
+        farm_seed
+    }
+
+    /// claimed rewards are sent out too, unless keep_rewards,
+    /// then they stay in the farmer's internal balance.
     pub(crate) fn internal_seed_withdraw(
         &mut self, 
         seed_id: &SeedId, 
         sender_id: &AccountId, 
-        amount: Balance) -> SeedType {
+        amount: Balance,
+        keep_rewards: bool) -> SeedType {
 
         // first claim all reward of the user for this seed farms
         // to update user reward_per_seed in each farm
@@ -288,8 +818,11 @@ impl Contract {
         let mut farmer = self.get_farmer(sender_id);
 
         // Then update user seed and total seed of this LPT
+        let was_staking = farmer.get_ref().is_staking(seed_id);
         let farmer_seed_remain = farmer.get_ref_mut().sub_seed(seed_id, amount);
         let _seed_remain = farm_seed.get_ref_mut().sub_amount(amount);
+        farm_seed.get_ref_mut().update_farmer_count(was_staking, farmer.get_ref().is_staking(seed_id));
+        self.internal_sync_opted_out_seed(farmer.get_ref(), seed_id, 0, amount);
 
         if farmer_seed_remain == 0 {
             // remove farmer rps of relative farm
@@ -300,6 +833,9 @@ impl Contract {
         self.data_mut().farmers.insert(sender_id, &farmer);
         self.data_mut().seeds.insert(seed_id, &farm_seed);
 
+        if keep_rewards {
+            return farm_seed.get_ref().seed_type.clone();
+        }
         let mut reward_tokens: Vec<AccountId> = vec![];
         for farm_id in farm_seed.get_ref().farms.iter() {
             let reward_token = self.data().farms.get(farm_id).unwrap().get_reward_token();
@@ -314,6 +850,142 @@ impl Contract {
         farm_seed.get_ref().seed_type.clone()
     }
 
+    /// Put back seed of a failed withdrawal, held in given token.
+    pub(crate) fn internal_revert_ft_seed_withdraw(
+        &mut self,
+        seed_id: &SeedId,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) {
+        // revert withdraw, equal to deposit, claim reward to update user reward_per_seed
+        self.internal_claim_user_reward_by_seed_id(sender_id, seed_id);
+        let mut farmer = self.get_farmer(sender_id);
+        let was_staking = farmer.get_ref().is_staking(seed_id);
+
+        // **** update seed (new version)
+        let mut farm_seed = self.get_seed(seed_id);
+        farm_seed.get_ref_mut().add_amount(amount);
+        farm_seed.get_ref_mut().update_farmer_count(was_staking, true);
+        self.data_mut().seeds.insert(seed_id, &farm_seed);
+
+        farmer.get_ref_mut().add_seed(seed_id, amount);
+        self.data_mut().farmers.insert(sender_id, &farmer);
+        self.internal_sync_opted_out_seed(farmer.get_ref(), seed_id, amount, 0);
+
+        self.internal_ledger_add_held(token_id, amount);
+    }
+
+    /// Record seed staked by the farmer through given alias token of the seed.
+    pub(crate) fn internal_alias_seed_deposit(
+        &mut self,
+        seed_id: &SeedId,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut farm_seed = self.get_seed(seed_id);
+        *farm_seed.get_ref_mut().aliases.entry(token_id.clone()).or_insert(0) += amount;
+        self.data_mut().seeds.insert(seed_id, &farm_seed);
+        let mut farmer = self.get_farmer(sender_id);
+        *farmer.get_ref_mut().alias_seeds.entry(token_id.clone()).or_insert(0) += amount;
+        self.data_mut().farmers.insert(sender_id, &farmer);
+    }
+
+    /// Split seed the farmer withdraws into the tokens it was staked in,
+    /// seed token first, then alias tokens, taking alias parts off the records.
+    /// return (token, amount) of each part.
+    pub(crate) fn internal_split_seed_withdraw(
+        &mut self,
+        seed_id: &SeedId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) -> Vec<(AccountId, Balance)> {
+        let seed_contract_id: AccountId = seed_id.split(FT_INDEX_TAG).next().unwrap().to_string();
+        let mut farm_seed = self.get_seed(seed_id);
+        if farm_seed.get_ref().aliases.is_empty() {
+            return vec![(seed_contract_id, amount)];
+        }
+        let mut farmer = self.get_farmer(sender_id);
+        let mut alias_tokens: Vec<AccountId> = farm_seed.get_ref().aliases.keys().cloned().collect();
+        alias_tokens.sort();
+        let staked = *farmer.get_ref().seeds.get(seed_id).unwrap_or(&0);
+        let in_aliases: Balance = alias_tokens
+            .iter()
+            .map(|token_id| *farmer.get_ref().alias_seeds.get(token_id).unwrap_or(&0))
+            .sum();
+
+        let mut parts = vec![];
+        let mut remain = amount;
+        let own = std::cmp::min(remain, staked.saturating_sub(in_aliases));
+        if own > 0 {
+            parts.push((seed_contract_id, own));
+            remain -= own;
+        }
+        for token_id in alias_tokens {
+            if remain == 0 {
+                break;
+            }
+            let staked_in_alias = farmer.get_ref().alias_seeds.get(&token_id).copied().unwrap_or(0);
+            let part = std::cmp::min(remain, staked_in_alias);
+            if part == 0 {
+                continue;
+            }
+            if part == staked_in_alias {
+                farmer.get_ref_mut().alias_seeds.remove(&token_id);
+            } else {
+                farmer.get_ref_mut().alias_seeds.insert(token_id.clone(), staked_in_alias - part);
+            }
+            *farm_seed.get_ref_mut().aliases.get_mut(&token_id).unwrap() -= part;
+            parts.push((token_id, part));
+            remain -= part;
+        }
+        assert_eq!(remain, 0, "{}", ERR32_NOT_ENOUGH_SEED);
+        self.data_mut().seeds.insert(seed_id, &farm_seed);
+        self.data_mut().farmers.insert(sender_id, &farmer);
+        parts
+    }
+
+    /// Add reward of given token into given farm,
+    /// panics if the token mismatches or the farm can not accept reward.
+    pub(crate) fn internal_deposit_reward(
+        &mut self,
+        farm_id: &FarmId,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut farm = self.data().farms.get(farm_id).expect(ERR41_FARM_NOT_EXIST);
+
+        // update farm
+        assert_eq!(&farm.get_reward_token(), token_id, "{}", ERR44_INVALID_FARM_REWARD);
+        assert!(farm.sustains_min_rounds(amount), "{}", ERR54_BELOW_MIN_ROUNDS);
+        assert!(farm.within_decayed_total(amount), "{}", ERR63_ABOVE_DECAYED_TOTAL);
+        let first_deposit = matches!(farm.status, FarmStatus::Created);
+        if let Some(cur_remain) = farm.add_reward(sender_id, &amount) {
+            // decimals of other tokens are fetched by fetch_reward_token_decimals
+            if first_deposit && farm.reward_token_decimals.is_none() && token_id == NEAR_REWARD_TOKEN {
+                farm.reward_token_decimals = Some(NEAR_DECIMALS);
+            }
+            self.internal_save_farm(farm_id, &farm);
+            let old_balance = self.data().reward_info.get(token_id).unwrap_or(0);
+            self.data_mut()
+                .reward_info
+                .insert(token_id, &(old_balance + amount));
+            self.internal_ledger_add_held(token_id, amount);
+
+            env::log(
+                format!(
+                    "{} added {} Reward Token, Now has {} left",
+                    sender_id, amount, cur_remain
+                )
+                .as_bytes(),
+            );
+        } else {
+            env::panic(format!("{}", ERR43_INVALID_FARM_STATUS).as_bytes())
+        }
+    }
+
     pub(crate) fn internal_nft_deposit(
         &mut self,
         seed_id: &String,
@@ -321,26 +993,62 @@ impl Contract {
         nft_contract_id: &String,
         nft_token_id: &String,
     ) -> bool {
-        let mut farm_seed = self.get_seed(seed_id);
+        let farm_seed = self.get_seed(seed_id);
 
         assert_eq!(farm_seed.get_ref().seed_type, SeedType::NFT, "Cannot deposit NFT to this farm");
 
         // update farmer seed
         let contract_nft_token_id = format!("{}{}{}", nft_contract_id, NFT_DELIMETER, nft_token_id);
         let nft_balance = self.data().nft_balance_seeds.get(&seed_id).unwrap();
-        return if let Some(nft_balance_equivalent) = get_nft_balance_equivalent(nft_balance, contract_nft_token_id.clone()) {
+        let weight_scale = farm_seed.get_ref().weight_scale;
+        return if let Some(nft_balance_equivalent) = get_nft_balance_equivalent(nft_balance, contract_nft_token_id.clone())
+            .map(|weight| scale_nft_weight(weight, weight_scale).expect(ERR64_NFT_WEIGHT_OVERFLOW)) {
             // first claim all reward of the user for this seed farms
             // to update user reward_per_seed in each farm
             self.internal_claim_user_reward_by_seed_id(sender_id, seed_id);
+            let mut farm_seed = self.get_seed(seed_id);
             let mut farmer = self.get_farmer(sender_id);
-            farmer.get_ref_mut().add_nft(seed_id, contract_nft_token_id);
+            let deposited_at = to_sec(env::block_timestamp());
+            let applied = ramped_nft_weight(
+                nft_balance_equivalent,
+                deposited_at,
+                farm_seed.get_ref().nft_ramp_sec,
+            );
+            let tier = self.internal_get_nft_tier(seed_id, &contract_nft_token_id);
+            let was_staking = farmer.get_ref().is_staking(seed_id);
+            if !farmer.get_ref_mut().add_nft(seed_id, contract_nft_token_id.clone(), NftStake {
+                deposited_at,
+                weight: nft_balance_equivalent,
+                applied,
+                tier: tier.clone(),
+            }) {
+                // a replayed nft_on_transfer, the nft has been staked already
+                env::log(
+                    format!("{} already staked {}, skipped.", sender_id, contract_nft_token_id)
+                        .as_bytes(),
+                );
+                return true;
+            }
 
-            farmer.get_ref_mut().add_seed(seed_id, nft_balance_equivalent);
+            farmer.get_ref_mut().add_seed(seed_id, applied);
             self.data_mut().farmers.insert(sender_id, &farmer);
+            self.internal_sync_opted_out_seed(farmer.get_ref(), seed_id, applied, 0);
+            self.internal_update_nft_tier_count(seed_id, &tier, true);
 
             // **** update seed (new version)
-            farm_seed.get_ref_mut().add_amount(nft_balance_equivalent);
+            farm_seed.get_ref_mut().add_amount(applied);
+            farm_seed.get_ref_mut().total_seed_deposited += nft_balance_equivalent;
+            farm_seed.get_ref_mut().update_farmer_count(was_staking, true);
+            farm_seed.get_ref_mut().nft_count += 1;
             self.data_mut().seeds.insert(&seed_id, &farm_seed);
+            emit_seed_deposit(SeedDepositData {
+                account_id: sender_id.clone(),
+                seed_id: seed_id.clone(),
+                amount: nft_balance_equivalent.into(),
+                farmer_seed: farmer.get_ref().seeds.get(seed_id).copied().unwrap_or(0).into(),
+                seed_total: farm_seed.get_ref().amount.into(),
+                nft_token_id: Some(contract_nft_token_id),
+            });
 
             let mut reward_tokens: Vec<AccountId> = vec![];
             for farm_id in farm_seed.get_ref().farms.iter() {
@@ -359,13 +1067,40 @@ impl Contract {
         }
     }
 
+    /// tier of given seed the nft falls into, by its most specific priced key.
+    pub(crate) fn internal_get_nft_tier(&self, seed_id: &SeedId, contract_nft_token_id: &str) -> Option<String> {
+        let tiers = self.data().nft_tiers.get(seed_id)?;
+        nft_balance_keys(contract_nft_token_id).iter().find_map(|key| {
+            tiers
+                .iter()
+                .find(|(_, tier)| tier.prefixes.contains(key))
+                .map(|(name, _)| name.clone())
+        })
+    }
+
+    pub(crate) fn internal_update_nft_tier_count(&mut self, seed_id: &SeedId, tier: &Option<String>, staked: bool) {
+        if let Some(tier) = tier {
+            let mut counts = self.data().nft_tier_counts.get(seed_id).unwrap_or_default();
+            let count = counts.entry(tier.clone()).or_insert(0);
+            if staked {
+                *count += 1;
+            } else {
+                *count = count.saturating_sub(1);
+                if *count == 0 {
+                    counts.remove(tier);
+                }
+            }
+            self.data_mut().nft_tier_counts.insert(seed_id, &counts);
+        }
+    }
+
     pub(crate) fn internal_nft_withdraw(
         &mut self,
         seed_id: &String,
         sender_id: &AccountId,
         nft_contract_id: &String,
         nft_token_id: &String
-    ) -> ContractNFTTokenId {
+    ) -> NftStake {
         self.internal_claim_user_reward_by_seed_id(sender_id, seed_id);
 
         let mut farm_seed = self.get_seed(seed_id);
@@ -373,14 +1108,21 @@ impl Contract {
 
         // sub nft
         let contract_nft_token_id : ContractNFTTokenId = format!("{}{}{}", nft_contract_id, NFT_DELIMETER, nft_token_id);
-        farmer.get_ref_mut().sub_nft(seed_id, contract_nft_token_id.clone()).unwrap();
-        let nft_balance = self.data().nft_balance_seeds.get(&seed_id).unwrap();
-        let nft_balance_equivalent: Balance = get_nft_balance_equivalent(nft_balance, contract_nft_token_id.clone()).unwrap();
+        let was_staking = farmer.get_ref().is_staking(seed_id);
+        let nft_stake = farmer.get_ref_mut().sub_nft(seed_id, contract_nft_token_id.clone()).unwrap();
+        self.internal_update_nft_tier_count(seed_id, &nft_stake.tier, false);
 
-        let farmer_seed_remain = farmer.get_ref_mut().sub_seed(seed_id, nft_balance_equivalent);
+        let farmer_seed_remain = if nft_stake.applied > 0 {
+            farmer.get_ref_mut().sub_seed(seed_id, nft_stake.applied)
+        } else {
+            *farmer.get_ref().seeds.get(seed_id).unwrap_or(&0)
+        };
 
         // calculate farm_seed after multiplier get removed
-        farm_seed.get_ref_mut().sub_amount(nft_balance_equivalent);
+        farm_seed.get_ref_mut().sub_amount(nft_stake.applied);
+        farm_seed.get_ref_mut().update_farmer_count(was_staking, farmer.get_ref().is_staking(seed_id));
+        farm_seed.get_ref_mut().nft_count = farm_seed.get_ref().nft_count.saturating_sub(1);
+        self.internal_sync_opted_out_seed(farmer.get_ref(), seed_id, 0, nft_stake.applied);
 
         if farmer_seed_remain == 0 {
             // remove farmer rps of relative farm
@@ -403,6 +1145,6 @@ impl Contract {
             }
         };
 
-        contract_nft_token_id
+        nft_stake
     }
 }
\ No newline at end of file
@@ -0,0 +1,160 @@
+//! Layouts of stored structures before they got versioned,
+//! only read by upgrades and migrate.
+
+use std::collections::{HashMap, HashSet};
+
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
+use near_sdk::{AccountId, Balance};
+
+use crate::farm::{ContractNFTTokenId, Farm, FarmId, FarmRewardDistribution, FarmStatus, FarmTerms, RPS};
+use crate::farm_seed::{FarmSeed, FarmSeedMetadata, NftBalance, SeedId, SeedType};
+use crate::farmer::{Farmer, NftStake, VersionedFarmer};
+use crate::utils::{gen_farm_id, get_nft_balance_equivalent, TimestampSec, OTHER_FUNDERS};
+use crate::Contract;
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FarmerV101 {
+    pub farmer_id: AccountId,
+    pub amount: Balance,
+    pub rewards: HashMap<AccountId, Balance>,
+    pub seeds: HashMap<SeedId, Balance>,
+    pub user_rps: LookupMap<FarmId, RPS>,
+    pub rps_count: u32,
+    pub nft_seeds: HashMap<SeedId, UnorderedSet<ContractNFTTokenId>>,
+}
+
+impl FarmerV101 {
+    /// Staked nfts had no weight record, each gets its current balance equivalent
+    /// as full weight, which is what its deposit added to the farmer's seed.
+    /// rps_farms is rebuilt from farms ever created in the farmer's seeds.
+    pub(crate) fn upgrade(self, contract: &Contract) -> Farmer {
+        let mut farmer = VersionedFarmer::new(self.farmer_id, self.amount).get();
+        for (seed_id, nft_contract_seed) in self.nft_seeds.iter() {
+            let nft_balance = contract.data().nft_balance_seeds.get(seed_id);
+            for token_id in nft_contract_seed.iter() {
+                let weight = nft_balance
+                    .clone()
+                    .and_then(|nft_balance| get_nft_balance_equivalent(nft_balance, token_id.clone()))
+                    .unwrap_or(0);
+                farmer.nft_stakes.insert(
+                    token_id,
+                    NftStake { deposited_at: 0, weight, applied: weight, tier: None },
+                );
+            }
+        }
+        for seed_id in self.seeds.keys() {
+            let next_index = contract.get_seed_wrapped(seed_id).map_or(0, |farm_seed| farm_seed.get_ref().next_index);
+            for index in 0..next_index {
+                if self.user_rps.contains_key(&gen_farm_id(seed_id, index as usize)) {
+                    farmer.rps_farms.entry(seed_id.clone()).or_default().insert(index);
+                }
+            }
+        }
+        farmer.rewards = self.rewards;
+        farmer.seeds = self.seeds;
+        farmer.user_rps = self.user_rps;
+        farmer.rps_count = self.rps_count;
+        farmer.nft_seeds = self.nft_seeds;
+        farmer
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FarmSeedV101 {
+    pub seed_id: SeedId,
+    pub seed_type: SeedType,
+    pub farms: HashSet<FarmId>,
+    pub next_index: u32,
+    pub amount: Balance,
+    pub min_deposit: Balance,
+    pub metadata: Option<FarmSeedMetadata>,
+}
+
+impl From<FarmSeedV101> for FarmSeed {
+    /// farmer_count and nft_count count from the upgrade on.
+    fn from(farm_seed: FarmSeedV101) -> Self {
+        let mut upgraded = FarmSeed::new(
+            &farm_seed.seed_id,
+            farm_seed.min_deposit,
+            farm_seed.seed_type == SeedType::NFT,
+            farm_seed.metadata,
+        );
+        upgraded.farms = farm_seed.farms;
+        upgraded.next_index = farm_seed.next_index;
+        upgraded.amount = farm_seed.amount;
+        // volume before upgrade is unknown, start from current staked
+        upgraded.total_seed_deposited = farm_seed.amount;
+        upgraded
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FarmTermsV101 {
+    pub seed_id: SeedId,
+    pub reward_token: AccountId,
+    pub start_at: TimestampSec,
+    pub reward_per_session: Balance,
+    pub session_interval: TimestampSec,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct FarmV101 {
+    pub farm_id: FarmId,
+    pub terms: FarmTermsV101,
+    pub status: FarmStatus,
+    pub last_distribution: FarmRewardDistribution,
+    pub amount_of_reward: Balance,
+    pub amount_of_claimed: Balance,
+    pub amount_of_beneficiary: Balance,
+}
+
+impl FarmV101 {
+    /// Farms were only created by the owner, who becomes their creator and
+    /// sole beneficiary, with what beneficiary got by far left to withdraw.
+    pub(crate) fn upgrade(self, owner_id: &AccountId) -> Farm {
+        let terms = FarmTerms {
+            seed_id: self.terms.seed_id,
+            reward_token: self.terms.reward_token,
+            start_at: self.terms.start_at,
+            reward_per_session: self.terms.reward_per_session,
+            session_interval: self.terms.session_interval,
+            by_block_height: false,
+            accrual_granularity: 0,
+            decay_factor_bps: 0,
+            decay_period_rounds: 0,
+            end_at: 0,
+        };
+        let mut farm = Farm::new(self.farm_id, owner_id.clone(), terms);
+        farm.status = self.status;
+        farm.last_distribution = self.last_distribution;
+        farm.amount_of_reward = self.amount_of_reward;
+        farm.amount_of_claimed = self.amount_of_claimed;
+        farm.amount_of_beneficiary = self.amount_of_beneficiary;
+        if self.amount_of_reward > 0 {
+            farm.funders.insert(OTHER_FUNDERS.to_string(), self.amount_of_reward);
+        }
+        if self.amount_of_beneficiary > 0 {
+            farm.beneficiary_rewards.insert(owner_id.clone(), self.amount_of_beneficiary);
+            farm.beneficiary_balances.insert(owner_id.clone(), self.amount_of_beneficiary);
+        }
+        farm
+    }
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractDataV101 {
+    pub owner_id: AccountId,
+    pub seeds: UnorderedMap<SeedId, FarmSeedV101>,
+    pub farmers: LookupMap<AccountId, VersionedFarmer>,
+    pub farms: UnorderedMap<FarmId, FarmV101>,
+    pub outdated_farms: UnorderedMap<FarmId, FarmV101>,
+    pub nft_balance_seeds: LookupMap<SeedId, NftBalance>,
+    pub farmer_count: u64,
+    pub reward_info: UnorderedMap<AccountId, Balance>,
+}
+
+#[derive(BorshSerialize, BorshDeserialize)]
+pub struct ContractV101 {
+    pub data: ContractDataV101,
+}
@@ -2,39 +2,53 @@
 * Ref-Farming
 *
 * lib.rs is the main entry point.
+*
+* State-changing public methods require exactly one yocto attached,
+* so that they can not be called with a function call access key, except:
+* - methods taking a real deposit: create_simple_farm, storage_deposit, storage_deposit_many;
+* - owner methods, guarded by assert_owner;
+* - migrate and callbacks from this contract (#[private]), and callbacks from token contracts
+*   (ft_on_transfer, nft_on_transfer).
 */
 
 use std::collections::HashMap;
 use std::convert::TryInto;
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::collections::{LookupMap, UnorderedMap, UnorderedSet};
 use near_sdk::json_types::{ValidAccountId, U128};
 use near_sdk::BorshStorageKey;
 use near_sdk::{
     assert_one_yocto, env, near_bindgen, AccountId, Balance, PanicOnDefault, Promise, PromiseResult,
 };
 
-use crate::farm::{ContractNFTTokenId, Farm, FarmId, RPS};
+use crate::farm::{ContractNFTTokenId, CreateFarmArgs, Farm, FarmId, FarmMap, RPS};
 use crate::farm_seed::SeedType;
-use crate::farm_seed::{FarmSeedMetadata, NFTTokenId, NftBalance, SeedId, FarmSeed};
-use crate::farmer::{Farmer, VersionedFarmer};
+use crate::farm_seed::{FarmSeedMetadata, NFTTokenId, NftBalance, NftTier, SeedId, VersionedFarmSeed};
+use crate::farmer::{Farmer, NftStake, VersionedFarmer};
+use crate::legacy::{ContractDataV101, ContractV101};
 use crate::utils::{
-    ext_fungible_token, ext_non_fungible_token, ext_self, gen_farm_id, get_nft_balance_equivalent,
-    parse_farm_id, FT_INDEX_TAG, GAS_FOR_FT_TRANSFER, GAS_FOR_NFT_TRANSFER,
-    GAS_FOR_RESOLVE_TRANSFER, MIN_SEED_DEPOSIT, NFT_DELIMETER,
+    ext_fungible_token, ext_non_fungible_token, FtMetadata, ext_self, ext_self_nft, gen_farm_id,
+    get_nft_balance_equivalent, parse_farm_id, ramped_nft_weight, scale_nft_weight, to_sec, transfer_reward, CLAIM_FARMS_PER_PAGE, FT_INDEX_TAG,
+    GAS_FOR_CLAIM_PAGE, GAS_FOR_FT_BALANCE_OF, GAS_FOR_FT_TRANSFER, GAS_FOR_NFT_TRANSFER, MAX_BATCH_FARMS, MAX_NFT_WITHDRAW_PER_CALL, NEAR_REWARD_TOKEN, GAS_FOR_RESOLVE_TRANSFER, MIN_SEED_DEPOSIT, NFT_DELIMETER,
 };
 
 // for simulator test
 use crate::errors::*;
+use crate::events::{
+    emit_withdraw_failed, WithdrawFailedData, REASON_FT_TRANSFER_FAILED,
+    REASON_NFT_TRANSFER_FAILED,
+};
 pub use crate::farm::HRFarmTerms;
 pub use crate::view::FarmInfo;
 
 mod errors;
+mod events;
 mod farm;
 mod farm_seed;
 mod farmer;
 mod internals;
+mod legacy;
 mod storage_impl;
 mod token_receiver;
 mod utils;
@@ -55,6 +69,31 @@ pub enum StorageKeys {
     UserRps { account_id: AccountId },
     AccountSeedId { account_seed_id: String },
     NftBalanceSeed,
+    ProtocolFee,
+    TokenLedger,
+    NftTier,
+    NftTierCount,
+    Escrow,
+    SeedRemap,
+    FarmerId,
+    SeedAlias,
+    UserClaimRound { account_id: AccountId },
+}
+
+/// Internal bookkeeping of a fungible token held by this contract.
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct TokenLedger {
+    /// token transferred in minus token transferred out
+    pub held: Balance,
+    /// claimed reward sitting in farmers' balances or escrow, ready to withdraw
+    pub farmer_rewards: Balance,
+    /// reward distributed in farms but not yet claimed, as last saved
+    pub farm_unclaimed: Balance,
+    /// reward deposited to farms, active or outdated, not yet claimed,
+    /// reclaimed or withdrawn by beneficiaries, as last saved
+    pub farm_reserved: Balance,
+    /// sent out by transfers not resolved yet, still in the real balance meanwhile
+    pub pending_out: Balance,
 }
 
 #[derive(BorshDeserialize, BorshSerialize)]
@@ -64,20 +103,54 @@ pub struct ContractData {
 
     // record seeds and the farms under it.
     // seeds: UnorderedMap<SeedId, FarmSeed>,
-    seeds: UnorderedMap<SeedId, FarmSeed>,
+    seeds: UnorderedMap<SeedId, VersionedFarmSeed>,
 
     // each farmer has a structure to describe
     // farmers: LookupMap<AccountId, Farmer>,
     farmers: LookupMap<AccountId, VersionedFarmer>,
 
-    farms: UnorderedMap<FarmId, Farm>,
-    outdated_farms: UnorderedMap<FarmId, Farm>,
+    farms: FarmMap,
+    outdated_farms: FarmMap,
 
     nft_balance_seeds: LookupMap<SeedId, NftBalance>,
 
     // for statistic
     farmer_count: u64,
     reward_info: UnorderedMap<AccountId, Balance>,
+
+    // protocol fee taken from claims, per reward token
+    protocol_fees: UnorderedMap<AccountId, Balance>,
+
+    // for solvency check, per fungible token
+    token_ledger: LookupMap<AccountId, TokenLedger>,
+
+    // named tiers of nft seeds, and how many staked nfts fall into each
+    nft_tiers: LookupMap<SeedId, HashMap<String, NftTier>>,
+    nft_tier_counts: LookupMap<SeedId, HashMap<String, u64>>,
+
+    // reward moved out of farmers' balances, survives their unregistration
+    escrow: LookupMap<AccountId, HashMap<AccountId, Balance>>,
+
+    // an ended farm can only be cleaned after this long
+    cleanup_grace_sec: u32,
+
+    // seeds moved to a new id, farmers are moved page by page after the seed
+    seed_remaps: LookupMap<SeedId, SeedId>,
+
+    // all registered farmers, for views and batch jobs iterating over them
+    farmer_ids: UnorderedSet<AccountId>,
+
+    // min_rounds given to each new farm
+    default_min_rounds: u32,
+
+    // other token staked as the same FT seed, alias token to seed
+    seed_aliases: LookupMap<AccountId, SeedId>,
+
+    // cut of each claim going to the farmer's referrer, in bps of FEE_DIVISOR
+    referral_bps: u16,
+
+    // number of seeds the owner paused deposits of
+    paused_seed_count: u64,
 }
 
 #[near_bindgen]
@@ -97,10 +170,120 @@ impl Contract {
                 farmer_count: 0,
                 seeds: UnorderedMap::new(StorageKeys::Seed),
                 farmers: LookupMap::new(StorageKeys::Farmer),
-                farms: UnorderedMap::new(StorageKeys::Farm),
-                outdated_farms: UnorderedMap::new(StorageKeys::OutdatedFarm),
+                farms: FarmMap::new(StorageKeys::Farm),
+                outdated_farms: FarmMap::new(StorageKeys::OutdatedFarm),
                 reward_info: UnorderedMap::new(StorageKeys::RewardInfo),
                 nft_balance_seeds: LookupMap::new(StorageKeys::NftBalanceSeed),
+                protocol_fees: UnorderedMap::new(StorageKeys::ProtocolFee),
+                token_ledger: LookupMap::new(StorageKeys::TokenLedger),
+                nft_tiers: LookupMap::new(StorageKeys::NftTier),
+                nft_tier_counts: LookupMap::new(StorageKeys::NftTierCount),
+                escrow: LookupMap::new(StorageKeys::Escrow),
+                cleanup_grace_sec: 0,
+                seed_remaps: LookupMap::new(StorageKeys::SeedRemap),
+                farmer_ids: UnorderedSet::new(StorageKeys::FarmerId),
+                default_min_rounds: 0,
+                seed_aliases: LookupMap::new(StorageKeys::SeedAlias),
+                referral_bps: 0,
+                paused_seed_count: 0,
+            },
+        }
+    }
+
+    /// Migrate state stored before farmers, seeds and farms got versioned.
+    /// Farms are converted here, farmers and seeds upgrade on read.
+    /// token_balances are what this contract holds of each fungible token, to rebuild
+    /// the token ledger, every seed and reward token must be given.
+    /// Farmers registered before are not listed in farmer_ids, see backfill_farmer_ids.
+    #[init(ignore_state)]
+    #[private]
+    pub fn migrate(token_balances: Vec<(ValidAccountId, U128)>) -> Self {
+        let old: ContractV101 = env::state_read().expect(ERR67_NO_STATE_TO_MIGRATE);
+        let ContractDataV101 {
+            owner_id,
+            seeds: old_seeds,
+            farmers,
+            farms: old_farms,
+            outdated_farms: old_outdated_farms,
+            nft_balance_seeds,
+            farmer_count,
+            reward_info,
+        } = old.data;
+
+        // typed maps over the same storage, entries are rewritten raw below,
+        // as insert reads the replaced value in the new layout
+        let mut seeds: UnorderedMap<SeedId, VersionedFarmSeed> =
+            UnorderedMap::try_from_slice(&old_seeds.try_to_vec().unwrap()).unwrap();
+        let mut farms = FarmMap::try_from_slice(&old_farms.try_to_vec().unwrap()).unwrap();
+        let mut outdated_farms = FarmMap::try_from_slice(&old_outdated_farms.try_to_vec().unwrap()).unwrap();
+
+        // staked seed and reserved reward of each token
+        let mut owed: HashMap<AccountId, Balance> = HashMap::new();
+        let mut ledgers: HashMap<AccountId, TokenLedger> = HashMap::new();
+        for (seed_id, farm_seed) in old_seeds.to_vec() {
+            if farm_seed.seed_type == SeedType::FT {
+                *owed.entry(seed_id.clone()).or_default() += farm_seed.amount;
+            }
+            seeds.insert_raw(
+                &seed_id.try_to_vec().unwrap(),
+                &VersionedFarmSeed::V101(farm_seed).try_to_vec().unwrap(),
+            );
+        }
+        for (farm_id, farm) in old_farms.to_vec() {
+            let farm = farm.upgrade(&owner_id);
+            let reserved = farm.reserved_reward();
+            *owed.entry(farm.get_reward_token()).or_default() += reserved;
+            let ledger = ledgers.entry(farm.get_reward_token()).or_default();
+            ledger.farm_reserved += reserved;
+            ledger.farm_unclaimed += farm.last_distribution.unclaimed;
+            farms.insert(&farm_id, &farm);
+        }
+        for (farm_id, farm) in old_outdated_farms.to_vec() {
+            let farm = farm.upgrade(&owner_id);
+            let reserved = farm.reserved_reward();
+            *owed.entry(farm.get_reward_token()).or_default() += reserved;
+            ledgers.entry(farm.get_reward_token()).or_default().farm_reserved += reserved;
+            outdated_farms.insert(&farm_id, &farm);
+        }
+
+        let mut balances: HashMap<AccountId, Balance> =
+            token_balances.into_iter().map(|(token_id, balance)| (token_id.into(), balance.0)).collect();
+        let mut token_ledger = LookupMap::new(StorageKeys::TokenLedger);
+        for (token_id, owed) in owed {
+            assert!(balances.contains_key(&token_id), "{}: {}", ERR68_MISSING_TOKEN_BALANCE, token_id);
+            let mut ledger = ledgers.remove(&token_id).unwrap_or_default();
+            ledger.held = balances.remove(&token_id).unwrap();
+            // what is held beyond staked seed and reserved reward was claimed by farmers
+            ledger.farmer_rewards = ledger.held.saturating_sub(owed);
+            token_ledger.insert(&token_id, &ledger);
+        }
+        // tokens of farms long removed, only left in farmers' balances
+        for (token_id, held) in balances {
+            token_ledger.insert(&token_id, &TokenLedger { held, farmer_rewards: held, ..Default::default() });
+        }
+
+        Self {
+            data: ContractData {
+                owner_id,
+                farmer_count,
+                seeds,
+                farmers,
+                farms,
+                outdated_farms,
+                reward_info,
+                nft_balance_seeds,
+                protocol_fees: UnorderedMap::new(StorageKeys::ProtocolFee),
+                token_ledger,
+                nft_tiers: LookupMap::new(StorageKeys::NftTier),
+                nft_tier_counts: LookupMap::new(StorageKeys::NftTierCount),
+                escrow: LookupMap::new(StorageKeys::Escrow),
+                cleanup_grace_sec: 0,
+                seed_remaps: LookupMap::new(StorageKeys::SeedRemap),
+                farmer_ids: UnorderedSet::new(StorageKeys::FarmerId),
+                default_min_rounds: 0,
+                seed_aliases: LookupMap::new(StorageKeys::SeedAlias),
+                referral_bps: 0,
+                paused_seed_count: 0,
             },
         }
     }
@@ -118,8 +301,9 @@ impl Contract {
         let prev_storage = env::storage_usage();
         let min_deposit: u128 = min_deposit.unwrap_or(U128(MIN_SEED_DEPOSIT)).0;
         let farm_id = self.internal_add_farm(&terms, min_deposit, nft_balance, metadata);
-        // Check how much storage cost and refund the left over back.
-        let storage_needed = env::storage_usage() - prev_storage;
+        // Check how much storage cost and refund the left over back,
+        // a farm on existing seed may need little or even no more storage.
+        let storage_needed = env::storage_usage().saturating_sub(prev_storage);
         let storage_cost = storage_needed as u128 * env::storage_byte_cost();
         assert!(
             storage_cost <= env::attached_deposit(),
@@ -134,943 +318,5916 @@ impl Contract {
         farm_id
     }
 
-    /// Clean invalid rps,
-    /// return false if the rps is still valid.
-    pub fn remove_user_rps_by_farm(&mut self, farm_id: FarmId) -> bool {
-        let sender_id = env::predecessor_account_id();
-        let mut farmer = self.get_farmer(&sender_id);
-        let (seed_id, _) = parse_farm_id(&farm_id);
-        let farm_seed = self.get_seed(&seed_id);
-        if !farm_seed.get_ref().farms.contains(&farm_id) {
-            farmer.get_ref_mut().remove_rps(&farm_id);
-            self.data_mut().farmers.insert(&sender_id, &farmer);
-            true
-        } else {
-            false
-        }
-    }
-
-    pub fn claim_reward_by_farm(&mut self, farm_id: FarmId) {
-        let sender_id = env::predecessor_account_id();
-        self.internal_claim_user_reward_by_farm_id(&sender_id, &farm_id);
-        self.assert_storage_usage(&sender_id);
+    /// same as create_simple_farm, but returns the created farm.
+    #[payable]
+    pub fn create_simple_farm_v2(
+        &mut self,
+        terms: HRFarmTerms,
+        min_deposit: Option<U128>,
+        nft_balance: Option<HashMap<NFTTokenId, U128>>,
+        metadata: Option<FarmSeedMetadata>,
+    ) -> FarmInfo {
+        let farm_id = self.create_simple_farm(terms, min_deposit, nft_balance, metadata);
+        (&self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST)).into()
     }
 
-    pub fn claim_reward_by_seed(&mut self, seed_id: SeedId) {
-        let sender_id = env::predecessor_account_id();
-        self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
-        self.assert_storage_usage(&sender_id);
+    /// same as create_simple_farm, with terms and options in one struct.
+    #[payable]
+    pub fn create_farm(&mut self, args: CreateFarmArgs) -> FarmId {
+        self.create_simple_farm(args.terms, args.min_deposit, args.nft_balance, args.metadata)
     }
 
+    /// Add native NEAR reward to given farm, whose reward token is NEAR_REWARD_TOKEN,
+    /// the whole attached deposit is taken as reward, none of it goes to storage,
+    /// as funders of the farm are covered by its storage at creation.
     #[payable]
-    pub fn claim_reward_by_farm_and_withdraw(&mut self, farm_id: FarmId) {
-        assert_one_yocto();
-        let sender_id = env::predecessor_account_id();
-        self.internal_claim_user_reward_by_farm_id(&sender_id, &farm_id);
-        self.assert_storage_usage(&sender_id);
-
-        let token_id = self.get_farm(farm_id).unwrap().reward_token;
-        self.internal_withdraw_reward(token_id, None);
+    pub fn fund_farm_near(&mut self, farm_id: FarmId) {
+        let amount = env::attached_deposit();
+        assert!(amount > 0, "{}", ERR22_NOT_ENOUGH_TOKENS);
+        self.internal_deposit_reward(
+            &farm_id,
+            &NEAR_REWARD_TOKEN.to_string(),
+            &env::predecessor_account_id(),
+            amount,
+        );
     }
 
+    /// Farm creator takes back all deposited reward,
+    /// only before any round of the farm is distributed.
     #[payable]
-    pub fn claim_reward_by_seed_and_withdraw(&mut self, seed_id: SeedId) {
+    pub fn clawback_undistributed(&mut self, farm_id: FarmId) -> U128 {
         assert_one_yocto();
-        let sender_id = env::predecessor_account_id();
-        self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
-        self.assert_storage_usage(&sender_id);
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        assert_eq!(
+            env::predecessor_account_id(),
+            farm.creator_id,
+            "{}",
+            ERR46_NOT_FARM_CREATOR
+        );
+        let amount = farm.clawback().expect(ERR47_FARM_DISTRIBUTION_STARTED);
+        self.internal_save_farm(&farm_id, &farm);
 
-        let farmer = self.get_farmer(&sender_id);
+        let reward_token = farm.get_reward_token();
+        let reward_balance = self.data().reward_info.get(&reward_token).unwrap_or(0);
+        self.data_mut()
+            .reward_info
+            .insert(&reward_token, &(reward_balance - amount));
 
-        let seed = self.data().seeds.get(&seed_id).unwrap();
-        let mut reward_tokens: Vec<AccountId> = vec![];
-        for farm_id in seed.get_ref().farms.iter() {
-            let reward_token = self.data().farms.get(farm_id).unwrap().get_reward_token();
-            if !reward_tokens.contains(&reward_token) {
-                if farmer.get_ref().rewards.get(&reward_token).is_some() {
-                    self.internal_withdraw_reward(reward_token.clone(), None);
-                }
-                reward_tokens.push(reward_token);
-            }
+        if amount > 0 {
+            self.internal_ledger_sub_held(&reward_token, amount);
+            self.internal_ledger_add_pending_out(&reward_token, amount);
+            transfer_reward(&reward_token, farm.creator_id.clone(), amount, None)
+            .then(ext_self::callback_post_clawback(
+                farm_id,
+                amount.into(),
+                &env::current_account_id(),
+                0,
+                GAS_FOR_RESOLVE_TRANSFER,
+            ));
         }
+        amount.into()
     }
 
-    /// Withdraws given reward token of given user.
+    /// Send reward left undistributed in a farm, active or outdated, ended by its end_at
+    /// back to the farm creator, callable by the creator or the owner.
     #[payable]
-    pub fn withdraw_reward(&mut self, token_id: ValidAccountId, amount: Option<U128>) {
+    pub fn reclaim_leftover_reward(&mut self, farm_id: FarmId) -> U128 {
         assert_one_yocto();
+        let (farm, amount) = if let Some(mut farm) = self.data().farms.get(&farm_id) {
+            let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+            let amount = farm.take_leftover(&farm.effective_seeds(seed_amount));
+            self.internal_save_farm(&farm_id, &farm);
+            (farm, amount)
+        } else {
+            let mut farm = self.data().outdated_farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+            let amount = farm.take_leftover(&0);
+            self.internal_save_outdated_farm(&farm_id, &farm);
+            (farm, amount)
+        };
+        let caller = env::predecessor_account_id();
+        assert!(
+            caller == farm.creator_id || caller == self.data().owner_id,
+            "{}",
+            ERR46_NOT_FARM_CREATOR
+        );
+        let amount = amount.expect(ERR56_FARM_NOT_ENDED_BY_DATE);
 
-        self.internal_withdraw_reward(token_id.to_string(), amount);
-    }
-
-    #[private]
-    pub fn private_withdraw_reward(
-        &mut self,
-        token_id: AccountId,
-        sender_id: AccountId,
-        amount: Option<U128>,
-    ) {
-        self.internal_execute_withdraw_reward(token_id, sender_id, amount);
-    }
-
-    fn internal_withdraw_reward(&mut self, token_id: AccountId, amount: Option<U128>) {
-        let sender_id = env::predecessor_account_id();
-        self.internal_execute_withdraw_reward(token_id, sender_id, amount);
-    }
-
-    fn internal_execute_withdraw_reward(
-        &mut self,
-        token_id: AccountId,
-        sender_id: AccountId,
-        amount: Option<U128>,
-    ) {
-        let token_id: AccountId = token_id.into();
-        let amount: u128 = amount.unwrap_or(U128(0)).into();
-        let mut farmer = self.get_farmer(&sender_id);
-
-        // Note: subtraction, will be reverted if the promise fails.
-        let amount = farmer.get_ref_mut().sub_reward(&token_id, amount);
-        self.data_mut().farmers.insert(&sender_id, &farmer);
-        ext_fungible_token::ft_transfer(
-            sender_id.clone().try_into().unwrap(),
-            amount.into(),
-            None,
-            &token_id,
-            1,
-            GAS_FOR_FT_TRANSFER,
-        )
-        .then(ext_self::callback_post_withdraw_reward(
-            token_id,
-            sender_id,
+        let reward_token = farm.get_reward_token();
+        let reward_balance = self.data().reward_info.get(&reward_token).unwrap_or(0);
+        self.data_mut()
+            .reward_info
+            .insert(&reward_token, &(reward_balance - amount));
+        self.internal_ledger_sub_held(&reward_token, amount);
+        self.internal_ledger_add_pending_out(&reward_token, amount);
+        transfer_reward(&reward_token, farm.creator_id.clone(), amount, None)
+        .then(ext_self::callback_post_reclaim_leftover(
+            farm_id,
             amount.into(),
             &env::current_account_id(),
             0,
             GAS_FOR_RESOLVE_TRANSFER,
         ));
+        amount.into()
     }
 
     #[private]
-    pub fn callback_post_withdraw_reward(
-        &mut self,
-        token_id: AccountId,
-        sender_id: AccountId,
-        amount: U128,
-    ) {
+    pub fn callback_post_reclaim_leftover(&mut self, farm_id: FarmId, amount: U128) {
         assert_eq!(
             env::promise_results_count(),
             1,
             "{}",
             ERR25_CALLBACK_POST_WITHDRAW_INVALID
         );
+        let reward_token = self.data().farms.get(&farm_id)
+            .or_else(|| self.data().outdated_farms.get(&farm_id))
+            .expect(ERR41_FARM_NOT_EXIST)
+            .get_reward_token();
+        self.internal_ledger_sub_pending_out(&reward_token, amount.0);
         match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
             PromiseResult::Successful(_) => {
                 env::log(
-                    format!(
-                        "{} withdraw reward {} amount {}, Succeed.",
-                        sender_id, token_id, amount.0,
-                    )
-                    .as_bytes(),
+                    format!("{} reclaim leftover reward amount {}, Succeed.", farm_id, amount.0)
+                        .as_bytes(),
                 );
             }
             PromiseResult::Failed => {
                 env::log(
                     format!(
-                        "{} withdraw reward {} amount {}, Callback Failed.",
-                        sender_id, token_id, amount.0,
+                        "{} reclaim leftover reward amount {}, Callback Failed.",
+                        farm_id, amount.0
                     )
                     .as_bytes(),
                 );
-                // This reverts the changes from withdraw function.
-                let mut farmer = self.get_farmer(&sender_id);
-                farmer.get_ref_mut().add_reward(&token_id, amount.0);
-                self.data_mut().farmers.insert(&sender_id, &farmer);
+                // put the leftover back to the farm, it may have been cleaned meanwhile
+                let farm = if let Some(mut farm) = self.data().farms.get(&farm_id) {
+                    farm.restore_leftover(amount.0);
+                    self.internal_save_farm(&farm_id, &farm);
+                    farm
+                } else {
+                    let mut farm = self.data().outdated_farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+                    farm.restore_leftover(amount.0);
+                    self.internal_save_outdated_farm(&farm_id, &farm);
+                    farm
+                };
+                let reward_token = farm.get_reward_token();
+                let reward_balance = self.data().reward_info.get(&reward_token).unwrap_or(0);
+                self.data_mut()
+                    .reward_info
+                    .insert(&reward_token, &(reward_balance + amount.0));
+                self.internal_ledger_add_held(&reward_token, amount.0);
             }
         };
     }
 
-    pub fn force_upgrade_seed(&mut self, seed_id: SeedId) {
-        self.assert_owner();
-        let seed = self.get_seed_and_upgrade(&seed_id);
-        self.data_mut().seeds.insert(&seed_id, &seed);
-    }
-
-    #[payable]
-    pub fn withdraw_nft(
-        &mut self,
-        seed_id: SeedId,
-        nft_contract_id: String,
-        nft_token_id: NFTTokenId,
-    ) {
-        assert_one_yocto();
-        let sender_id = env::predecessor_account_id();
-
-        self.internal_nft_withdraw(&seed_id, &sender_id, &nft_contract_id, &nft_token_id);
-
-        // transfer nft back to the owner
-        ext_non_fungible_token::nft_transfer(
-            sender_id.clone(),
-            nft_token_id.clone(),
-            None,
-            None,
-            &nft_contract_id,
-            1,
-            GAS_FOR_NFT_TRANSFER,
-        )
-        .then(ext_self::callback_post_withdraw_nft(
-            seed_id,
-            sender_id,
-            nft_contract_id,
-            nft_token_id,
-            &env::current_account_id(),
-            0,
-            GAS_FOR_RESOLVE_TRANSFER,
-        ));
-    }
-
-    #[payable]
-    pub fn withdraw_seed(&mut self, seed_id: SeedId, amount: U128) {
-        assert_one_yocto();
-        let sender_id = env::predecessor_account_id();
-
-        let seed_contract_id: AccountId = seed_id.split(FT_INDEX_TAG).next().unwrap().to_string();
-        let amount: Balance = amount.into();
-
-        // update inner state
-        let seed_type = self.internal_seed_withdraw(&seed_id, &sender_id, amount);
-
-        match seed_type {
-            SeedType::FT => {
-                ext_fungible_token::ft_transfer(
-                    sender_id.clone().try_into().unwrap(),
-                    amount.into(),
-                    None,
-                    &seed_contract_id,
-                    1, // one yocto near
-                    GAS_FOR_FT_TRANSFER,
-                )
-                .then(ext_self::callback_post_withdraw_ft_seed(
-                    seed_id,
-                    sender_id,
-                    amount.into(),
-                    &env::current_account_id(),
-                    0,
-                    GAS_FOR_RESOLVE_TRANSFER,
-                ));
-            }
-            SeedType::NFT => {
-                panic!("Use withdraw_nft for this");
-            }
-        }
-    }
-
     #[private]
-    pub fn callback_post_withdraw_nft(
-        &mut self,
-        seed_id: SeedId,
-        sender_id: AccountId,
-        nft_contract_id: String,
-        nft_token_id: String,
-    ) {
+    pub fn callback_post_ft_metadata(&mut self, farm_id: FarmId) {
         assert_eq!(
             env::promise_results_count(),
             1,
             "{}",
-            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+            ERR65_CALLBACK_POST_FT_METADATA_INVALID
         );
-
-        match env::promise_result(0) {
-            PromiseResult::NotReady => unreachable!(),
-            PromiseResult::Failed => {
-                env::log(
-                    format!(
-                        "{} withdraw {} nft from {}, Callback failed.",
-                        sender_id, nft_token_id, nft_contract_id
-                    )
-                    .as_bytes(),
-                );
-
-                // revert withdraw
-
-                let mut farmer = self.get_farmer(&sender_id);
-                let mut farm_seed = self.get_seed(&seed_id);
-
-                let contract_nft_token_id: ContractNFTTokenId =
-                    format!("{}{}{}", nft_contract_id, NFT_DELIMETER, nft_token_id);
-                let nft_balance = self.data().nft_balance_seeds.get(&seed_id).unwrap();
-                if let Some(nft_balance_equivalent) =
-                    get_nft_balance_equivalent(nft_balance, contract_nft_token_id.clone())
-                {
-                    self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
-
-                    farmer
-                        .get_ref_mut()
-                        .add_nft(&seed_id, contract_nft_token_id);
-
-                    farmer
-                        .get_ref_mut()
-                        .add_seed(&seed_id, nft_balance_equivalent);
-                    self.data_mut().farmers.insert(&sender_id, &farmer);
-
-                    // **** update seed (new version)
-                    farm_seed.get_ref_mut().add_amount(nft_balance_equivalent);
-                    self.data_mut().seeds.insert(&seed_id, &farm_seed);
-                }
+        let decimals = match env::promise_result(0) {
+            PromiseResult::Successful(value) => {
+                near_sdk::serde_json::from_slice::<FtMetadata>(&value).ok().map(|metadata| metadata.decimals)
             }
-            PromiseResult::Successful(_) => {
-                env::log(
-                    format!(
-                        "{} withdraw {} nft from {}, Succeed.",
-                        sender_id, nft_token_id, nft_contract_id
-                    )
-                    .as_bytes(),
-                );
+            _ => None,
+        };
+        match (decimals, self.data().farms.get(&farm_id)) {
+            (Some(decimals), Some(mut farm)) => {
+                farm.reward_token_decimals = Some(decimals);
+                self.internal_save_farm(&farm_id, &farm);
+            }
+            _ => {
+                env::log(format!("{} reward token decimals unknown.", farm_id).as_bytes());
             }
         }
     }
+
     #[private]
-    pub fn callback_post_withdraw_ft_seed(
-        &mut self,
-        seed_id: SeedId,
-        sender_id: AccountId,
-        amount: U128,
-    ) {
+    pub fn callback_post_clawback(&mut self, farm_id: FarmId, amount: U128) {
         assert_eq!(
             env::promise_results_count(),
             1,
             "{}",
             ERR25_CALLBACK_POST_WITHDRAW_INVALID
         );
-        let amount: Balance = amount.into();
+        let reward_token = self.data().farms.get(&farm_id)
+            .or_else(|| self.data().outdated_farms.get(&farm_id))
+            .expect(ERR41_FARM_NOT_EXIST)
+            .get_reward_token();
+        self.internal_ledger_sub_pending_out(&reward_token, amount.0);
         match env::promise_result(0) {
             PromiseResult::NotReady => unreachable!(),
-            PromiseResult::Failed => {
+            PromiseResult::Successful(_) => {
                 env::log(
-                    format!(
-                        "{} withdraw {} ft seed with amount {}, Callback Failed.",
-                        sender_id, seed_id, amount,
-                    )
-                    .as_bytes(),
+                    format!("{} clawback reward amount {}, Succeed.", farm_id, amount.0)
+                        .as_bytes(),
                 );
-                // revert withdraw, equal to deposit, claim reward to update user reward_per_seed
-                self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
-                // **** update seed (new version)
-                let mut farm_seed = self.get_seed(&seed_id);
-                farm_seed.get_ref_mut().add_amount(amount);
-                self.data_mut().seeds.insert(&seed_id, &farm_seed);
-
-                let mut farmer = self.get_farmer(&sender_id);
-                farmer.get_ref_mut().add_seed(&seed_id, amount);
-                self.data_mut().farmers.insert(&sender_id, &farmer);
             }
-            PromiseResult::Successful(_) => {
+            PromiseResult::Failed => {
                 env::log(
                     format!(
-                        "{} withdraw {} ft seed with amount {}, Succeed.",
-                        sender_id, seed_id, amount,
+                        "{} clawback reward amount {}, Callback Failed.",
+                        farm_id, amount.0
                     )
                     .as_bytes(),
                 );
+                // put the reward back to the farm
+                let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+                if farm.add_reward(&farm.creator_id.clone(), &amount.0).is_some() {
+                    self.internal_save_farm(&farm_id, &farm);
+                    let reward_token = farm.get_reward_token();
+                    let reward_balance = self.data().reward_info.get(&reward_token).unwrap_or(0);
+                    self.data_mut()
+                        .reward_info
+                        .insert(&reward_token, &(reward_balance + amount.0));
+                    self.internal_ledger_add_held(&reward_token, amount.0);
+                }
             }
         };
     }
-}
 
-#[cfg(test)]
+    /// Clean invalid rps,
+    /// return false if the rps is still valid or the caller is not registered.
+    #[payable]
+    pub fn remove_user_rps_by_farm(&mut self, farm_id: FarmId) -> bool {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let mut farmer = match self.get_farmer_wrapped(&sender_id) {
+            Some(farmer) => farmer,
+            None => return false,
+        };
+        let (seed_id, _) = parse_farm_id(&farm_id);
+        let farm_seed = self.get_seed(&seed_id);
+        if !farm_seed.get_ref().farms.contains(&farm_id) {
+            farmer.get_ref_mut().remove_rps(&farm_id);
+            farmer.get_ref_mut().opted_out_farms.remove(&farm_id);
+            self.data_mut().farmers.insert(&sender_id, &farmer);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Clean invalid rps of given farms at once,
+    /// return for each farm whether its rps was removed.
+    #[payable]
+    pub fn remove_user_rps_by_farms(&mut self, farm_ids: Vec<FarmId>) -> Vec<bool> {
+        assert_one_yocto();
+        assert!(
+            !farm_ids.is_empty() && farm_ids.len() as u64 <= MAX_BATCH_FARMS,
+            "{}",
+            ERR15_INVALID_BATCH_SIZE
+        );
+        let sender_id = env::predecessor_account_id();
+        let mut farmer = match self.get_farmer_wrapped(&sender_id) {
+            Some(farmer) => farmer,
+            None => return vec![false; farm_ids.len()],
+        };
+        let removed: Vec<bool> = farm_ids
+            .iter()
+            .map(|farm_id| {
+                let (seed_id, _) = parse_farm_id(farm_id);
+                let stale = self
+                    .get_seed_wrapped(&seed_id)
+                    .map_or(true, |farm_seed| !farm_seed.get_ref().farms.contains(farm_id));
+                if stale {
+                    farmer.get_ref_mut().remove_rps(farm_id);
+                    farmer.get_ref_mut().opted_out_farms.remove(farm_id);
+                }
+                stale
+            })
+            .collect();
+        if removed.contains(&true) {
+            self.data_mut().farmers.insert(&sender_id, &farmer);
+        }
+        removed
+    }
+
+    /// Stop farming on given farm, while keep farming on other farms of the same seed.
+    #[payable]
+    pub fn opt_out_farm(&mut self, farm_id: FarmId) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let (seed_id, _) = parse_farm_id(&farm_id);
+        assert!(
+            self.get_seed(&seed_id).get_ref().farms.contains(&farm_id),
+            "{}",
+            ERR41_FARM_NOT_EXIST
+        );
+        // settle all farms of the seed with the current stake
+        self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
+
+        let mut farmer = self.get_farmer(&sender_id);
+        if farmer.get_ref_mut().opted_out_farms.insert(farm_id.clone()) {
+            let user_seeds = *farmer.get_ref().seeds.get(&seed_id).unwrap_or(&0);
+            let mut farm = self.data().farms.get(&farm_id).unwrap();
+            farm.opted_out_seed += user_seeds;
+            self.internal_save_farm(&farm_id, &farm);
+            self.data_mut().farmers.insert(&sender_id, &farmer);
+        }
+        self.assert_storage_usage(&sender_id);
+    }
+
+    /// Resume farming on given farm, reward during opted out is not counted.
+    #[payable]
+    pub fn opt_in_farm(&mut self, farm_id: FarmId) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let (seed_id, _) = parse_farm_id(&farm_id);
+        // settle all farms of the seed, user rps of this farm catches up with no reward
+        self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
+
+        let mut farmer = self.get_farmer(&sender_id);
+        if farmer.get_ref_mut().opted_out_farms.remove(&farm_id) {
+            let user_seeds = *farmer.get_ref().seeds.get(&seed_id).unwrap_or(&0);
+            let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+            farm.opted_out_seed -= user_seeds;
+            self.internal_save_farm(&farm_id, &farm);
+            self.data_mut().farmers.insert(&sender_id, &farmer);
+        }
+    }
+
+    /// Set or unset the account allowed to claim and withdraw on behalf of caller,
+    /// funds always go to caller.
+    #[payable]
+    pub fn set_claim_delegate(&mut self, delegate_id: Option<ValidAccountId>) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let mut farmer = self.get_farmer(&sender_id);
+        farmer.get_ref_mut().claim_delegate = delegate_id.map(|d| d.into());
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+        self.assert_storage_usage(&sender_id);
+    }
+
+    /// Opt in or out of covering a storage shortfall on claims from the NEAR reward just claimed.
+    #[payable]
+    pub fn set_storage_top_up(&mut self, enabled: bool) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let mut farmer = self.get_farmer(&sender_id);
+        farmer.get_ref_mut().storage_top_up = enabled;
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+    }
+
+    /// Set or unset the contract notified with claimed rewards after each claim,
+    /// see `ClaimReceiver`.
+    #[payable]
+    pub fn set_on_claim_receiver(&mut self, receiver_id: Option<ValidAccountId>) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let mut farmer = self.get_farmer(&sender_id);
+        farmer.get_ref_mut().on_claim_receiver = receiver_id.map(|r| r.into());
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+        self.assert_storage_usage(&sender_id);
+    }
+
+    /// Set the account referred caller, which gets referral_bps of each claim of caller
+    /// in its reward balance. Can be set only once.
+    #[payable]
+    pub fn set_referrer(&mut self, referrer_id: ValidAccountId) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let referrer_id: AccountId = referrer_id.into();
+        let mut farmer = self.get_farmer(&sender_id);
+        assert!(
+            farmer.get_ref().referrer.is_none()
+                && referrer_id != sender_id
+                && self.get_farmer_wrapped(&referrer_id).is_some(),
+            "{}",
+            ERR60_INVALID_REFERRER
+        );
+        farmer.get_ref_mut().referrer = Some(referrer_id);
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+        self.assert_storage_usage(&sender_id);
+    }
+
+    /// claim reward from given farm for account_id, caller must be its delegate.
+    #[payable]
+    pub fn claim_reward_by_farm_for(&mut self, account_id: ValidAccountId, farm_id: FarmId) {
+        assert_one_yocto();
+        let account_id: AccountId = account_id.into();
+        self.assert_claim_delegate(&account_id);
+        let claimed = self.internal_claim_and_notify_by_farm(&account_id, &farm_id, None);
+        self.assert_claim_storage_usage(&account_id, self.internal_near_claimed(&farm_id, claimed));
+    }
+
+    /// claim reward from given seed for account_id, caller must be its delegate.
+    #[payable]
+    pub fn claim_reward_by_seed_for(&mut self, account_id: ValidAccountId, seed_id: SeedId) {
+        assert_one_yocto();
+        let account_id: AccountId = account_id.into();
+        self.assert_claim_delegate(&account_id);
+        let claimed_rewards = self.internal_claim_user_reward_by_seed_id(&account_id, &seed_id);
+        let near_claimed = claimed_rewards.get(NEAR_REWARD_TOKEN).copied().unwrap_or(0);
+        self.assert_claim_storage_usage(&account_id, near_claimed);
+    }
+
+    /// Withdraws given reward token to account_id, caller must be its delegate.
+    #[payable]
+    pub fn withdraw_reward_for(
+        &mut self,
+        account_id: ValidAccountId,
+        token_id: ValidAccountId,
+        amount: Option<U128>,
+    ) {
+        assert_one_yocto();
+        let account_id: AccountId = account_id.into();
+        self.assert_claim_delegate(&account_id);
+        self.internal_execute_withdraw_reward(token_id.into(), account_id, amount, None);
+    }
+
+    /// claim reward from given farm,
+    /// if min_expected is given, revert when actual claimed reward is below it.
+    #[payable]
+    pub fn claim_reward_by_farm(&mut self, farm_id: FarmId, min_expected: Option<U128>) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let claimed = self.internal_claim_and_notify_by_farm(&sender_id, &farm_id, None);
+        if let Some(min_expected) = min_expected {
+            assert!(
+                claimed >= min_expected.0,
+                "{}: {}",
+                ERR23_CLAIMED_BELOW_MIN_EXPECTED,
+                claimed
+            );
+        }
+        self.assert_claim_storage_usage(&sender_id, self.internal_near_claimed(&farm_id, claimed));
+    }
+
+    /// claim at most max_amount of pending reward from given farm, the rest stays pending,
+    /// return amount of reward claimed, less protocol fee.
+    #[payable]
+    pub fn claim_reward_by_farm_amount(&mut self, farm_id: FarmId, max_amount: U128) -> U128 {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let claimed = self.internal_claim_and_notify_by_farm(&sender_id, &farm_id, Some(max_amount.0));
+        self.assert_claim_storage_usage(&sender_id, self.internal_near_claimed(&farm_id, claimed));
+        claimed.into()
+    }
+
+    /// Claims reward of given farm and withdraws it, only if what caller would get,
+    /// less protocol fee and referral share, is above min_net in reward token, else does nothing,
+    /// so keepers do not spend gas on dust. Return whether the claim went on.
+    #[payable]
+    pub fn claim_if_profitable(&mut self, farm_id: FarmId, min_net: U128) -> bool {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        let (net, _) = farm.split_fee(self.internal_unclaimed_reward(&sender_id, &farm_id));
+        let net = net - self.internal_referral_share(self.get_farmer(&sender_id).get_ref(), &farm.get_reward_token(), net);
+        if net <= min_net.0 {
+            return false;
+        }
+        let claimed = self.internal_claim_and_notify_by_farm(&sender_id, &farm_id, None);
+        if claimed == 0 {
+            // deferred under min_claim_amount of the farm
+            return false;
+        }
+        self.assert_claim_storage_usage(&sender_id, self.internal_near_claimed(&farm_id, claimed));
+        self.internal_execute_withdraw_reward(farm.get_reward_token(), sender_id, Some(claimed.into()), None);
+        true
+    }
+
+    /// Claims reward of all farms of given seed, page by page while gas lasts,
+    /// return farm index to continue from with claim_reward_by_seed_from, None if all claimed.
+    /// Before paging it returned nothing.
+    #[payable]
+    pub fn claim_reward_by_seed(&mut self, seed_id: SeedId) -> Option<u32> {
+        self.claim_reward_by_seed_from(seed_id, 0)
+    }
+
+    /// Continues claim_reward_by_seed from given farm index.
+    #[payable]
+    pub fn claim_reward_by_seed_from(&mut self, seed_id: SeedId, from_index: u32) -> Option<u32> {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let mut claimed_rewards: HashMap<AccountId, Balance> = HashMap::new();
+        let mut next = Some(from_index);
+        while let Some(index) = next {
+            if env::prepaid_gas().saturating_sub(env::used_gas()) < GAS_FOR_CLAIM_PAGE {
+                break;
+            }
+            let (claimed, next_index) = self.internal_claim_user_reward_by_seed_range(
+                &sender_id,
+                &seed_id,
+                index,
+                CLAIM_FARMS_PER_PAGE,
+            );
+            for (token, amount) in claimed {
+                *claimed_rewards.entry(token).or_insert(0) += amount;
+            }
+            next = next_index;
+        }
+        // nft weight only moves once every farm of the seed got settled in the same call
+        if from_index == 0 && next.is_none() {
+            self.internal_finish_seed_claim(&sender_id, &seed_id);
+        }
+        let near_claimed = claimed_rewards.get(NEAR_REWARD_TOKEN).copied().unwrap_or(0);
+        self.internal_notify_claim(&sender_id, &claimed_rewards);
+        self.assert_claim_storage_usage(&sender_id, near_claimed);
+        next
+    }
+
+    /// Claims reward of given FT seed, and stakes what was claimed in the seed token
+    /// back into the seed, with no transfer. Return amount compounded,
+    /// the part beyond max stake of the seed stays in caller's reward balance.
+    #[payable]
+    pub fn compound(&mut self, seed_id: SeedId) -> U128 {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let farm_seed = self.get_seed(&seed_id);
+        assert_eq!(farm_seed.get_ref().seed_type, SeedType::FT, "{}", ERR37_NOT_FT_SEED);
+        assert!(
+            farm_seed
+                .get_ref()
+                .farms
+                .iter()
+                .any(|farm_id| self.data().farms.get(farm_id).unwrap().get_reward_token() == seed_id),
+            "{}",
+            ERR40_REWARD_NOT_SEED_TOKEN
+        );
+        let claimed_rewards = self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
+        let claimed = claimed_rewards.get(&seed_id).copied().unwrap_or(0);
+
+        let mut farmer = self.get_farmer(&sender_id);
+        let current = farmer.get_ref().seeds.get(&seed_id).copied().unwrap_or(0);
+        let amount = if farm_seed.get_ref().paused {
+            0
+        } else {
+            farm_seed.get_ref().bound_farmer_stake(current, claimed).unwrap_or(0)
+        };
+        if amount > 0 {
+            let balance = farmer.get_ref().rewards.get(&seed_id).copied().unwrap_or(0);
+            // sub_reward takes 0 as the whole balance
+            farmer.get_ref_mut().sub_reward(&seed_id, if amount == balance { 0 } else { amount });
+            self.data_mut().farmers.insert(&sender_id, &farmer);
+            self.internal_ledger_sub_farmer_rewards(&seed_id, amount);
+            self.internal_add_farmer_seed(&seed_id, &sender_id, amount);
+        }
+        env::log(
+            format!("{} compound {} of seed {}.", sender_id, amount, seed_id).as_bytes(),
+        );
+        self.assert_storage_usage(&sender_id);
+        amount.into()
+    }
+
+    #[payable]
+    pub fn claim_reward_by_farm_and_withdraw(&mut self, farm_id: FarmId) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let claimed = self.internal_claim_and_notify_by_farm(&sender_id, &farm_id, None);
+        self.assert_claim_storage_usage(&sender_id, self.internal_near_claimed(&farm_id, claimed));
+
+        let token_id = self.get_farm(farm_id).unwrap().reward_token;
+        self.internal_withdraw_reward(token_id, None, None);
+    }
+
+    #[payable]
+    pub fn claim_reward_by_seed_and_withdraw(&mut self, seed_id: SeedId) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let claimed_rewards = self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
+        let near_claimed = claimed_rewards.get(NEAR_REWARD_TOKEN).copied().unwrap_or(0);
+        self.assert_claim_storage_usage(&sender_id, near_claimed);
+
+        let farmer = self.get_farmer(&sender_id);
+
+        let seed = self.get_seed(&seed_id);
+        let mut reward_tokens: Vec<AccountId> = vec![];
+        for farm_id in seed.get_ref().farms.iter() {
+            let reward_token = self.data().farms.get(farm_id).unwrap().get_reward_token();
+            if !reward_tokens.contains(&reward_token) {
+                if farmer.get_ref().rewards.get(&reward_token).is_some() {
+                    self.internal_withdraw_reward(reward_token.clone(), None, None);
+                }
+                reward_tokens.push(reward_token);
+            }
+        }
+    }
+
+    /// Withdraws given reward token of given user,
+    /// memo is passed to the ft_transfer.
+    #[payable]
+    pub fn withdraw_reward(
+        &mut self,
+        token_id: ValidAccountId,
+        amount: Option<U128>,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+
+        self.internal_withdraw_reward(token_id.to_string(), amount, memo);
+    }
+
+    /// Withdraws caller's full balance of given reward token in one transfer,
+    /// however many seeds it was earned from.
+    #[payable]
+    pub fn withdraw_reward_all_for_token(&mut self, token_id: ValidAccountId) -> U128 {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let token_id: AccountId = token_id.into();
+        let amount = self.get_farmer(&sender_id).get_ref().rewards.get(&token_id).copied().unwrap_or(0);
+        assert!(amount > 0, "{}", ERR22_NOT_ENOUGH_TOKENS);
+        self.internal_execute_withdraw_reward(token_id, sender_id, None, None);
+        amount.into()
+    }
+
+    /// Claims reward of given seed, then moves all caller's reward balances into escrow,
+    /// held by the contract apart from caller's storage record,
+    /// so that caller can unregister and withdraw them later token by token.
+    #[payable]
+    pub fn claim_to_escrow(&mut self, seed_id: SeedId) -> HashMap<AccountId, U128> {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
+
+        let mut farmer = self.get_farmer(&sender_id);
+        let rewards = std::mem::take(&mut farmer.get_ref_mut().rewards);
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+        let mut escrow = self.data().escrow.get(&sender_id).unwrap_or_default();
+        for (token_id, amount) in rewards.iter() {
+            *escrow.entry(token_id.clone()).or_insert(0) += amount;
+        }
+        self.data_mut().escrow.insert(&sender_id, &escrow);
+        env::log(format!("{} moved rewards {:?} to escrow.", sender_id, rewards).as_bytes());
+        rewards.into_iter().map(|(k, v)| (k, v.into())).collect()
+    }
+
+    /// Withdraws all caller's escrow of given reward token, registered or not,
+    /// a failed transfer goes back to escrow.
+    #[payable]
+    pub fn withdraw_from_escrow(&mut self, token_id: ValidAccountId) -> U128 {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let token_id: AccountId = token_id.into();
+        let mut escrow = self.data().escrow.get(&sender_id).unwrap_or_default();
+        let amount = escrow.remove(&token_id).expect(ERR21_TOKEN_NOT_REG);
+        if escrow.is_empty() {
+            self.data_mut().escrow.remove(&sender_id);
+        } else {
+            self.data_mut().escrow.insert(&sender_id, &escrow);
+        }
+
+        self.internal_ledger_sub_farmer_rewards(&token_id, amount);
+        self.internal_ledger_sub_held(&token_id, amount);
+        self.internal_ledger_add_pending_out(&token_id, amount);
+        self.assert_token_solvency(&token_id);
+        transfer_reward(&token_id, sender_id.clone(), amount, None)
+        .then(ext_self::callback_post_withdraw_escrow(
+            token_id,
+            sender_id,
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+        amount.into()
+    }
+
+    #[private]
+    pub fn callback_post_withdraw_escrow(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        self.internal_ledger_sub_pending_out(&token_id, amount.0);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                env::log(
+                    format!(
+                        "{} withdraw escrow {} amount {}, Succeed.",
+                        sender_id, token_id, amount.0,
+                    )
+                    .as_bytes(),
+                );
+            }
+            PromiseResult::Failed => {
+                env::log(
+                    format!(
+                        "{} withdraw escrow {} amount {}, Callback Failed.",
+                        sender_id, token_id, amount.0,
+                    )
+                    .as_bytes(),
+                );
+                let mut escrow = self.data().escrow.get(&sender_id).unwrap_or_default();
+                *escrow.entry(token_id.clone()).or_insert(0) += amount.0;
+                self.data_mut().escrow.insert(&sender_id, &escrow);
+                self.internal_ledger_add_farmer_rewards(&token_id, amount.0);
+                self.internal_ledger_add_held(&token_id, amount.0);
+            }
+        };
+    }
+
+    /// Withdraws all reward of given farm, active or outdated, that went to caller
+    /// as its beneficiary, caller must be registered.
+    #[payable]
+    pub fn withdraw_beneficiary_reward(&mut self, farm_id: FarmId) -> U128 {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let (token_id, amount) = if let Some(mut farm) = self.data().farms.get(&farm_id) {
+            let amount = farm.take_beneficiary_balance(&sender_id);
+            self.internal_save_farm(&farm_id, &farm);
+            (farm.get_reward_token(), amount)
+        } else {
+            let mut farm = self.data().outdated_farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+            let amount = farm.take_beneficiary_balance(&sender_id);
+            self.internal_save_outdated_farm(&farm_id, &farm);
+            (farm.get_reward_token(), amount)
+        };
+        assert!(amount > 0, "{}", ERR22_NOT_ENOUGH_TOKENS);
+
+        // into caller's balance first, where a failed transfer is reverted to
+        let mut farmer = self.get_farmer(&sender_id);
+        farmer.get_ref_mut().add_reward(&token_id, amount);
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+        self.internal_ledger_add_farmer_rewards(&token_id, amount);
+        self.assert_storage_usage(&sender_id);
+        self.internal_execute_withdraw_reward(token_id, sender_id, Some(amount.into()), None);
+        amount.into()
+    }
+
+    /// Give up all caller's balance of given reward token, without any transfer,
+    /// the balance goes to protocol fee, so that caller can unregister
+    /// even if the token contract can not be withdrawn from anymore.
+    #[payable]
+    pub fn forfeit_reward(&mut self, token_id: ValidAccountId) -> U128 {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let token_id: AccountId = token_id.into();
+        let mut farmer = self.get_farmer(&sender_id);
+        let amount = farmer.get_ref_mut().sub_reward(&token_id, 0);
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+        self.internal_ledger_sub_farmer_rewards(&token_id, amount);
+        self.internal_add_protocol_fee(&token_id, amount);
+        env::log(
+            format!("{} forfeit reward {} amount {}.", sender_id, token_id, amount).as_bytes(),
+        );
+        amount.into()
+    }
+
+    #[private]
+    pub fn private_withdraw_reward(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: Option<U128>,
+    ) {
+        self.internal_execute_withdraw_reward(token_id, sender_id, amount, None);
+    }
+
+    fn internal_withdraw_reward(
+        &mut self,
+        token_id: AccountId,
+        amount: Option<U128>,
+        memo: Option<String>,
+    ) {
+        let sender_id = env::predecessor_account_id();
+        self.internal_execute_withdraw_reward(token_id, sender_id, amount, memo);
+    }
+
+    fn internal_execute_withdraw_reward(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: Option<U128>,
+        memo: Option<String>,
+    ) {
+        let token_id: AccountId = token_id.into();
+        let amount: u128 = amount.unwrap_or(U128(0)).into();
+        let mut farmer = self.get_farmer(&sender_id);
+
+        // Note: subtraction, will be reverted if the promise fails.
+        let amount = farmer.get_ref_mut().sub_reward(&token_id, amount);
+        self.data_mut().farmers.insert(&sender_id, &farmer);
+        self.internal_ledger_sub_farmer_rewards(&token_id, amount);
+        self.internal_ledger_sub_held(&token_id, amount);
+        self.internal_ledger_add_pending_out(&token_id, amount);
+        self.assert_token_solvency(&token_id);
+        transfer_reward(&token_id, sender_id.clone(), amount, memo)
+        .then(ext_self::callback_post_withdraw_reward(
+            token_id,
+            sender_id,
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+    }
+
+    #[private]
+    pub fn callback_post_withdraw_reward(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        self.internal_ledger_sub_pending_out(&token_id, amount.0);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(value) => {
+                // plain ft_transfer returns nothing and moves all,
+                // a transfer call returns the used amount, the rest comes back to us.
+                let used = if value.is_empty() {
+                    amount.0
+                } else {
+                    near_sdk::serde_json::from_slice::<U128>(&value)
+                        .map(|used| std::cmp::min(used.0, amount.0))
+                        .unwrap_or(amount.0)
+                };
+                if used < amount.0 {
+                    env::log(
+                        format!(
+                            "{} withdraw reward {} amount {}, Partially Succeed with {}.",
+                            sender_id, token_id, amount.0, used,
+                        )
+                        .as_bytes(),
+                    );
+                    self.internal_revert_withdraw_reward(&token_id, &sender_id, amount.0 - used);
+                } else {
+                    env::log(
+                        format!(
+                            "{} withdraw reward {} amount {}, Succeed.",
+                            sender_id, token_id, amount.0,
+                        )
+                        .as_bytes(),
+                    );
+                }
+            }
+            PromiseResult::Failed => {
+                env::log(
+                    format!(
+                        "{} withdraw reward {} amount {}, Callback Failed.",
+                        sender_id, token_id, amount.0,
+                    )
+                    .as_bytes(),
+                );
+                emit_withdraw_failed(WithdrawFailedData {
+                    reason: REASON_FT_TRANSFER_FAILED.to_string(),
+                    account_id: sender_id.clone(),
+                    token_id: Some(token_id.clone()),
+                    amount: Some(amount),
+                    ..Default::default()
+                });
+                self.internal_revert_withdraw_reward(&token_id, &sender_id, amount.0);
+            }
+        };
+    }
+
+    /// This reverts the changes from withdraw function for the amount not transferred.
+    fn internal_revert_withdraw_reward(
+        &mut self,
+        token_id: &AccountId,
+        sender_id: &AccountId,
+        amount: Balance,
+    ) {
+        let mut farmer = self.get_farmer(sender_id);
+        farmer.get_ref_mut().add_reward(token_id, amount);
+        self.data_mut().farmers.insert(sender_id, &farmer);
+        self.internal_ledger_add_farmer_rewards(token_id, amount);
+        self.internal_ledger_add_held(token_id, amount);
+    }
+
+    pub fn force_upgrade_seed(&mut self, seed_id: SeedId) {
+        self.assert_owner();
+        let seed = self.get_seed_and_upgrade(&seed_id);
+        self.data_mut().seeds.insert(&seed_id, &seed);
+    }
+
+    #[payable]
+    pub fn withdraw_nft(
+        &mut self,
+        seed_id: SeedId,
+        nft_contract_id: String,
+        nft_token_id: NFTTokenId,
+        memo: Option<String>,
+    ) {
+        assert_one_yocto();
+        assert_eq!(
+            self.get_seed(&seed_id).get_ref().seed_type,
+            SeedType::NFT,
+            "{}",
+            ERR38_NOT_NFT_SEED
+        );
+        let sender_id = env::predecessor_account_id();
+        self.internal_execute_withdraw_nft(seed_id, sender_id, nft_contract_id, nft_token_id, memo);
+    }
+
+    /// Withdraws all staked seed of caller in given seed, with rewards claimed first.
+    /// For NFT seed, at most MAX_NFT_WITHDRAW_PER_CALL nfts go in one call,
+    /// call again until nothing left.
+    #[payable]
+    pub fn withdraw_all_seed(&mut self, seed_id: SeedId) {
+        assert_one_yocto();
+        let sender_id = env::predecessor_account_id();
+        let farmer = self.get_farmer(&sender_id);
+        match self.get_seed(&seed_id).get_ref().seed_type {
+            SeedType::FT => {
+                let amount = *farmer.get_ref().seeds.get(&seed_id).expect(ERR31_SEED_NOT_EXIST);
+                self.internal_execute_withdraw_seed(seed_id, sender_id, amount, None, false);
+            }
+            SeedType::NFT => {
+                let nfts: Vec<ContractNFTTokenId> = farmer
+                    .get_ref()
+                    .nft_seeds
+                    .get(&seed_id)
+                    .map(|nfts| nfts.iter().take(MAX_NFT_WITHDRAW_PER_CALL).collect())
+                    .unwrap_or_default();
+                assert!(!nfts.is_empty(), "{}", ERR31_SEED_NOT_EXIST);
+                for contract_nft_token_id in nfts {
+                    let mut parts = contract_nft_token_id.splitn(2, NFT_DELIMETER);
+                    let nft_contract_id = parts.next().unwrap().to_string();
+                    let nft_token_id = parts.next().expect(ERR500).to_string();
+                    self.internal_execute_withdraw_nft(
+                        seed_id.clone(),
+                        sender_id.clone(),
+                        nft_contract_id,
+                        nft_token_id,
+                        None,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Withdraws given amount of staked FT seed, with rewards claimed first,
+    /// claimed rewards stay in caller's internal balance unless keep_rewards is false,
+    /// then they are sent out together.
+    #[payable]
+    pub fn withdraw_seed(&mut self, seed_id: SeedId, amount: U128, memo: Option<String>, keep_rewards: Option<bool>) {
+        assert_one_yocto();
+        assert_eq!(
+            self.get_seed(&seed_id).get_ref().seed_type,
+            SeedType::FT,
+            "{}",
+            ERR37_NOT_FT_SEED
+        );
+        let sender_id = env::predecessor_account_id();
+        self.internal_execute_withdraw_seed(seed_id, sender_id, amount.into(), memo, keep_rewards.unwrap_or(true));
+    }
+
+    fn internal_execute_withdraw_nft(
+        &mut self,
+        seed_id: SeedId,
+        sender_id: AccountId,
+        nft_contract_id: String,
+        nft_token_id: NFTTokenId,
+        memo: Option<String>,
+    ) {
+        let nft_stake = self.internal_nft_withdraw(&seed_id, &sender_id, &nft_contract_id, &nft_token_id);
+
+        // transfer nft back to the owner
+        ext_non_fungible_token::nft_transfer(
+            sender_id.clone(),
+            nft_token_id.clone(),
+            None,
+            memo,
+            &nft_contract_id,
+            1,
+            GAS_FOR_NFT_TRANSFER,
+        )
+        .then(ext_self_nft::callback_post_withdraw_nft(
+            seed_id,
+            sender_id,
+            nft_contract_id,
+            nft_token_id,
+            Some(nft_stake.weight.into()),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+    }
+
+    fn internal_execute_withdraw_seed(
+        &mut self,
+        seed_id: SeedId,
+        sender_id: AccountId,
+        amount: Balance,
+        memo: Option<String>,
+        keep_rewards: bool,
+    ) {
+        let seed_contract_id: AccountId = seed_id.split(FT_INDEX_TAG).next().unwrap().to_string();
+
+        // update inner state
+        let parts = self.internal_split_seed_withdraw(&seed_id, &sender_id, amount);
+        let seed_type = self.internal_seed_withdraw(&seed_id, &sender_id, amount, keep_rewards);
+
+        match seed_type {
+            SeedType::FT => {
+                // each part goes back in the token it was staked in
+                for (token_id, part) in parts {
+                    self.internal_ledger_sub_held(&token_id, part);
+                    self.internal_ledger_add_pending_out(&token_id, part);
+                    self.assert_token_solvency(&token_id);
+                    let transfer = ext_fungible_token::ft_transfer(
+                        sender_id.clone().try_into().unwrap(),
+                        part.into(),
+                        memo.clone(),
+                        &token_id,
+                        1, // one yocto near
+                        GAS_FOR_FT_TRANSFER,
+                    );
+                    if token_id == seed_contract_id {
+                        transfer.then(ext_self::callback_post_withdraw_ft_seed(
+                            seed_id.clone(),
+                            sender_id.clone(),
+                            part.into(),
+                            &env::current_account_id(),
+                            0,
+                            GAS_FOR_RESOLVE_TRANSFER,
+                        ));
+                    } else {
+                        transfer.then(ext_self::callback_post_withdraw_alias_seed(
+                            seed_id.clone(),
+                            token_id,
+                            sender_id.clone(),
+                            part.into(),
+                            &env::current_account_id(),
+                            0,
+                            GAS_FOR_RESOLVE_TRANSFER,
+                        ));
+                    }
+                }
+            }
+            SeedType::NFT => {
+                panic!("Use withdraw_nft for this");
+            }
+        }
+    }
+
+    #[private]
+    pub fn callback_post_withdraw_nft(
+        &mut self,
+        seed_id: SeedId,
+        sender_id: AccountId,
+        nft_contract_id: String,
+        nft_token_id: String,
+        weight: Option<U128>,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Failed => {
+                env::log(
+                    format!(
+                        "{} withdraw {} nft from {}, Callback failed.",
+                        sender_id, nft_token_id, nft_contract_id
+                    )
+                    .as_bytes(),
+                );
+                emit_withdraw_failed(WithdrawFailedData {
+                    reason: REASON_NFT_TRANSFER_FAILED.to_string(),
+                    account_id: sender_id.clone(),
+                    token_id: Some(nft_contract_id.clone()),
+                    seed_id: Some(seed_id.clone()),
+                    nft_token_id: Some(nft_token_id.clone()),
+                    ..Default::default()
+                });
+
+                // revert withdraw, the nft is staked again from now on,
+                // with the weight it had, even if its nft balance changed since.
+
+                let contract_nft_token_id: ContractNFTTokenId =
+                    format!("{}{}{}", nft_contract_id, NFT_DELIMETER, nft_token_id);
+                let nft_balance_equivalent: Balance = match weight {
+                    Some(weight) => weight.into(),
+                    // scheduled without the weight, take the one the seed gives it now
+                    None => self
+                        .data()
+                        .nft_balance_seeds
+                        .get(&seed_id)
+                        .and_then(|nft_balance| get_nft_balance_equivalent(nft_balance, contract_nft_token_id.clone()))
+                        .and_then(|weight| scale_nft_weight(weight, self.get_seed(&seed_id).get_ref().weight_scale))
+                        .unwrap_or(0),
+                };
+                self.internal_claim_user_reward_by_seed_id(&sender_id, &seed_id);
+
+                let mut farmer = self.get_farmer(&sender_id);
+                let mut farm_seed = self.get_seed(&seed_id);
+                let deposited_at = to_sec(env::block_timestamp());
+                let applied = ramped_nft_weight(
+                    nft_balance_equivalent,
+                    deposited_at,
+                    farm_seed.get_ref().nft_ramp_sec,
+                );
+
+                let tier = self.internal_get_nft_tier(&seed_id, &contract_nft_token_id);
+                let was_staking = farmer.get_ref().is_staking(&seed_id);
+                if !farmer.get_ref_mut().add_nft(
+                    &seed_id,
+                    contract_nft_token_id,
+                    NftStake {
+                        deposited_at,
+                        weight: nft_balance_equivalent,
+                        applied,
+                        tier: tier.clone(),
+                    },
+                ) {
+                    return;
+                }
+                self.internal_update_nft_tier_count(&seed_id, &tier, true);
+
+                farmer.get_ref_mut().add_seed(&seed_id, applied);
+                self.data_mut().farmers.insert(&sender_id, &farmer);
+                self.internal_sync_opted_out_seed(farmer.get_ref(), &seed_id, applied, 0);
+
+                // **** update seed (new version)
+                farm_seed.get_ref_mut().add_amount(applied);
+                farm_seed.get_ref_mut().update_farmer_count(was_staking, true);
+                farm_seed.get_ref_mut().nft_count += 1;
+                self.data_mut().seeds.insert(&seed_id, &farm_seed);
+            }
+            PromiseResult::Successful(_) => {
+                env::log(
+                    format!(
+                        "{} withdraw {} nft from {}, Succeed.",
+                        sender_id, nft_token_id, nft_contract_id
+                    )
+                    .as_bytes(),
+                );
+            }
+        }
+    }
+    /// Same as callback_post_withdraw_ft_seed, for the part staked through an alias token.
+    #[private]
+    pub fn callback_post_withdraw_alias_seed(
+        &mut self,
+        seed_id: SeedId,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        let amount: Balance = amount.into();
+        self.internal_ledger_sub_pending_out(&token_id, amount);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Failed => {
+                env::log(
+                    format!(
+                        "{} withdraw {} ft seed in {} with amount {}, Callback Failed.",
+                        sender_id, seed_id, token_id, amount,
+                    )
+                    .as_bytes(),
+                );
+                emit_withdraw_failed(WithdrawFailedData {
+                    reason: REASON_FT_TRANSFER_FAILED.to_string(),
+                    account_id: sender_id.clone(),
+                    seed_id: Some(seed_id.clone()),
+                    amount: Some(amount.into()),
+                    ..Default::default()
+                });
+                self.internal_revert_ft_seed_withdraw(&seed_id, &token_id, &sender_id, amount);
+                self.internal_alias_seed_deposit(&seed_id, &token_id, &sender_id, amount);
+            }
+            PromiseResult::Successful(_) => {
+                env::log(
+                    format!(
+                        "{} withdraw {} ft seed in {} with amount {}, Succeed.",
+                        sender_id, seed_id, token_id, amount,
+                    )
+                    .as_bytes(),
+                );
+            }
+        };
+    }
+
+    #[private]
+    pub fn callback_post_withdraw_ft_seed(
+        &mut self,
+        seed_id: SeedId,
+        sender_id: AccountId,
+        amount: U128,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        let amount: Balance = amount.into();
+        let seed_contract_id: AccountId =
+            seed_id.split(FT_INDEX_TAG).next().unwrap().to_string();
+        self.internal_ledger_sub_pending_out(&seed_contract_id, amount);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Failed => {
+                env::log(
+                    format!(
+                        "{} withdraw {} ft seed with amount {}, Callback Failed.",
+                        sender_id, seed_id, amount,
+                    )
+                    .as_bytes(),
+                );
+                emit_withdraw_failed(WithdrawFailedData {
+                    reason: REASON_FT_TRANSFER_FAILED.to_string(),
+                    account_id: sender_id.clone(),
+                    seed_id: Some(seed_id.clone()),
+                    amount: Some(amount.into()),
+                    ..Default::default()
+                });
+                self.internal_revert_ft_seed_withdraw(&seed_id, &seed_contract_id, &sender_id, amount);
+            }
+            PromiseResult::Successful(_) => {
+                env::log(
+                    format!(
+                        "{} withdraw {} ft seed with amount {}, Succeed.",
+                        sender_id, seed_id, amount,
+                    )
+                    .as_bytes(),
+                );
+            }
+        };
+    }
+}
+
+#[cfg(test)]
 mod tests {
 
-    use farm::HRFarmTerms;
-    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
-    use near_contract_standards::storage_management::{StorageBalance, StorageManagement};
-    use near_sdk::json_types::{ValidAccountId, U128};
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
-    use near_sdk::{testing_env, Balance, MockedBlockchain};
+    use farm::{HRFarmTerms, FEE_DIVISOR};
+    use near_contract_standards::fungible_token::receiver::FungibleTokenReceiver;
+    use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+    use near_contract_standards::storage_management::{StorageBalance, StorageManagement};
+    use near_sdk::json_types::{ValidAccountId, U128, U64};
+    use near_sdk::test_utils::{accounts, get_created_receipts, VMContextBuilder};
+    use near_sdk::{testing_env, Balance, MockedBlockchain};
+
+    use super::utils::*;
+    use super::*;
+
+    fn setup_contract() -> (VMContextBuilder, Contract) {
+        let mut context = VMContextBuilder::new();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        let contract = Contract::new(accounts(0));
+        (context, contract)
+    }
+
+    fn create_farm(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        seed: ValidAccountId,
+        reward: ValidAccountId,
+        session_amount: Balance,
+        session_interval: u32,
+    ) -> FarmId {
+        // storage needed: 341
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: seed.into(),
+                reward_token: reward.into(),
+                start_at: 0,
+                reward_per_session: U128(session_amount),
+                session_interval: session_interval,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        )
+    }
+
+    fn deposit_reward(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        amount: u128,
+        time_stamp: u32,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(amount), String::from("bob#0"));
+    }
+
+    fn register_farmer(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+    ) -> StorageBalance {
+        testing_env!(context
+            .predecessor_account_id(farmer.clone())
+            .is_view(false)
+            .attached_deposit(env::storage_byte_cost() * 1852)
+            .build());
+        contract.storage_deposit(Some(farmer), Some(true))
+    }
+
+    fn storage_withdraw(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+    ) -> StorageBalance {
+        testing_env!(context
+            .predecessor_account_id(farmer.clone())
+            .is_view(false)
+            .attached_deposit(1)
+            .build());
+        contract.storage_withdraw(None)
+    }
+
+    fn deposit_seed(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+        time_stamp: u32,
+        amount: Balance,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .is_view(false)
+            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(farmer, U128(amount), String::from(""));
+    }
+
+    fn withdraw_seed(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+        time_stamp: u32,
+        amount: Balance,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(farmer)
+            .is_view(false)
+            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_seed(accounts(1).into(), U128(amount), None, Some(false));
+    }
+
+    fn claim_reward(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+        time_stamp: u32,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(farmer)
+            .is_view(false)
+            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(String::from("bob#0"), None);
+    }
+
+    fn claim_reward_by_seed(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+        time_stamp: u32,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(farmer)
+            .is_view(false)
+            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_seed(String::from("bob"));
+    }
+
+    fn remove_farm(context: &mut VMContextBuilder, contract: &mut Contract, time_stamp: u32) {
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .is_view(false)
+            .block_timestamp(to_nano(time_stamp))
+            .build());
+        contract.force_clean_farm(String::from("bob#0"), None);
+    }
+
+    fn remove_user_rps(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+        farm_id: String,
+        time_stamp: u32,
+    ) -> bool {
+        testing_env!(context
+            .predecessor_account_id(farmer)
+            .is_view(false)
+            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(1)
+            .build());
+        contract.remove_user_rps_by_farm(farm_id)
+    }
+
+    /// env of a callback from this contract with given promise result.
+    fn callback_env(context: &mut VMContextBuilder, time_stamp: u32, promise_result: PromiseResult) {
+        testing_env!(
+            context
+                .predecessor_account_id(env::current_account_id().try_into().unwrap())
+                .block_timestamp(to_nano(time_stamp))
+                .attached_deposit(0)
+                .build(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+            vec![promise_result]
+        );
+    }
+
+    fn create_nft_farm(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        seed: ValidAccountId,
+        reward: ValidAccountId,
+        nft_balance: HashMap<NFTTokenId, U128>,
+    ) -> FarmId {
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 1000)
+            .build());
+        contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: seed.into(),
+                reward_token: reward,
+                start_at: 0,
+                reward_per_session: U128(5000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            Some(nft_balance),
+            None,
+        )
+    }
+
+    fn deposit_nft(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        farmer: ValidAccountId,
+        nft_contract: ValidAccountId,
+        nft_token_id: &str,
+        seed_id: &str,
+        time_stamp: u32,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(nft_contract)
+            .signer_account_id(farmer.clone())
+            .is_view(false)
+            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(0)
+            .build());
+        contract.nft_on_transfer(
+            farmer.clone().into(),
+            farmer.into(),
+            nft_token_id.to_string(),
+            seed_id.to_string(),
+        );
+    }
+
+    fn to_yocto(value: &str) -> u128 {
+        let vals: Vec<_> = value.split('.').collect();
+        let part1 = vals[0].parse::<u128>().unwrap() * 10u128.pow(24);
+        if vals.len() > 1 {
+            let power = vals[1].len() as u32;
+            let part2 = vals[1].parse::<u128>().unwrap() * 10u128.pow(24 - power);
+            part1 + part2
+        } else {
+            part1
+        }
+    }
+
+    #[test]
+    fn test_basics() {
+        let (mut context, mut contract) = setup_contract();
+        // seed is bob, reward is charlie
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        assert_eq!(farm_id, String::from("bob#0"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        // assert_eq!(farm_info.farm_kind, String::from("SIMPLE_FARM"));
+        assert_eq!(farm_info.farm_status, String::from("Created"));
+        assert_eq!(farm_info.seed_id, String::from("bob"));
+        assert_eq!(farm_info.reward_token, String::from("charlie"));
+        assert_eq!(farm_info.reward_per_session, U128(5000));
+        assert_eq!(farm_info.session_interval, 50);
+
+        // deposit 50k, can last 10 rounds from 0 to 9
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.farm_status, String::from("Running"));
+        assert_eq!(farm_info.start_at, 100);
+
+        // Farmer accounts(0) come in round 1
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.beneficiary_reward, U128(5000));
+        assert_eq!(farm_info.cur_round, 1);
+        assert_eq!(farm_info.last_round, 1);
+
+        // move to round 2, 5k unclaimed for accounts(0)
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(210))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(5000));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 2);
+        assert_eq!(farm_info.last_round, 1);
+
+        // Farmer accounts(3) come in
+        register_farmer(&mut context, &mut contract, accounts(3));
+        // deposit seed
+        deposit_seed(&mut context, &mut contract, accounts(3), 260, 10);
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(10000));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 3);
+        assert_eq!(farm_info.last_round, 3);
+
+        // move to round 4,
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(320))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(12500));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(2500));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 4);
+        assert_eq!(farm_info.last_round, 3);
+
+        // remove all seeds at round 5
+        println!("----> remove all seeds at round 5");
+        withdraw_seed(&mut context, &mut contract, accounts(0), 360, 10);
+        withdraw_seed(&mut context, &mut contract, accounts(3), 370, 10);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(380))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let rewarded = contract.get_reward(accounts(0), accounts(2));
+        assert_eq!(rewarded, U128(0));
+        let rewarded = contract.get_reward(accounts(3), accounts(2));
+        assert_eq!(rewarded, U128(0));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 5);
+        assert_eq!(farm_info.last_round, 5);
+
+        // move to round 7, account3 come in again
+        println!("----> move to round 7, account3 come in again");
+        deposit_seed(&mut context, &mut contract, accounts(3), 460, 10);
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.beneficiary_reward, U128(15000));
+        assert_eq!(farm_info.cur_round, 7);
+        assert_eq!(farm_info.last_round, 7);
+
+        // move to round 8, account0 come in again
+        println!("----> move to round 8, account0 come in again");
+        deposit_seed(&mut context, &mut contract, accounts(0), 520, 10);
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(5000));
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 8);
+        assert_eq!(farm_info.last_round, 8);
+
+        // move to round 9,
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(580))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(2500));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(7500));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 9);
+        assert_eq!(farm_info.last_round, 8);
+        assert_eq!(farm_info.farm_status, String::from("Running"));
+
+        // move to round 10,
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(610))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(5000));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(10000));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 10);
+        assert_eq!(farm_info.last_round, 8);
+        assert_eq!(farm_info.farm_status, String::from("Ended"));
+
+        // claim reward
+        println!("----> accounts(0) and accounts(3) claim reward");
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(710))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(5000));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(10000));
+        claim_reward(&mut context, &mut contract, accounts(0), 720);
+        claim_reward(&mut context, &mut contract, accounts(3), 730);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(740))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let rewarded = contract.get_reward(accounts(0), accounts(2));
+        assert_eq!(rewarded, U128(5000));
+        let rewarded = contract.get_reward(accounts(3), accounts(2));
+        assert_eq!(rewarded, U128(10000));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 10);
+        assert_eq!(farm_info.last_round, 10);
+
+        // clean farm
+        println!("----> clean farm");
+        remove_farm(&mut context, &mut contract, 750);
+        assert!(contract.get_farm(farm_id.clone()).is_none());
+
+        // remove user rps
+        println!("----> remove user rps");
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(760))
+            .is_view(true)
+            .build());
+        let prev_available = contract
+            .storage_balance_of(accounts(0))
+            .expect("Error")
+            .available
+            .0;
+        let ret = remove_user_rps(
+            &mut context,
+            &mut contract,
+            accounts(0).into(),
+            String::from("bob#0"),
+            770,
+        );
+        assert!(ret);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(780))
+            .is_view(true)
+            .build());
+        let post_available = contract
+            .storage_balance_of(accounts(0))
+            .expect("Error")
+            .available
+            .0;
+        assert_eq!(post_available - prev_available, 378 * 10_u128.pow(19));
+
+        // withdraw seed
+        println!("----> accounts(0) and accounts(3) withdraw seed");
+        withdraw_seed(&mut context, &mut contract, accounts(0), 800, 10);
+        withdraw_seed(&mut context, &mut contract, accounts(3), 810, 10);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(820))
+            .is_view(true)
+            .build());
+        let rewarded = contract.get_reward(accounts(0), accounts(2));
+        assert_eq!(rewarded, U128(5000));
+        let rewarded = contract.get_reward(accounts(3), accounts(2));
+        assert_eq!(rewarded, U128(10000));
+    }
+
+    #[test]
+    fn test_unclaimed_rewards() {
+        let (mut context, mut contract) = setup_contract();
+        // seed is bob, reward is charlie
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            to_yocto("1"),
+            50,
+        );
+        assert_eq!(farm_id, String::from("bob#0"));
+
+        // deposit 10, can last 10 rounds from 0 to 9
+        deposit_reward(&mut context, &mut contract, to_yocto("10"), 100);
+
+        // Farmer1 accounts(0) come in round 0
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, to_yocto("1"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed, U128(0));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 0);
+        assert_eq!(farm_info.last_round, 0);
+        assert_eq!(farm_info.claimed_reward.0, 0);
+        assert_eq!(farm_info.unclaimed_reward.0, 0);
+
+        // move to round 1,
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("1"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 1);
+        assert_eq!(farm_info.last_round, 0);
+        assert_eq!(farm_info.claimed_reward.0, to_yocto("0"));
+        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("1"));
+
+        // Farmer2 accounts(3) come in round 1
+        register_farmer(&mut context, &mut contract, accounts(3));
+        // deposit seed
+        deposit_seed(&mut context, &mut contract, accounts(3), 180, to_yocto("1"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("1"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+
+        // move to round 2,
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(210))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("1.5"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0.5"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 2);
+        assert_eq!(farm_info.last_round, 1);
+        assert_eq!(farm_info.claimed_reward.0, to_yocto("0"));
+        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("2"));
+
+        // farmer1 claim reward by farm_id at round 3
+        claim_reward(&mut context, &mut contract, accounts(0), 260);
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("1"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 3);
+        assert_eq!(farm_info.last_round, 3);
+        assert_eq!(farm_info.claimed_reward.0, to_yocto("2"));
+        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("1"));
+
+        // farmer2 claim reward by seed_id at round 4
+        claim_reward_by_seed(&mut context, &mut contract, accounts(3), 310);
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0.5"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 4);
+        assert_eq!(farm_info.last_round, 4);
+        assert_eq!(farm_info.claimed_reward.0, to_yocto("3.5"));
+        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("0.5"));
+
+        // farmer1 unstake half lpt at round 5
+        withdraw_seed(
+            &mut context,
+            &mut contract,
+            accounts(0),
+            360,
+            to_yocto("0.4"),
+        );
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0.5"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 5);
+        assert_eq!(farm_info.last_round, 5);
+        assert_eq!(farm_info.claimed_reward.0, to_yocto("4.5"));
+        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("0.5"));
+
+        // farmer2 unstake all his lpt at round 6
+        withdraw_seed(&mut context, &mut contract, accounts(3), 410, to_yocto("1"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0.375"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 6);
+        assert_eq!(farm_info.last_round, 6);
+        assert_eq!(farm_info.claimed_reward.0, to_yocto("5.625"));
+        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("0.375"));
+
+        // move to round 7
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(460))
+            .is_view(true)
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("1.374999999999999999999999"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 7);
+        assert_eq!(farm_info.last_round, 6);
+        assert_eq!(farm_info.claimed_reward.0, to_yocto("5.625"));
+        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("1.375"));
+        withdraw_seed(
+            &mut context,
+            &mut contract,
+            accounts(0),
+            470,
+            to_yocto("0.6"),
+        );
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
+        assert_eq!(unclaimed.0, to_yocto("0"));
+        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.cur_round, 7);
+        assert_eq!(farm_info.last_round, 7);
+        assert_eq!(
+            farm_info.claimed_reward.0,
+            to_yocto("6.999999999999999999999999")
+        );
+        assert_eq!(farm_info.unclaimed_reward.0, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "E11: insufficient $NEAR storage deposit")]
+    fn test_storage_withdraw() {
+        let (mut context, mut contract) = setup_contract();
+        // Farmer1 accounts(0) come in round 0
+        register_farmer(&mut context, &mut contract, accounts(0));
+        // println!("locked: {}, deposited: {}", sb.total.0, sb.available.0);
+        let sb = storage_withdraw(&mut context, &mut contract, accounts(0));
+        // println!("locked: {}, deposited: {}", sb.total.0, sb.available.0);
+        assert_eq!(sb.total.0, 2460000000000000000000);
+        assert_eq!(sb.available.0, 0);
+
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        assert_eq!(farm_id, String::from("bob#0"));
+
+        deposit_seed(&mut context, &mut contract, accounts(0), 60, 10);
+    }
+
+    #[test]
+    #[should_panic(expected = "E23: claimed reward below min_expected")]
+    fn test_claim_with_min_expected() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        // move to round 1, 5k unclaimed
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .is_view(false)
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(farm_id.clone(), Some(U128(5000)));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)), U128(5000));
+
+        // move to round 2, another 5k unclaimed, but expect more
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(210))
+            .is_view(false)
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(farm_id.clone(), Some(U128(5001)));
+    }
+
+    #[test]
+    fn test_storage_deposit_many() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+
+        let per_account = env::storage_byte_cost() * 1852;
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .is_view(false)
+            .attached_deposit(per_account * 3 + 100)
+            .build());
+        let balances = contract.storage_deposit_many(
+            vec![accounts(3), accounts(4), accounts(5)],
+            None,
+        );
+        assert_eq!(balances.len(), 3);
+        for balance in balances.iter() {
+            assert_eq!(balance.total.0, (per_account * 3 + 100) / 3);
+        }
+        assert_eq!(contract.get_metadata().farmer_count.0, 3);
+
+        deposit_seed(&mut context, &mut contract, accounts(3), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(4), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(5), 110, 20);
+        assert_eq!(contract.get_seed_info(String::from("bob")).unwrap().amount, U128(40));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .is_view(true)
+            .build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(3), farm_id.clone()), U128(1250));
+        assert_eq!(contract.get_unclaimed_reward(accounts(5), farm_id.clone()), U128(2500));
+    }
+
+    #[test]
+    #[should_panic(expected = "E11: insufficient $NEAR storage deposit")]
+    fn test_storage_deposit_many_insufficient() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 1852)
+            .build());
+        contract.storage_deposit_many(vec![accounts(3), accounts(4)], None);
+    }
+
+    #[test]
+    fn test_remove_user_rps_unregistered() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        let ret = remove_user_rps(
+            &mut context,
+            &mut contract,
+            accounts(3),
+            String::from("bob#0"),
+            100,
+        );
+        assert!(!ret);
+        assert!(contract.storage_balance_of(accounts(3)).is_none());
+    }
+
+    #[test]
+    fn test_outdated_farm_view() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 10000, 100);
+        assert!(contract.get_outdated_farm(farm_id.clone()).is_none());
+        assert!(contract.list_outdated_farms(0, 10).is_empty());
+
+        // farm ends at round 2, then clean it
+        remove_farm(&mut context, &mut contract, 300);
+        assert!(contract.get_farm(farm_id.clone()).is_none());
+        let farm_info = contract.get_outdated_farm(farm_id.clone()).expect("Error");
+        assert_eq!(farm_info.farm_status, String::from("Cleared"));
+        assert_eq!(farm_info.beneficiary_reward, U128(10000));
+        let outdated = contract.list_outdated_farms(0, 10);
+        assert_eq!(outdated.len(), 1);
+        assert_eq!(outdated[0], farm_info);
+        assert_eq!(contract.get_number_of_outdated_farms(), 1);
+    }
+
+    #[test]
+    fn test_protocol_fee() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_farm_fee(farm_id.clone(), 1000);
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().fee_bps, 1000);
+
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)), U128(4500));
+        let fees = contract.list_protocol_fees(0, 10);
+        assert_eq!(fees.get(&String::from("charlie")), Some(&U128(500)));
+        assert_eq!(contract.list_protocol_fees(0, u64::MAX), fees);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.claimed_reward, U128(5000));
+        assert_eq!(farm_info.unclaimed_reward, U128(0));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_protocol_fee(accounts(2), None);
+        assert!(contract.list_protocol_fees(0, 10).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "E45: invalid fee bps")]
+    fn test_protocol_fee_invalid() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_farm_fee(farm_id, 10001);
+    }
+
+    #[test]
+    fn test_nft_weight_ramp() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(accounts(3).to_string(), U128(100));
+        let farm_id = create_nft_farm(
+            &mut context,
+            &mut contract,
+            accounts(4),
+            accounts(2),
+            nft_balance,
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_seed_nft_ramp(accounts(4).into(), 100);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(50000), farm_id.clone());
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        assert_eq!(contract.get_user_nft_weight(accounts(0), accounts(4).into()), U128(0));
+        assert_eq!(contract.get_seed_info(accounts(4).into()).unwrap().amount, U128(0));
+
+        // mid ramp
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.get_user_nft_weight(accounts(0), accounts(4).into()), U128(50));
+        contract.claim_reward_by_seed(accounts(4).into());
+        assert_eq!(contract.get_seed_info(accounts(4).into()).unwrap().amount, U128(50));
+        assert_eq!(
+            contract.list_user_seeds(accounts(0)).get(&String::from("eugene")),
+            Some(&U128(50))
+        );
+
+        // full weight
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(260))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.get_user_nft_weight(accounts(0), accounts(4).into()), U128(100));
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()), U128(10000));
+        contract.claim_reward_by_seed(accounts(4).into());
+        assert_eq!(contract.get_seed_info(accounts(4).into()).unwrap().amount, U128(100));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)), U128(10000));
+
+        // withdraw takes back the applied weight
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(270))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_nft(accounts(4).into(), accounts(3).into(), "1".to_string(), None);
+        assert_eq!(contract.get_seed_info(accounts(4).into()).unwrap().amount, U128(0));
+        assert!(contract.list_user_seeds(accounts(0)).is_empty());
+    }
+
+    #[test]
+    fn test_clawback_undistributed() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+
+        // still in round 0, nobody staked
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        let amount = contract.clawback_undistributed(farm_id.clone());
+        assert_eq!(amount, U128(50000));
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.farm_status, String::from("Created"));
+        assert_eq!(farm_info.total_reward, U128(0));
+        assert_eq!(farm_info.start_at, 0);
+        assert_eq!(contract.list_rewards_info(0, 10).get("charlie"), Some(&U128(0)));
+
+        // fund again, farming restarts from the new deposit
+        deposit_reward(&mut context, &mut contract, 10000, 300);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.farm_status, String::from("Running"));
+        assert_eq!(farm_info.start_at, 300);
+        assert_eq!(farm_info.total_reward, U128(10000));
+    }
+
+    #[test]
+    #[should_panic(expected = "E47: farm has started distribution")]
+    fn test_clawback_after_distribution() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.clawback_undistributed(farm_id);
+    }
+
+    #[test]
+    #[should_panic(expected = "E46: only farm creator can do this")]
+    fn test_clawback_not_creator() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(110))
+            .attached_deposit(1)
+            .build());
+        contract.clawback_undistributed(farm_id);
+    }
+
+    #[test]
+    fn test_withdraw_with_memo() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_reward(accounts(2), None, Some("reward-memo".to_string()));
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("ft_transfer"));
+        assert!(receipts.contains("reward-memo"));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(180))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_seed(accounts(1).into(), U128(10), Some("seed-memo".to_string()), None);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("seed-memo"));
+    }
+
+    #[test]
+    fn test_token_solvency() {
+        let (mut context, mut contract) = setup_contract();
+        // seed and reward are the same token
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(1),
+            5000,
+            50,
+        );
+        let assert_solvent = |contract: &Contract| {
+            let solvency = contract.get_token_solvency(accounts(1));
+            assert!(solvency.deposited.0 >= solvency.owed.0);
+            solvency
+        };
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(50000), String::from("bob#0"));
+        let solvency = assert_solvent(&contract);
+        assert_eq!(solvency.deposited.0, 50000);
+        assert_eq!(solvency.owed.0, 50000);
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        let solvency = assert_solvent(&contract);
+        assert_eq!(solvency.deposited.0, 50010);
+
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        assert_solvent(&contract);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_reward(accounts(1), None, None);
+        let solvency = assert_solvent(&contract);
+        assert_eq!(solvency.deposited.0, 50010 - 5000);
+
+        withdraw_seed(&mut context, &mut contract, accounts(0), 180, 10);
+        let solvency = assert_solvent(&contract);
+        assert_eq!(solvency.deposited.0, 50000 - 5000);
+
+        // distribute all the rest to beneficiary and clean the farm
+        remove_farm(&mut context, &mut contract, 100 + 50 * 11);
+        let solvency = assert_solvent(&contract);
+        assert_eq!(solvency.owed.0, 45000);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_beneficiary_reward(String::from("bob#0"));
+        let solvency = assert_solvent(&contract);
+        assert_eq!(solvency.owed.0, 0);
+    }
+
+    #[test]
+    fn test_claim_delegate() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        contract.set_claim_delegate(Some(accounts(3)));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(160))
+            .build());
+        contract.claim_reward_by_farm_for(accounts(0), String::from("bob#0"));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_reward_for(accounts(0), accounts(2), None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\\\"receiver_id\\\":\\\"{}\\\"", accounts(0))));
+    }
+
+    #[test]
+    #[should_panic(expected = "E16: caller is not the claim delegate")]
+    fn test_claim_delegate_unauthorized() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        contract.set_claim_delegate(Some(accounts(3)));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .block_timestamp(to_nano(160))
+            .build());
+        contract.claim_reward_by_farm_for(accounts(0), String::from("bob#0"));
+    }
+
+    #[test]
+    fn test_rps_delta() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        // round 1: 5000 reward over 10 seeds
+        testing_env!(context.block_timestamp(to_nano(160)).build());
+        let first = contract.get_rps_delta(farm_id.clone(), U128(0)).0;
+        assert_eq!(first, 500 * farm::DENOM);
+
+        // round 2
+        testing_env!(context.block_timestamp(to_nano(210)).build());
+        let second = contract.get_rps_delta(farm_id.clone(), U128(first)).0;
+        assert_eq!(second, 500 * farm::DENOM);
+        assert_eq!(second * 10 / farm::DENOM, 5000);
+
+        assert_eq!(contract.get_rps_delta(farm_id.clone(), U128(u128::MAX)).0, 0);
+        assert_eq!(contract.get_rps_delta("bob#9".to_string(), U128(0)).0, 0);
+    }
+
+    #[test]
+    fn test_farm_state() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 5000, 100);
+        match contract.get_farm_state(farm_id.clone()) {
+            view::FarmLookup::Active(info) => assert_eq!(info.farm_id, farm_id),
+            _ => panic!("farm should be active"),
+        }
+
+        remove_farm(&mut context, &mut contract, 200);
+        match contract.get_farm_state(farm_id.clone()) {
+            view::FarmLookup::Outdated(info) => assert_eq!(info.farm_status, "Cleared"),
+            _ => panic!("farm should be outdated"),
+        }
+
+        assert_eq!(
+            contract.get_farm_state("bob#9".to_string()),
+            view::FarmLookup::NotFound
+        );
+    }
+
+    #[test]
+    fn test_farm_by_block_height() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        let farm_id = contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 0,
+                reward_per_session: U128(5000),
+                session_interval: 10,
+                by_block_height: true,
+                accrual_granularity: 0,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        );
+
+        // timestamp never moves, only block height does
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(100))
+            .block_index(1000)
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(50000), farm_id.clone());
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert!(farm_info.by_block_height);
+        assert_eq!(farm_info.start_at, 1000);
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_index(1005)
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(10), String::from(""));
+
+        testing_env!(context.block_index(1009).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 0);
+
+        testing_env!(context.block_index(1010).build());
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.cur_round, 1);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 5000);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_index(1025)
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(farm_id.clone(), None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 10000);
+    }
+
+    #[test]
+    fn test_create_farm_storage_cost() {
+        let (mut context, mut contract) = setup_contract();
+        // actual storage fee taken by create_simple_farm
+        let create = |context: &mut VMContextBuilder,
+                      contract: &mut Contract,
+                      seed_id: ValidAccountId,
+                      nft_balance: Option<HashMap<NFTTokenId, U128>>| {
+            testing_env!(context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(env::storage_byte_cost() * 1000)
+                .build());
+            let prev_usage = env::storage_usage();
+            contract.create_simple_farm(
+                HRFarmTerms {
+                    seed_id: seed_id.into(),
+                    reward_token: accounts(2),
+                    start_at: 0,
+                    reward_per_session: U128(5000),
+                    session_interval: 50,
+                    by_block_height: false,
+                    accrual_granularity: 0,
+                    decay_factor_bps: 0,
+                    decay_period_rounds: 0,
+                    end_at: 0,
+                },
+                Some(U128(10)),
+                nft_balance,
+                None,
+            );
+            (env::storage_usage() - prev_usage) as u128 * env::storage_byte_cost()
+        };
+
+        let actual = create(&mut context, &mut contract, accounts(1), None);
+        assert!(contract.get_create_farm_storage_cost(false, false).0 >= actual);
+
+        let actual = create(&mut context, &mut contract, accounts(1), None);
+        assert!(contract.get_create_farm_storage_cost(true, false).0 >= actual);
+        assert!(
+            contract.get_create_farm_storage_cost(true, false).0
+                < contract.get_create_farm_storage_cost(false, false).0
+        );
+
+        let actual = create(&mut context, &mut contract, accounts(4), Some(HashMap::new()));
+        assert!(contract.get_create_farm_storage_cost(false, true).0 >= actual);
+    }
+
+    #[test]
+    fn test_forfeit_reward() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        // reward token contract is dead, auto withdraw of reward fails
+        callback_env(&mut context, 161, PromiseResult::Failed);
+        contract.callback_post_withdraw_reward(accounts(2).into(), accounts(0).into(), U128(5000));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.forfeit_reward(accounts(2)).0, 5000);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        assert_eq!(
+            contract.list_protocol_fees(0, 10).get(&accounts(2).to_string()),
+            Some(&U128(5000))
+        );
+        let solvency = contract.get_token_solvency(accounts(2));
+        assert!(solvency.deposited.0 >= solvency.owed.0);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(180))
+            .attached_deposit(1)
+            .build());
+        assert!(contract.storage_unregister(None));
+        assert!(contract.get_farmer_wrapped(accounts(0).as_ref()).is_none());
+    }
+
+    #[test]
+    fn test_total_seed_deposited() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(0), 120, 20);
+        let seed_info = contract.get_seed_info(accounts(1).into()).unwrap();
+        assert_eq!(seed_info.amount.0, 30);
+        assert_eq!(seed_info.total_seed_deposited.0, 30);
+
+        withdraw_seed(&mut context, &mut contract, accounts(0), 130, 25);
+        let seed_info = contract.get_seed_info(accounts(1).into()).unwrap();
+        assert_eq!(seed_info.amount.0, 5);
+        assert_eq!(seed_info.total_seed_deposited.0, 30);
+
+        deposit_seed(&mut context, &mut contract, accounts(0), 140, 10);
+        let seed_info = contract.get_seed_info(accounts(1).into()).unwrap();
+        assert_eq!(seed_info.amount.0, 15);
+        assert_eq!(seed_info.total_seed_deposited.0, 40);
+    }
+
+    #[test]
+    fn test_top_up_running_farm() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 12000, 100);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.start_at, 100);
+        assert_eq!(contract.get_farm_rounds_remaining(farm_id.clone()), 3);
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        // one round distributed, then top up
+        deposit_reward(&mut context, &mut contract, 8000, 160);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.farm_status, "Running");
+        assert_eq!(farm_info.start_at, 100);
+        assert_eq!(farm_info.total_reward.0, 20000);
+        assert_eq!(contract.get_farm_rounds_remaining(farm_id.clone()), 3);
+
+        deposit_reward(&mut context, &mut contract, 5000, 260);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.start_at, 100);
+        assert_eq!(farm_info.cur_round, 3);
+        assert_eq!(contract.get_farm_rounds_remaining(farm_id.clone()), 2);
+        assert_eq!(contract.get_farm_rounds_remaining("bob#9".to_string()), 0);
+    }
+
+    #[test]
+    fn test_sweep_dust() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_farm_sweep_dust(farm_id.clone(), true);
+        deposit_reward(&mut context, &mut contract, 10000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(3), 110, 20);
+
+        // farm ended, each round leaves 1 as dust
+        withdraw_seed(&mut context, &mut contract, accounts(0), 260, 10);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.farm_status, "Ended");
+        assert_eq!(farm_info.unclaimed_reward.0, 6667);
+        // the view shows the dust swept too
+        assert_eq!(contract.get_unclaimed_reward(accounts(3), farm_id.clone()).0, 6667);
+
+        claim_reward(&mut context, &mut contract, accounts(3), 270);
+        assert_eq!(contract.get_reward(accounts(3), accounts(2)).0, 6667);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.unclaimed_reward.0, 0);
+        assert_eq!(farm_info.claimed_reward.0, 10000);
+    }
+
+    #[test]
+    fn test_opt_out_farm() {
+        let (mut context, mut contract) = setup_contract();
+        let farm0 = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        let farm1 = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(3),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(50000), farm1.clone());
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(4));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(4), 110, 10);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        contract.opt_out_farm(farm1.clone());
+        assert_eq!(contract.get_farm(farm1.clone()).unwrap().farm_id, farm1);
+
+        // farm0 is shared by both, farm1 goes to eugene's stake only
+        testing_env!(context.block_timestamp(to_nano(160)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm0.clone()).0, 2500);
+        assert_eq!(contract.get_unclaimed_reward(accounts(4), farm0.clone()).0, 2500);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm1.clone()).0, 0);
+        assert_eq!(contract.get_unclaimed_reward(accounts(4), farm1.clone()).0, 5000);
+
+        // seed change of an opted out farmer is not counted in farm1
+        deposit_seed(&mut context, &mut contract, accounts(0), 170, 10);
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .block_timestamp(to_nano(210))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_seed(accounts(1).into());
+        assert_eq!(contract.get_reward(accounts(4), accounts(3)).0, 10000);
+        assert_eq!(contract.get_reward(accounts(4), accounts(2)).0, 2500 + 5000 / 3);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(220))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_seed(accounts(1).into());
+        assert_eq!(contract.get_reward(accounts(0), accounts(3)).0, 0);
+        contract.opt_in_farm(farm1.clone());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm1.clone()).0, 0);
+
+        // both farm on farm1 again, alice with 20 and eugene with 10
+        testing_env!(context.block_timestamp(to_nano(260)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm1.clone()).0, 3333);
+        assert_eq!(contract.get_unclaimed_reward(accounts(4), farm1.clone()).0, 1666);
+    }
+
+    #[test]
+    fn test_unclaimed_rewards_batch() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context.block_timestamp(to_nano(160)).build());
+        let rewards = contract.get_unclaimed_rewards(
+            accounts(0),
+            vec![
+                "bob#9".to_string(),
+                farm_id.clone(),
+                "unknown".to_string(),
+                farm_id.clone(),
+            ],
+        );
+        assert_eq!(rewards, vec![U128(0), U128(5000), U128(0), U128(5000)]);
+    }
+
+    #[test]
+    fn test_nft_deposit_replay() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        // the nft contract retries the same transfer
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 111);
+
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 10);
+        assert_eq!(
+            contract.list_user_seeds(accounts(0)).get(&String::from("eugene")),
+            Some(&U128(10))
+        );
+    }
+
+    #[test]
+    fn test_farm_reward_breakdown() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        // nothing staked in round #1, it goes to beneficiary
+        testing_env!(context.block_timestamp(to_nano(155)).build());
+        let breakdown = contract.get_farm_reward_breakdown(farm_id.clone()).unwrap();
+        assert_eq!(breakdown.amount_of_beneficiary.0, 5000);
+        assert_eq!(breakdown.undistributed.0, 45000);
+
+        deposit_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 210);
+        testing_env!(context.block_timestamp(to_nano(230)).build());
+        let breakdown = contract.get_farm_reward_breakdown(farm_id.clone()).unwrap();
+        assert_eq!(breakdown.amount_of_reward.0, 50000);
+        assert_eq!(breakdown.amount_of_beneficiary.0, 5000);
+        assert_eq!(breakdown.amount_of_claimed.0, 10000);
+        assert_eq!(breakdown.unclaimed.0, 0);
+        assert_eq!(breakdown.undistributed.0, 40000);
+        assert!(contract.get_farm_reward_breakdown("bob#9".to_string()).is_none());
+    }
+
+    #[test]
+    fn test_freeze_all_farms() {
+        let (mut context, mut contract) = setup_contract();
+        let farm0 = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        let farm1 = create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(3),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(50000), farm1.clone());
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(175))
+            .attached_deposit(0)
+            .build());
+        assert_eq!(contract.freeze_all_farms(0, 10), 2);
+        assert_eq!(contract.get_farm(farm0.clone()).unwrap().farm_status, "Paused");
+
+        // no accrual while frozen
+        testing_env!(context.block_timestamp(to_nano(400)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm0.clone()).0, 5000);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm1.clone()).0, 5000);
+        claim_reward(&mut context, &mut contract, accounts(0), 400);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+
+        // resume from the middle of round #2
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(500))
+            .build());
+        assert_eq!(contract.unfreeze_all_farms(0, 10), 2);
+        testing_env!(context.block_timestamp(to_nano(524)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm1.clone()).0, 5000);
+        testing_env!(context.block_timestamp(to_nano(525)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm1.clone()).0, 10000);
+        assert_eq!(contract.get_farm(farm0.clone()).unwrap().farm_status, "Running");
+    }
+
+    #[test]
+    fn test_nft_weight_scale() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(2));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_seed_weight_scale("eugene".to_string(), 18);
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().weight_scale, 18);
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        assert_eq!(
+            contract.get_seed_info("eugene".to_string()).unwrap().amount.0,
+            2 * 10u128.pow(18)
+        );
+        assert_eq!(
+            contract.list_user_seeds(accounts(0)).get(&String::from("eugene")),
+            Some(&U128(2 * 10u128.pow(18)))
+        );
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_nft("eugene".to_string(), accounts(3).into(), "1".to_string(), None);
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 0);
+        assert!(!contract.list_user_seeds(accounts(0)).contains_key("eugene"));
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_claim_without_one_yocto() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(0)
+            .build());
+        contract.claim_reward_by_farm(String::from("bob#0"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "Requires attached deposit of exactly 1 yoctoNEAR")]
+    fn test_claim_by_seed_without_one_yocto() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(
+            &mut context,
+            &mut contract,
+            accounts(1),
+            accounts(2),
+            5000,
+            50,
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(0)
+            .build());
+        contract.claim_reward_by_seed(String::from("bob"));
+    }
+
+    #[test]
+    fn test_seeds_by_reward_token() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        create_farm(&mut context, &mut contract, accounts(1), accounts(3), 5000, 50);
+        create_farm(&mut context, &mut contract, accounts(4), accounts(3), 5000, 50);
+        create_farm(&mut context, &mut contract, accounts(5), accounts(2), 5000, 50);
+        create_farm(&mut context, &mut contract, accounts(5), accounts(2), 5000, 50);
+
+        let seeds = contract.get_seeds_by_reward_token(accounts(2), 0, 10);
+        assert_eq!(seeds, vec![accounts(1).to_string(), accounts(5).to_string()]);
+        let seeds = contract.get_seeds_by_reward_token(accounts(3), 0, 10);
+        assert_eq!(seeds, vec![accounts(1).to_string(), accounts(4).to_string()]);
+        let seeds = contract.get_seeds_by_reward_token(accounts(2), 1, 2);
+        assert!(seeds.is_empty());
+        assert!(contract.get_seeds_by_reward_token(accounts(0), 0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_create_farm_on_existing_seed_refund() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+
+        let attached = env::storage_byte_cost() * 800;
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(attached)
+            .build());
+        let prev_usage = env::storage_usage();
+        contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(3),
+                start_at: 0,
+                reward_per_session: U128(5000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        );
+        let cost = (env::storage_usage() - prev_usage) as u128 * env::storage_byte_cost();
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\"deposit\":{}", attached - cost)));
+    }
+
+    #[test]
+    fn test_on_claim_receiver_notified() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        contract.set_on_claim_receiver(Some(accounts(5)));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(String::from("bob#0"), None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\"receiver_id\":\"{}\"", accounts(5))));
+        assert!(receipts.contains("on_reward_claimed"));
+        assert!(!receipts.contains("Transfer"));
+
+        // claims inside seed deposits and withdraws notify too
+        deposit_seed(&mut context, &mut contract, accounts(0), 210, 10);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("on_reward_claimed"));
+        withdraw_seed(&mut context, &mut contract, accounts(0), 260, 20);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("on_reward_claimed"));
+    }
+
+    #[test]
+    fn test_list_farms_ending_before() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        create_farm(&mut context, &mut contract, accounts(1), accounts(3), 5000, 50);
+        create_farm(&mut context, &mut contract, accounts(1), accounts(4), 5000, 50);
+        // bob#0 ends at 100 + 10 * 50 = 600
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        // bob#1 ends at 100 + 3 * 50 = 250, including a tail round
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(12000), String::from("bob#1"));
+        // bob#2 got no reward, stays Created
+
+        testing_env!(context.block_timestamp(to_nano(200)).is_view(true).build());
+        assert_eq!(contract.list_farms_ending_before(200, 0, 10).len(), 0);
+        assert_eq!(
+            contract.list_farms_ending_before(300, 0, 10),
+            vec![String::from("bob#1")]
+        );
+        assert_eq!(contract.list_farms_ending_before(700, 0, 10).len(), 2);
+        assert_eq!(
+            contract.list_farms_ending_before(700, 1, 10),
+            vec![String::from("bob#1")]
+        );
+    }
+
+    #[test]
+    fn test_callback_post_withdraw_reward_outcomes() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        // auto withdraw of 5000 reward
+        withdraw_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+
+        // plain ft_transfer succeed, nothing comes back
+        callback_env(&mut context, 161, PromiseResult::Successful(vec![]));
+        contract.callback_post_withdraw_reward(accounts(2).into(), accounts(0).into(), U128(5000));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+
+        // transfer call used 2000 only, 3000 credited back
+        callback_env(&mut context, 162, PromiseResult::Successful(b"\"2000\"".to_vec()));
+        contract.callback_post_withdraw_reward(accounts(2).into(), accounts(0).into(), U128(5000));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 3000);
+
+        // failed, all credited back
+        callback_env(&mut context, 163, PromiseResult::Failed);
+        contract.callback_post_withdraw_reward(accounts(2).into(), accounts(0).into(), U128(5000));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 8000);
+    }
+
+    fn recover_foreign_ft(
+        context: &mut VMContextBuilder,
+        contract: &mut Contract,
+        real_balance: Balance,
+        amount: Balance,
+    ) {
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(200))
+            .attached_deposit(1)
+            .build());
+        contract.recover_foreign_ft(accounts(2), U128(amount), accounts(5));
+        let balance = near_sdk::serde_json::to_vec(&U128(real_balance)).unwrap();
+        callback_env(context, 201, PromiseResult::Successful(balance));
+        contract.callback_recover_foreign_ft(accounts(2).into(), U128(amount), accounts(5).into());
+    }
+
+    #[test]
+    fn test_recover_foreign_ft() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        // 3000 stray tokens sent by plain ft_transfer
+        recover_foreign_ft(&mut context, &mut contract, 53000, 3000);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("ft_transfer"));
+        assert!(receipts.contains(&format!("\"receiver_id\":\"{}\"", accounts(2))));
+        let solvency = contract.get_token_solvency(accounts(2));
+        assert_eq!(solvency.deposited.0, 50000);
+        assert_eq!(solvency.owed.0, 50000);
+    }
+
+    #[test]
+    #[should_panic(expected = "E52: not enough tokens beyond liabilities")]
+    fn test_recover_foreign_ft_touch_liabilities() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        recover_foreign_ft(&mut context, &mut contract, 53000, 3001);
+    }
+
+    #[test]
+    fn test_pause_seed() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.pause_seed(accounts(1).into());
+        assert!(contract.get_seed_info(accounts(1).into()).unwrap().paused);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        let ret = contract.ft_on_transfer(accounts(0), U128(10), String::from(""));
+        match ret {
+            near_sdk::PromiseOrValue::Value(refund) => assert_eq!(refund.0, 10),
+            _ => panic!("expected refund"),
+        }
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 10);
+
+        // claiming still works
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(String::from("bob#0"), None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.resume_seed(accounts(1).into());
+        deposit_seed(&mut context, &mut contract, accounts(0), 170, 10);
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 20);
+    }
+
+    #[test]
+    fn test_touch_farm() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        // nobody interacts for 4 rounds
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(310))
+            .build());
+        let info = contract.touch_farm(String::from("bob#0"));
+        assert_eq!(info.last_round, 4);
+        assert_eq!(info.cur_round, 4);
+        assert_eq!(info.unclaimed_reward.0, 20000);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), String::from("bob#0")).0, 20000);
+    }
+
+    #[test]
+    fn test_native_near_reward() {
+        let (mut context, mut contract) = setup_contract();
+        let near: ValidAccountId = NEAR_REWARD_TOKEN.try_into().unwrap();
+        create_farm(&mut context, &mut contract, accounts(1), near.clone(), 5000, 50);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        let storage_before = contract.storage_balance_of(accounts(0)).unwrap().total.0;
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(50000)
+            .build());
+        contract.fund_farm_near(String::from("bob#0"));
+        assert_eq!(contract.get_farm(String::from("bob#0")).unwrap().total_reward.0, 50000);
+
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(String::from("bob#0"), None);
+        assert_eq!(contract.get_reward(accounts(0), near.clone()).0, 5000);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_reward(near.clone(), None, None);
+        assert_eq!(contract.get_reward(accounts(0), near.clone()).0, 0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("{\"Transfer\":{\"deposit\":5000}}"));
+        assert!(!receipts.contains("ft_transfer"));
+        // storage deposit of the farmer is untouched
+        assert_eq!(contract.storage_balance_of(accounts(0)).unwrap().total.0, storage_before);
+    }
+
+    #[test]
+    #[should_panic(expected = "E11: insufficient $NEAR storage deposit")]
+    fn test_native_near_reward_apart_from_storage() {
+        let (mut context, mut contract) = setup_contract();
+        let near: ValidAccountId = NEAR_REWARD_TOKEN.try_into().unwrap();
+        create_farm(&mut context, &mut contract, accounts(1), near, 5000, 50);
+        register_farmer(&mut context, &mut contract, accounts(3));
+        let storage_before = contract.storage_balance_of(accounts(3)).unwrap();
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(50000)
+            .build());
+        contract.fund_farm_near(String::from("bob#0"));
+        assert_eq!(contract.storage_balance_of(accounts(3)).unwrap().total.0, storage_before.total.0);
+
+        // funder takes back only its own storage deposit
+        let storage_after = storage_withdraw(&mut context, &mut contract, accounts(3));
+        assert_eq!(storage_after.available.0, 0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("{{\"Transfer\":{{\"deposit\":{}}}}}", storage_before.available.0)));
+        assert_eq!(contract.get_farm(String::from("bob#0")).unwrap().total_reward.0, 50000);
+        assert_eq!(contract.list_rewards_info(0, 10).get(NEAR_REWARD_TOKEN), Some(&U128(50000)));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        contract.storage_withdraw(Some(U128(1)));
+    }
+
+    #[test]
+    fn test_min_claim_amount() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_farm_min_claim_amount(String::from("bob#0"), U128(8000));
+        assert_eq!(
+            contract.get_farm(String::from("bob#0")).unwrap().min_claim_amount.0,
+            8000
+        );
+
+        // 5000 is below the minimum, nothing claimed
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(String::from("bob#0"), None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), String::from("bob#0")).0, 5000);
+
+        // accumulated 10000 exceeds the minimum
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(210))
+            .attached_deposit(1)
+            .build());
+        contract.claim_reward_by_farm(String::from("bob#0"), None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 10000);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), String::from("bob#0")).0, 0);
+    }
+
+    #[test]
+    fn test_get_nft_balance() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        nft_balance.insert(format!("{}@2", accounts(3)), U128(20));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance.clone());
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+
+        assert_eq!(contract.get_nft_balance(accounts(4).into()), Some(nft_balance));
+        assert_eq!(contract.get_nft_balance(accounts(1).into()), None);
+    }
+
+    #[test]
+    fn test_mismatched_reward_token_refunded() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+
+        // accounts(3) is not the reward token of bob#0
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        match contract.ft_on_transfer(accounts(0), U128(50000), String::from("bob#0")) {
+            near_sdk::PromiseOrValue::Value(refund) => assert_eq!(refund.0, 50000),
+            _ => panic!("expected refund"),
+        }
+        let farm = contract.get_farm(String::from("bob#0")).unwrap();
+        assert_eq!(farm.total_reward.0, 0);
+        assert_eq!(farm.farm_status, "Created");
+    }
+
+    #[test]
+    fn test_get_farmer_positions_ranked() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        create_farm(&mut context, &mut contract, accounts(1), accounts(3), 9000, 50);
+        create_farm(&mut context, &mut contract, accounts(1), accounts(4), 1000, 50);
+        for (token, farm_id) in [(accounts(2), "bob#0"), (accounts(3), "bob#1"), (accounts(4), "bob#2")] {
+            testing_env!(context
+                .predecessor_account_id(token)
+                .block_timestamp(to_nano(100))
+                .attached_deposit(1)
+                .build());
+            contract.ft_on_transfer(accounts(0), U128(50000), String::from(farm_id));
+        }
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context.block_timestamp(to_nano(160)).is_view(true).build());
+        assert_eq!(
+            contract.get_farmer_positions_ranked(accounts(0)),
+            vec![
+                (String::from("bob#1"), U128(9000)),
+                (String::from("bob#0"), U128(5000)),
+                (String::from("bob#2"), U128(1000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_farm_funders() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 30000, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(3), U128(20000), String::from("bob#0"));
+        deposit_reward(&mut context, &mut contract, 10000, 130);
+
+        let funders = contract.get_farm_funders(String::from("bob#0"));
+        assert_eq!(funders.len(), 2);
+        assert_eq!(funders.get(&accounts(0).to_string()), Some(&U128(40000)));
+        assert_eq!(funders.get(&accounts(3).to_string()), Some(&U128(20000)));
+    }
+
+    #[test]
+    fn test_withdraw_all_seed_ft() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(0), 120, 15);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_all_seed(accounts(1).into());
+        assert!(contract.list_user_seeds(accounts(0)).is_empty());
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("callback_post_withdraw_ft_seed"));
+        // reward claimed first and auto withdrawn
+        assert!(receipts.contains("callback_post_withdraw_reward"));
+    }
+
+    #[test]
+    fn test_withdraw_all_seed_nft() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        nft_balance.insert(format!("{}@2", accounts(3)), U128(20));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "2", "eugene", 111);
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 30);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .signer_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_all_seed("eugene".to_string());
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 0);
+        assert!(contract.list_user_seeds(accounts(0)).is_empty());
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert_eq!(receipts.matches("callback_post_withdraw_nft").count(), 2);
+    }
+
+    #[test]
+    fn test_withdraw_failed_events() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 160, 10);
+
+        callback_env(&mut context, 161, PromiseResult::Failed);
+        contract.callback_post_withdraw_reward(accounts(2).into(), accounts(0).into(), U128(5000));
+        let logs = near_sdk::test_utils::get_logs();
+        let event = logs.iter().find(|log| log.starts_with("EVENT_JSON:")).unwrap();
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(&event["EVENT_JSON:".len()..]).unwrap();
+        assert_eq!(event["event"], "withdraw_failed");
+        assert_eq!(event["data"][0]["reason"], "ft_transfer_failed");
+        assert_eq!(event["data"][0]["token_id"], accounts(2).to_string());
+        assert_eq!(event["data"][0]["amount"], "5000");
+
+        callback_env(&mut context, 162, PromiseResult::Failed);
+        contract.callback_post_withdraw_ft_seed(accounts(1).into(), accounts(0).into(), U128(10));
+        let logs = near_sdk::test_utils::get_logs();
+        let event = logs.iter().find(|log| log.starts_with("EVENT_JSON:")).unwrap();
+        assert!(event.contains("\"reason\":\"ft_transfer_failed\""));
+        assert!(event.contains(&format!("\"seed_id\":\"{}\"", accounts(1))));
+    }
+
+    #[test]
+    fn test_withdraw_nft_failed_event() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_nft("eugene".to_string(), accounts(3).into(), "1".to_string(), None);
+
+        callback_env(&mut context, 161, PromiseResult::Failed);
+        contract.callback_post_withdraw_nft(
+            "eugene".to_string(),
+            accounts(0).into(),
+            accounts(3).into(),
+            "1".to_string(),
+            Some(U128(10)),
+        );
+        let logs = near_sdk::test_utils::get_logs();
+        let event = logs.iter().find(|log| log.starts_with("EVENT_JSON:")).unwrap();
+        assert!(event.contains("\"reason\":\"nft_transfer_failed\""));
+        assert!(event.contains("\"nft_token_id\":\"1\""));
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 10);
+    }
+
+    #[test]
+    fn test_claim_prunes_stale_rps() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 10000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 300);
+        assert_ne!(contract.get_user_rps(accounts(0), farm_id.clone()), "0");
+        remove_farm(&mut context, &mut contract, 310);
+        assert!(contract.get_farm(farm_id.clone()).is_none());
+
+        testing_env!(context.is_view(true).build());
+        let prev_available = contract.storage_balance_of(accounts(0)).unwrap().available.0;
+        claim_reward_by_seed(&mut context, &mut contract, accounts(0), 320);
+        assert_eq!(contract.get_user_rps(accounts(0), farm_id.clone()), "0");
+        testing_env!(context.is_view(true).build());
+        let post_available = contract.storage_balance_of(accounts(0)).unwrap().available.0;
+        assert!(post_available > prev_available);
+    }
+
+    #[test]
+    fn test_get_seed_type() {
+        let (mut context, mut contract) = setup_contract();
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), HashMap::new());
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        assert_eq!(contract.get_seed_type(accounts(4).into()), "NFT");
+        assert_eq!(contract.get_seed_type(accounts(1).into()), "FT");
+    }
+
+    #[test]
+    #[should_panic(expected = "E37: not a FT seed, use withdraw_nft")]
+    fn test_withdraw_seed_on_nft_seed() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_seed("eugene".to_string(), U128(10), None, None);
+    }
+
+    #[test]
+    #[should_panic(expected = "E38: not a NFT seed, use withdraw_seed")]
+    fn test_withdraw_nft_on_ft_seed() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_nft(accounts(1).into(), accounts(3).into(), "1".to_string(), None);
+    }
+
+    #[test]
+    fn test_accrual_granularity() {
+        let (mut context, mut contract) = setup_contract();
+        // 24000 a day, one accrues per day, the other per hour
+        for (reward, accrual_granularity) in [(accounts(2), 0), (accounts(3), 3600)] {
+            testing_env!(context
+                .predecessor_account_id(accounts(0))
+                .attached_deposit(env::storage_byte_cost() * 800)
+                .build());
+            contract.create_simple_farm(
+                HRFarmTerms {
+                    seed_id: accounts(1).into(),
+                    reward_token: reward.clone(),
+                    start_at: 100,
+                    reward_per_session: U128(24000),
+                    session_interval: 86400,
+                    by_block_height: false,
+                    accrual_granularity,
+                    decay_factor_bps: 0,
+                    decay_period_rounds: 0,
+                    end_at: 0,
+                },
+                Some(U128(10)),
+                None,
+                None,
+            );
+        }
+        for (reward, farm_id) in [(accounts(2), "bob#0"), (accounts(3), "bob#1")] {
+            testing_env!(context
+                .predecessor_account_id(reward)
+                .block_timestamp(to_nano(50))
+                .attached_deposit(1)
+                .build());
+            contract.ft_on_transfer(accounts(0), U128(48000), String::from(farm_id));
+        }
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 60, 10);
+
+        let unclaimed = |context: &mut VMContextBuilder, contract: &Contract, ts: u32| {
+            testing_env!(context.block_timestamp(to_nano(ts)).is_view(true).build());
+            contract.get_unclaimed_rewards(
+                accounts(0),
+                vec![String::from("bob#0"), String::from("bob#1")],
+            )
+        };
+        // 5.5 hours in
+        assert_eq!(unclaimed(&mut context, &contract, 100 + 3600 * 5 + 1800), vec![U128(0), U128(5000)]);
+        // half a day in
+        assert_eq!(unclaimed(&mut context, &contract, 100 + 43200), vec![U128(0), U128(12000)]);
+        // a full day in, both the same
+        assert_eq!(unclaimed(&mut context, &contract, 100 + 86400), vec![U128(24000), U128(24000)]);
+        assert_eq!(contract.get_farm(String::from("bob#1")).unwrap().cur_round, 24);
+    }
+
+    #[test]
+    #[should_panic(expected = "E48: invalid accrual granularity")]
+    fn test_accrual_granularity_not_dividing_session() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 100,
+                reward_per_session: U128(24000),
+                session_interval: 86400,
+                by_block_height: false,
+                accrual_granularity: 7000,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_outstanding_liabilities() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(3), 160, 10);
+        let liabilities = |contract: &Contract| {
+            contract.get_outstanding_liabilities().get(&accounts(2).to_string()).unwrap().0
+        };
+        // round 1 distributed when accounts(3) deposited
+        assert_eq!(liabilities(&contract), 5000);
+
+        // accounts(0) claims round 1 and 2, 7500 goes to its balance
+        claim_reward(&mut context, &mut contract, accounts(0), 210);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 7500);
+        assert_eq!(liabilities(&contract), 10000);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(220))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_reward(accounts(2), None, None);
+        assert_eq!(liabilities(&contract), 2500);
+    }
+
+    #[test]
+    fn test_nft_tiers() {
+        let (mut context, mut contract) = setup_contract();
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), HashMap::new());
+        let mut tiers = HashMap::new();
+        tiers.insert(
+            "gold".to_string(),
+            NftTier { prefixes: vec![format!("{}@1", accounts(3))], weight: U128(100) },
+        );
+        tiers.insert(
+            "common".to_string(),
+            NftTier { prefixes: vec![accounts(3).to_string()], weight: U128(10) },
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_seed_nft_tiers("eugene".to_string(), tiers);
+        assert_eq!(contract.get_seed_nft_tiers("eugene".to_string()).len(), 2);
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "2", "eugene", 111);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "3", "eugene", 112);
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 120);
+        let counts = contract.get_nft_tier_counts("eugene".to_string());
+        assert_eq!(counts.get("gold"), Some(&1));
+        assert_eq!(counts.get("common"), Some(&2));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_nft("eugene".to_string(), accounts(3).into(), "1".to_string(), None);
+        let counts = contract.get_nft_tier_counts("eugene".to_string());
+        assert_eq!(counts.get("gold"), None);
+        assert_eq!(counts.get("common"), Some(&2));
+    }
+
+    #[test]
+    #[should_panic(expected = "E39: too many nft tiers or prefix in multiple tiers")]
+    fn test_nft_tiers_overlap() {
+        let (mut context, mut contract) = setup_contract();
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), HashMap::new());
+        let mut tiers = HashMap::new();
+        tiers.insert(
+            "gold".to_string(),
+            NftTier { prefixes: vec![accounts(3).to_string()], weight: U128(100) },
+        );
+        tiers.insert(
+            "common".to_string(),
+            NftTier { prefixes: vec![accounts(3).to_string()], weight: U128(10) },
+        );
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_seed_nft_tiers("eugene".to_string(), tiers);
+    }
+
+    #[test]
+    fn test_create_simple_farm_v2() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        let farm_info = contract.create_simple_farm_v2(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 0,
+                reward_per_session: U128(5000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        );
+        assert_eq!(farm_info.farm_id, "bob#0".to_string());
+        assert_eq!(contract.get_farm(farm_info.farm_id.clone()), Some(farm_info));
+    }
+
+    #[test]
+    fn test_seed_stake_bounds_max() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_seed_stake_bounds(accounts(1).into(), U128(10), U128(100));
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 60);
+        let refund = match contract.ft_on_transfer(accounts(0), U128(60), String::from("")) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 20);
+        assert_eq!(
+            contract.list_user_seeds(accounts(0)).get(&accounts(1).to_string()),
+            Some(&U128(100))
+        );
+        // already at max, all refunded
+        let refund = match contract.ft_on_transfer(accounts(0), U128(30), String::from("")) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 30);
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 100);
+    }
+
+    #[test]
+    fn test_seed_stake_bounds_min() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_seed_stake_bounds(accounts(1).into(), U128(50), U128(0));
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 60);
+        let refund = match contract.ft_on_transfer(accounts(3), U128(20), String::from("")) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 20);
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 60);
+    }
+
+    #[test]
+    fn test_emissions_overview() {
+        let (mut context, mut contract) = setup_contract();
+        let farm0 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        let farm1 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 600, 60);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        testing_env!(context.block_timestamp(to_nano(170)).build());
+
+        let overview = contract.get_emissions_overview(0, 100);
+        assert_eq!(overview.len(), 2);
+        for (emission, farm_id) in overview.iter().zip([farm0, farm1]) {
+            let farm = contract.get_farm(farm_id.clone()).unwrap();
+            let breakdown = contract.get_farm_reward_breakdown(farm_id.clone()).unwrap();
+            assert_eq!(emission.farm_id, farm_id);
+            assert_eq!(emission.reward_token, farm.reward_token);
+            assert_eq!(emission.total_seed, contract.get_seed_info(farm.seed_id).unwrap().amount);
+            assert_eq!(emission.undistributed, breakdown.undistributed);
+        }
+        assert_eq!(overview[0].emission_per_second.0, 100);
+        assert_eq!(overview[0].undistributed.0, 45000);
+        // farm1 has no reward deposited
+        assert_eq!(overview[1].emission_per_second.0, 0);
+        assert_eq!(contract.get_emissions_overview(1, 100).len(), 1);
+    }
+
+    #[test]
+    fn test_withdraw_nft_after_balance_removed() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.update_nft_balance("eugene".to_string(), HashMap::new());
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_nft("eugene".to_string(), accounts(3).into(), "1".to_string(), None);
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 0);
+        assert_eq!(contract.get_user_nft_weight(accounts(0), "eugene".to_string()).0, 0);
+
+        // a failed transfer stakes it back with the weight it had
+        callback_env(&mut context, 161, PromiseResult::Failed);
+        contract.callback_post_withdraw_nft(
+            "eugene".to_string(),
+            accounts(0).into(),
+            accounts(3).into(),
+            "1".to_string(),
+            Some(U128(10)),
+        );
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 10);
+        assert_eq!(
+            contract.list_user_seeds(accounts(0)).get(&String::from("eugene")),
+            Some(&U128(10))
+        );
+    }
+
+    #[test]
+    fn test_estimate_unclaimed_at() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 20000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context.block_timestamp(to_nano(120)).build());
+        let estimate = contract.estimate_unclaimed_at(accounts(0), farm_id.clone(), 260);
+        testing_env!(context.block_timestamp(to_nano(260)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()), estimate);
+        assert_eq!(estimate.0, 15000);
+
+        // reward runs out after 4 rounds
+        testing_env!(context.block_timestamp(to_nano(120)).build());
+        let estimate = contract.estimate_unclaimed_at(accounts(0), farm_id.clone(), 1000);
+        assert_eq!(estimate.0, 20000);
+        // a time in the past is taken as now
+        assert_eq!(contract.estimate_unclaimed_at(accounts(0), farm_id, 0).0, 0);
+    }
+
+    #[test]
+    fn test_set_farm_beneficiary() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().beneficiary_id, accounts(0).to_string());
+
+        // nothing staked, round #3 goes to the creator
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .build());
+        contract.set_farm_beneficiary(farm_id.clone(), accounts(3));
+        testing_env!(context.block_timestamp(to_nano(260)).build());
+        contract.touch_farm(farm_id.clone());
+
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.beneficiary_id, accounts(3).to_string());
+        assert_eq!(farm_info.beneficiary_reward.0, 15000);
+        let rewards = contract.get_farm_beneficiary_rewards(farm_id);
+        assert_eq!(rewards.get(&accounts(0).to_string()), Some(&U128(5000)));
+        assert_eq!(rewards.get(&accounts(3).to_string()), Some(&U128(10000)));
+    }
+
+    #[test]
+    fn test_farm_beneficiary_cap() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_farm_beneficiary_cap(farm_id.clone(), 1000);
+
+        // 3 rounds with nothing staked, only 10% of total reward goes to beneficiary
+        testing_env!(context.block_timestamp(to_nano(260)).build());
+        let breakdown = contract.get_farm_reward_breakdown(farm_id.clone()).unwrap();
+        assert_eq!(breakdown.amount_of_beneficiary.0, 5000);
+        assert_eq!(breakdown.undistributed.0, 45000);
+        contract.touch_farm(farm_id.clone());
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.beneficiary_reward.0, 5000);
+        assert_eq!(farm_info.unclaimed_reward.0, 0);
+        assert_eq!(contract.get_farm_reward_breakdown(farm_id.clone()).unwrap().undistributed.0, 45000);
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 270, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 320);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+        assert_eq!(contract.get_farm(farm_id).unwrap().beneficiary_reward.0, 5000);
+    }
+
+    fn routed_event() -> String {
+        near_sdk::test_utils::get_logs()
+            .into_iter()
+            .find(|log| log.contains("\"event\":\"ft_transfer_routed\""))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_ft_transfer_routed_events() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        register_farmer(&mut context, &mut contract, accounts(0));
+
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        let event = routed_event();
+        assert!(event.contains("\"route\":\"seed\""));
+        assert!(event.contains("\"seed_id\":\"bob\""));
+        assert!(event.contains("\"refund\":\"0\""));
+
+        deposit_reward(&mut context, &mut contract, 10000, 120);
+        let event = routed_event();
+        assert!(event.contains("\"route\":\"reward\""));
+        assert!(event.contains("\"farm_id\":\"bob#0\""));
+        assert!(event.contains("\"amount\":\"10000\""));
+
+        // reward sent from a token other than the farm's reward token
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(130))
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(100), "bob#0".to_string());
+        let event = routed_event();
+        assert!(event.contains("\"route\":\"refund\""));
+        assert!(event.contains("\"token_id\":\"danny\""));
+        assert!(event.contains("\"refund\":\"100\""));
+    }
+
+    #[test]
+    fn test_claim_to_escrow() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        // reward token contract is down, auto withdraw of reward fails
+        callback_env(&mut context, 161, PromiseResult::Failed);
+        contract.callback_post_withdraw_reward(accounts(2).into(), accounts(0).into(), U128(5000));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        let escrowed = contract.claim_to_escrow(accounts(1).into());
+        assert_eq!(escrowed.get(&accounts(2).to_string()), Some(&U128(5000)));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        assert_eq!(contract.get_escrow(accounts(0)).get(&accounts(2).to_string()), Some(&U128(5000)));
+        let solvency = contract.get_token_solvency(accounts(2));
+        assert!(solvency.deposited.0 >= solvency.owed.0);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(180))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.withdraw_from_escrow(accounts(2)).0, 5000);
+        assert!(contract.get_escrow(accounts(0)).is_empty());
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("ft_transfer"));
+        assert!(receipts.contains("callback_post_withdraw_escrow"));
+
+        // a failed transfer goes back to escrow, not to caller's balance
+        callback_env(&mut context, 181, PromiseResult::Failed);
+        contract.callback_post_withdraw_escrow(accounts(2).into(), accounts(0).into(), U128(5000));
+        assert_eq!(contract.get_escrow(accounts(0)).get(&accounts(2).to_string()), Some(&U128(5000)));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        let solvency = contract.get_token_solvency(accounts(2));
+        assert!(solvency.deposited.0 >= solvency.owed.0);
+    }
+
+    #[test]
+    fn test_unregister_with_escrow() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        callback_env(&mut context, 161, PromiseResult::Failed);
+        contract.callback_post_withdraw_reward(accounts(2).into(), accounts(0).into(), U128(5000));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        contract.claim_to_escrow(accounts(1).into());
+        assert!(contract.storage_unregister(None));
+        assert!(contract.storage_balance_of(accounts(0)).is_none());
+        assert_eq!(contract.get_escrow(accounts(0)).get(&accounts(2).to_string()), Some(&U128(5000)));
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(180))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.withdraw_from_escrow(accounts(2)).0, 5000);
+        assert!(contract.get_escrow(accounts(0)).is_empty());
+        callback_env(&mut context, 181, PromiseResult::Successful(vec![]));
+        contract.callback_post_withdraw_escrow(accounts(2).into(), accounts(0).into(), U128(5000));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        let solvency = contract.get_token_solvency(accounts(2));
+        assert!(solvency.deposited.0 >= solvency.owed.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E69: too many farms in this seed")]
+    fn test_max_farms_per_seed() {
+        let (mut context, mut contract) = setup_contract();
+        for _ in 0..MAX_FARMS_PER_SEED {
+            create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        }
+        // farms of other seeds are not counted
+        create_farm(&mut context, &mut contract, accounts(3), accounts(2), 5000, 50);
+        assert_eq!(contract.get_number_of_farms(), MAX_FARMS_PER_SEED as u64 + 1);
+
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+    }
+
+    #[test]
+    #[should_panic(expected = "E49: too many reward tokens for this farm")]
+    fn test_max_reward_tokens_per_farm() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_farm_reward_rate(farm_id, U128(4000));
+        let tokens: Vec<AccountId> = (0..MAX_REWARD_TOKENS_PER_FARM)
+            .map(|i| format!("token{}.near", i))
+            .collect();
+        contract.assert_reward_tokens(&tokens);
+
+        let mut tokens = tokens;
+        tokens.push("extra.near".to_string());
+        contract.assert_reward_tokens(&tokens);
+    }
+
+    #[test]
+    fn test_get_farm_rounds() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        assert_eq!(contract.get_farm_rounds(farm_id.clone()), (0, 0));
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        for &ts in &[120, 160, 230, 300] {
+            testing_env!(context.block_timestamp(to_nano(ts)).build());
+            let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+            assert_eq!(
+                contract.get_farm_rounds(farm_id.clone()),
+                (farm_info.cur_round, farm_info.last_round)
+            );
+        }
+        claim_reward(&mut context, &mut contract, accounts(0), 310);
+        // rounds count from the first reward deposit at 100
+        assert_eq!(contract.get_farm_rounds(farm_id), (4, 4));
+    }
+
+    #[test]
+    fn test_remove_user_rps_by_farms() {
+        let (mut context, mut contract) = setup_contract();
+        let farm0 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        let farm1 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        let farm2 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 10000, 100);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(accounts(2), U128(10000), farm1.clone());
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        claim_reward_by_seed(&mut context, &mut contract, accounts(0), 300);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(310))
+            .attached_deposit(0)
+            .build());
+        assert!(contract.force_clean_farm(farm0.clone(), None));
+        assert!(contract.force_clean_farm(farm1.clone(), None));
+        let prev_available = contract.storage_balance_of(accounts(0)).unwrap().available.0;
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(320))
+            .attached_deposit(1)
+            .build());
+        let removed = contract.remove_user_rps_by_farms(vec![farm0.clone(), farm1.clone(), farm2.clone()]);
+        assert_eq!(removed, vec![true, true, false]);
+        assert_eq!(contract.get_user_rps(accounts(0), farm0), "0".to_string());
+        assert_eq!(contract.get_user_rps(accounts(0), farm1), "0".to_string());
+        let post_available = contract.storage_balance_of(accounts(0)).unwrap().available.0;
+        assert!(post_available > prev_available);
+    }
+
+    #[test]
+    fn test_cleanup_grace_sec() {
+        let (mut context, mut contract) = setup_contract();
+        let farm0 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        let farm1 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 10000, 100);
+        testing_env!(context.predecessor_account_id(accounts(2)).build());
+        contract.ft_on_transfer(accounts(2), U128(10000), farm1.clone());
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_cleanup_grace_sec(100);
+        assert_eq!(contract.get_metadata().cleanup_grace_sec, 100);
+
+        // both farms end at 200
+        testing_env!(context.block_timestamp(to_nano(250)).build());
+        assert!(!contract.force_clean_farm(farm0.clone(), None));
+        assert!(contract.get_farm(farm0.clone()).is_some());
+        assert!(contract.force_clean_farm(farm1.clone(), Some(true)));
+        assert!(contract.get_farm(farm1).is_none());
+
+        testing_env!(context.block_timestamp(to_nano(300)).build());
+        assert!(contract.force_clean_farm(farm0.clone(), None));
+        assert!(contract.get_farm(farm0).is_none());
+    }
+
+    #[test]
+    fn test_will_claim_fit() {
+        let (mut context, mut contract) = setup_contract();
+        for i in 0..5 {
+            let reward: ValidAccountId = format!("token{}.near", i).as_str().try_into().unwrap();
+            let farm_id = create_farm(&mut context, &mut contract, accounts(1), reward.clone(), 5000, 50);
+            testing_env!(context
+                .predecessor_account_id(reward.clone())
+                .block_timestamp(to_nano(100))
+                .build());
+            contract.ft_on_transfer(accounts(0), U128(10000), farm_id);
+        }
+        register_farmer(&mut context, &mut contract, accounts(0));
+        // rps of 5 farms
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 200)
+            .build());
+        contract.storage_deposit(None, None);
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        // keep only storage currently used
+        storage_withdraw(&mut context, &mut contract, accounts(0));
+
+        testing_env!(context.block_timestamp(to_nano(120)).build());
+        assert!(contract.will_claim_fit(accounts(0), accounts(1).into()));
+        testing_env!(context.block_timestamp(to_nano(160)).build());
+        assert!(!contract.will_claim_fit(accounts(0), accounts(1).into()));
+        assert!(!contract.will_claim_fit(accounts(3), accounts(1).into()));
+    }
+
+    #[test]
+    fn test_seed_deposit_below_min_refunded() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 20);
+        let refund = match contract.ft_on_transfer(accounts(0), U128(9), String::from("")) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 9);
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 20);
+        assert!(near_sdk::test_utils::get_logs()
+            .iter()
+            .any(|log| log.contains("E34: below min_deposit of this seed")));
+    }
+
+    #[test]
+    fn test_ft_deposit_to_nft_seed_refunded() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        testing_env!(context
+            .predecessor_account_id(accounts(4))
+            .block_timestamp(to_nano(110))
+            .attached_deposit(1)
+            .build());
+        let refund = match contract.ft_on_transfer(accounts(0), U128(100), String::from("")) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 100);
+        assert!(contract.list_user_seeds(accounts(0)).is_empty());
+    }
+
+    #[test]
+    fn test_remap_seed() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(260))
+            .build());
+        let unclaimed = contract.get_unclaimed_reward(accounts(0), String::from("bob#0"));
+        assert_eq!(unclaimed.0, 15000);
+
+        let moved = contract.remap_seed(accounts(1).into(), accounts(3).into(), vec![accounts(0)]);
+        assert_eq!(moved, 1);
+        assert!(contract.get_seed_info(accounts(1).into()).is_none());
+        let seed_info = contract.get_seed_info(accounts(3).into()).unwrap();
+        assert_eq!(seed_info.amount.0, 100);
+        assert!(seed_info.paused);
+        assert!(contract.get_farm(String::from("bob#0")).is_none());
+        assert_eq!(
+            contract.list_user_seeds(accounts(0)).get(&accounts(3).to_string()),
+            Some(&U128(100))
+        );
+        assert_eq!(
+            contract.get_unclaimed_reward(accounts(0), String::from("danny#0")),
+            unclaimed
+        );
+        // farmers already moved are skipped on later pages
+        assert_eq!(contract.remap_seed(accounts(1).into(), accounts(3).into(), vec![accounts(0)]), 0);
+
+        testing_env!(context.attached_deposit(1).build());
+        contract.claim_reward_by_seed(accounts(3).into());
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 15000);
+    }
+
+    #[test]
+    fn test_withdraw_reward_all_for_token() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        let farm_id = create_farm(&mut context, &mut contract, accounts(3), accounts(2), 3000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        contract.ft_on_transfer(accounts(0), U128(30000), farm_id);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 100);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.ft_on_transfer(accounts(0), U128(100), String::from(""));
+        claim_reward_by_seed(&mut context, &mut contract, accounts(0), 210);
+        contract.claim_reward_by_seed(accounts(3).into());
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 16000);
+
+        let amount = contract.withdraw_reward_all_for_token(accounts(2));
+        assert_eq!(amount.0, 16000);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert_eq!(receipts.matches("ft_transfer").count(), 1);
+    }
+
+    #[test]
+    fn test_is_farm_idle() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        assert!(!contract.is_farm_idle(farm_id.clone()));
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        testing_env!(context.block_timestamp(to_nano(160)).build());
+        assert!(contract.is_farm_idle(farm_id.clone()));
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 170, 10);
+        assert!(!contract.is_farm_idle(farm_id));
+    }
+
+    #[test]
+    fn test_compound() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(1), 5000, 50);
+        testing_env!(context
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(50000), farm_id);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 100);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(210))
+            .attached_deposit(1)
+            .build());
+        let amount = contract.compound(accounts(1).into());
+        assert_eq!(amount.0, 10000);
+        assert_eq!(contract.get_reward(accounts(0), accounts(1)).0, 0);
+        assert_eq!(
+            contract.list_user_seeds(accounts(0)).get(&accounts(1).to_string()),
+            Some(&U128(10100))
+        );
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 10100);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(!receipts.contains("ft_transfer"));
+    }
+
+    #[test]
+    fn test_get_account_farms() {
+        let (mut context, mut contract) = setup_contract();
+        let farm0 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        let farm1 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 600, 60);
+        let farm2 = create_farm(&mut context, &mut contract, accounts(3), accounts(2), 3000, 50);
+        create_farm(&mut context, &mut contract, accounts(5), accounts(2), 3000, 50);
+        assert!(contract.get_account_farms(accounts(0)).is_empty());
+        register_farmer(&mut context, &mut contract, accounts(0));
+        assert!(contract.get_account_farms(accounts(0)).is_empty());
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 100);
+        testing_env!(context.predecessor_account_id(accounts(3)).build());
+        contract.ft_on_transfer(accounts(0), U128(100), String::from(""));
+        assert_eq!(contract.get_account_farms(accounts(0)), vec![farm0, farm1, farm2]);
+    }
+
+    #[test]
+    fn test_reward_halving() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        // halves every 2 rounds: 8000, 8000, 4000, 4000, 2000, 2000, 1000, 1000, 500, ...
+        let farm_id = contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 0,
+                reward_per_session: U128(8000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 5000,
+                decay_period_rounds: 2,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        );
+        deposit_reward(&mut context, &mut contract, 30700, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        testing_env!(context.block_timestamp(to_nano(300)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 24000);
+        testing_env!(context.block_timestamp(to_nano(500)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 30000);
+        // 500 of round 8 and a tail round of 200
+        assert_eq!(contract.get_farm_rounds_remaining(farm_id.clone()), 2);
+
+        testing_env!(context.block_timestamp(to_nano(1000)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 30700);
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.cur_round, 10);
+        assert_eq!(farm_info.farm_status, "Ended".to_string());
+    }
+
+    #[test]
+    fn test_reward_decay_caps_deposit() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        let farm_id = contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 0,
+                reward_per_session: U128(8000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 5000,
+                decay_period_rounds: 2,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        );
+        // at most 32000 is ever released, more would never be distributed
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        match contract.ft_on_transfer(accounts(0), U128(32001), farm_id.clone()) {
+            near_sdk::PromiseOrValue::Value(refund) => assert_eq!(refund.0, 32001),
+            _ => panic!("expected refund"),
+        }
+        deposit_reward(&mut context, &mut contract, 32000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        assert!(contract.get_farm_rounds_remaining(farm_id.clone()) < u32::MAX);
+
+        testing_env!(context.block_timestamp(to_nano(100 + 50 * 200)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 32000);
+        assert_eq!(contract.get_farm(farm_id).unwrap().farm_status, "Ended".to_string());
+    }
+
+    #[test]
+    #[should_panic(expected = "E63: reward above what the decaying farm ever releases")]
+    fn test_reward_decay_caps_rate_change() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        let farm_id = contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 0,
+                reward_per_session: U128(8000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 5000,
+                decay_period_rounds: 2,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
+        );
+        deposit_reward(&mut context, &mut contract, 16000, 100);
+        // 4000 per session still releases 16000 in total
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_farm_reward_rate(farm_id.clone(), U128(4000));
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().reward_per_session.0, 4000);
+
+        contract.modify_farm_reward_rate(farm_id, U128(3999));
+    }
+
+    #[test]
+    fn test_effective_seed_total() {
+        let (mut context, mut contract) = setup_contract();
+        let farm0 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        let farm1 = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 600, 60);
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        nft_balance.insert(format!("{}@2", accounts(3)), U128(30));
+        let nft_farm = create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(5));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 100);
+        deposit_seed(&mut context, &mut contract, accounts(5), 110, 50);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        deposit_nft(&mut context, &mut contract, accounts(5), accounts(3), "2", "eugene", 110);
+
+        assert_eq!(contract.get_effective_seed_total(accounts(1).into()), U128(150));
+        // nft weights add to the seed total
+        assert_eq!(contract.get_effective_seed_total(accounts(4).into()), U128(40));
+        assert_eq!(contract.get_farm_effective_total(nft_farm), U128(40));
+        assert_eq!(contract.get_farm_effective_total(farm0.clone()), U128(150));
+
+        testing_env!(context
+            .predecessor_account_id(accounts(5))
+            .attached_deposit(1)
+            .build());
+        contract.opt_out_farm(farm1.clone());
+        assert_eq!(contract.get_farm_effective_total(farm0), U128(150));
+        assert_eq!(contract.get_farm_effective_total(farm1), U128(100));
+        assert_eq!(contract.get_effective_seed_total(accounts(3).into()), U128(0));
+        assert_eq!(contract.get_farm_effective_total("danny#0".to_string()), U128(0));
+    }
+
+    #[test]
+    fn test_noop_claim_keeps_storage() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+
+        let prev_usage = env::storage_usage();
+        let prev_rps = contract.get_user_rps(accounts(0), "bob#0".to_string());
+        contract.claim_reward_by_farm("bob#0".to_string(), None);
+        assert_eq!(env::storage_usage(), prev_usage);
+        assert_eq!(contract.get_user_rps(accounts(0), "bob#0".to_string()), prev_rps);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+    }
+
+    #[test]
+    fn test_farm_beneficiaries_split() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_farm_beneficiaries(farm_id.clone(), vec![(accounts(3), 6000), (accounts(4), 4000)]);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        // nothing staked in round #0, split 60/40
+        deposit_seed(&mut context, &mut contract, accounts(0), 160, 10);
+
+        let balances = contract.get_farm_beneficiary_balances(farm_id.clone());
+        assert_eq!(balances.get(&accounts(3).to_string()), Some(&U128(3000)));
+        assert_eq!(balances.get(&accounts(4).to_string()), Some(&U128(2000)));
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.beneficiary_reward.0, 5000);
+        assert_eq!(farm_info.beneficiary_id, accounts(3).to_string());
+
+        register_farmer(&mut context, &mut contract, accounts(3));
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .attached_deposit(1)
+            .build());
+        assert_eq!(contract.withdraw_beneficiary_reward(farm_id.clone()).0, 3000);
+        let balances = contract.get_farm_beneficiary_balances(farm_id.clone());
+        assert!(!balances.contains_key(&accounts(3).to_string()));
+        assert_eq!(
+            contract.get_farm_beneficiary_rewards(farm_id).get(&accounts(3).to_string()),
+            Some(&U128(3000))
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "E53: beneficiary weights must sum to 10000")]
+    fn test_farm_beneficiaries_invalid_weights() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_farm_beneficiaries(farm_id, vec![(accounts(3), 6000), (accounts(4), 3000)]);
+    }
+
+    #[test]
+    fn test_farm_funding_history() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        assert!(contract.get_farm_funding_history(String::from("bob#0")).is_empty());
+        deposit_reward(&mut context, &mut contract, 10000, 100);
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(3), U128(20000), String::from("bob#0"));
+
+        assert_eq!(
+            contract.get_farm_funding_history(String::from("bob#0")),
+            vec![(accounts(3).to_string(), U128(20000)), (accounts(0).to_string(), U128(10000))]
+        );
+    }
+
+    #[test]
+    fn test_claim_reward_by_farm_amount() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 30);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(260))
+            .attached_deposit(1)
+            .build());
+        let pending = contract.get_unclaimed_reward(accounts(0), String::from("bob#0")).0;
+        assert_eq!(pending, 15000);
+
+        let claimed = contract.claim_reward_by_farm_amount(String::from("bob#0"), U128(pending / 2));
+        assert_eq!(claimed.0, 7500);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 7500);
+        // rounding of user rps may keep at most a yocto per seed back
+        let left = contract.get_unclaimed_reward(accounts(0), String::from("bob#0")).0;
+        assert!(left <= 7500 && left + 30 >= 7500);
+
+        // the rest goes on accruing and is claimable in full
+        testing_env!(context.block_timestamp(to_nano(310)).build());
+        claim_reward(&mut context, &mut contract, accounts(0), 310);
+        let total = contract.get_reward(accounts(0), accounts(2)).0;
+        assert!(total <= 20000 && total + 30 >= 20000);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), String::from("bob#0")).0, 0);
+    }
+
+    #[test]
+    fn test_list_seed_nft_stakers() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        nft_balance.insert(format!("{}@2", accounts(3)), U128(20));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(5));
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        deposit_nft(&mut context, &mut contract, accounts(5), accounts(3), "2", "eugene", 111);
+
+        let stakers = contract.list_seed_nft_stakers("eugene".to_string(), 0, 10);
+        assert_eq!(
+            stakers,
+            vec![
+                (accounts(0).to_string(), vec![format!("{}@1", accounts(3))]),
+                (accounts(5).to_string(), vec![format!("{}@2", accounts(3))]),
+            ]
+        );
+        assert_eq!(contract.list_seed_nft_stakers("eugene".to_string(), 1, 1).len(), 1);
+        assert!(contract.list_seed_nft_stakers("eugene".to_string(), 2, 10).is_empty());
+        assert!(contract.list_seed_nft_stakers("unknown".to_string(), 0, 10).is_empty());
+    }
+
+    #[test]
+    fn test_reward_below_min_rounds_refunded() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_default_min_rounds(10);
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        assert_eq!(contract.get_farm("bob#0".to_string()).unwrap().min_rounds, 10);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        let refund = match contract.ft_on_transfer(accounts(0), U128(45000), String::from("bob#0")) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 45000);
+        assert!(near_sdk::test_utils::get_logs()
+            .iter()
+            .any(|log| log.contains(ERR54_BELOW_MIN_ROUNDS)));
+        let farm_info = contract.get_farm("bob#0".to_string()).unwrap();
+        assert_eq!(farm_info.farm_status, "Created".to_string());
+        assert_eq!(farm_info.total_reward.0, 0);
+
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        // once running, topping up has no minimum
+        deposit_reward(&mut context, &mut contract, 5000, 110);
+        assert_eq!(contract.get_farm("bob#0".to_string()).unwrap().total_reward.0, 55000);
+    }
 
-    use super::utils::*;
-    use super::*;
+    #[test]
+    fn test_check_invariants() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 260);
+        assert!(contract.check_invariants().is_empty());
+
+        let mut farm = contract.data().farms.get(&farm_id).unwrap();
+        farm.amount_of_reward += 1;
+        contract.data_mut().farms.insert(&farm_id, &farm);
+        let violations = contract.check_invariants();
+        assert_eq!(violations.len(), 1);
+        assert!(violations[0].starts_with(&format!("{}: amount_of_reward 50001", farm_id)));
+        assert_eq!(contract.check_invariants_in_range(0, 1), violations);
+        assert!(contract.check_invariants_in_range(1, 10).is_empty());
+    }
+
+    #[test]
+    fn test_reward_token_decimals_cached() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        // depositing reward does not fetch it
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(!receipts.contains("ft_metadata"));
 
-    fn setup_contract() -> (VMContextBuilder, Contract) {
-        let mut context = VMContextBuilder::new();
         testing_env!(context.predecessor_account_id(accounts(0)).build());
-        let contract = Contract::new(accounts(0));
-        (context, contract)
+        contract.fetch_reward_token_decimals(farm_id.clone());
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("ft_metadata"));
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().reward_token_decimals, None);
+
+        callback_env(&mut context, 101, PromiseResult::Failed);
+        contract.callback_post_ft_metadata(farm_id.clone());
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().reward_token_decimals, None);
+
+        let metadata = br#"{"spec":"ft-1.0.0","name":"Charlie","symbol":"CHA","decimals":18}"#;
+        callback_env(&mut context, 102, PromiseResult::Successful(metadata.to_vec()));
+        contract.callback_post_ft_metadata(farm_id.clone());
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().reward_token_decimals, Some(18));
     }
 
-    fn create_farm(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        seed: ValidAccountId,
-        reward: ValidAccountId,
-        session_amount: Balance,
-        session_interval: u32,
-    ) -> FarmId {
-        // storage needed: 341
+    #[test]
+    fn test_withdraw_seed_keep_rewards() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 20);
+
+        // by default, claimed reward stays inside
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .attached_deposit(env::storage_byte_cost() * 573)
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
             .build());
-        contract.create_simple_farm(
+        contract.withdraw_seed(accounts(1).into(), U128(10), None, None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 5000);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(!receipts.contains("callback_post_withdraw_reward"));
+        assert!(receipts.contains("callback_post_withdraw_ft_seed"));
+
+        // or goes out along with the seed
+        withdraw_seed(&mut context, &mut contract, accounts(0), 210, 10);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("callback_post_withdraw_reward"));
+    }
+
+    #[test]
+    fn test_seed_farmer_count() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        assert_eq!(contract.get_seed_farmer_count(accounts(1).into()), 0);
+
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 20);
+        deposit_seed(&mut context, &mut contract, accounts(0), 120, 20);
+        deposit_seed(&mut context, &mut contract, accounts(3), 130, 20);
+        assert_eq!(contract.get_seed_farmer_count(accounts(1).into()), 2);
+
+        withdraw_seed(&mut context, &mut contract, accounts(0), 140, 30);
+        assert_eq!(contract.get_seed_farmer_count(accounts(1).into()), 2);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 150, 10);
+        assert_eq!(contract.get_seed_farmer_count(accounts(1).into()), 1);
+        assert_eq!(contract.get_seed_farmer_count("unknown".to_string()), 0);
+
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        nft_balance.insert(format!("{}@2", accounts(3)), U128(20));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 160);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "2", "eugene", 161);
+        assert_eq!(contract.get_seed_farmer_count("eugene".to_string()), 1);
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(170))
+            .attached_deposit(1)
+            .build());
+        contract.withdraw_nft("eugene".to_string(), accounts(3).into(), "1".to_string(), None);
+        assert_eq!(contract.get_seed_farmer_count("eugene".to_string()), 1);
+        contract.withdraw_nft("eugene".to_string(), accounts(3).into(), "2".to_string(), None);
+        assert_eq!(contract.get_seed_farmer_count("eugene".to_string()), 0);
+    }
+
+    #[test]
+    fn test_farm_end_at_leftover_reclaimed() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        let farm_id = contract.create_simple_farm(
             HRFarmTerms {
-                seed_id: seed.into(),
-                reward_token: reward.into(),
-                start_at: 0,
-                reward_per_session: U128(session_amount),
-                session_interval: session_interval,
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 100,
+                reward_per_session: U128(5000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 0,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 300,
             },
             Some(U128(10)),
             None,
             None,
-        )
-    }
+        );
+        deposit_reward(&mut context, &mut contract, 50000, 90);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 100, 10);
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().farm_status, "Running".to_string());
+
+        // 4 rounds till end_at, 30000 left over
+        testing_env!(context.block_timestamp(to_nano(500)).build());
+        let farm_info = contract.get_farm(farm_id.clone()).unwrap();
+        assert_eq!(farm_info.farm_status, "Ended".to_string());
+        assert_eq!(farm_info.cur_round, 4);
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 20000);
 
-    fn deposit_reward(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        amount: u128,
-        time_stamp: u32,
-    ) {
         testing_env!(context
-            .predecessor_account_id(accounts(2))
-            .block_timestamp(to_nano(time_stamp))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(500))
             .attached_deposit(1)
             .build());
-        contract.ft_on_transfer(accounts(0), U128(amount), String::from("bob#0"));
+        assert_eq!(contract.reclaim_leftover_reward(farm_id.clone()).0, 30000);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("callback_post_reclaim_leftover"));
+        assert_eq!(contract.get_farm(farm_id.clone()).unwrap().total_reward.0, 20000);
+        assert!(contract.check_invariants().is_empty());
+        assert_eq!(contract.get_token_solvency(accounts(2)).owed.0, 20000);
+
+        claim_reward(&mut context, &mut contract, accounts(0), 510);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 20000);
     }
 
-    fn register_farmer(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        farmer: ValidAccountId,
-    ) -> StorageBalance {
+    #[test]
+    fn test_farm_rate_changed() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 100, 10);
+
         testing_env!(context
-            .predecessor_account_id(farmer.clone())
-            .is_view(false)
-            .attached_deposit(env::storage_byte_cost() * 1852)
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(200))
             .build());
-        contract.storage_deposit(Some(farmer), Some(true))
+        contract.modify_farm_reward_rate(farm_id.clone(), U128(1000));
+        let logs = near_sdk::test_utils::get_logs();
+        let event = logs.iter().find(|log| log.starts_with("EVENT_JSON:")).unwrap();
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(&event["EVENT_JSON:".len()..]).unwrap();
+        assert_eq!(event["event"], "farm_rate_changed");
+        assert_eq!(event["data"][0]["farm_id"], farm_id);
+        assert_eq!(event["data"][0]["old_reward_per_session"], "5000");
+        assert_eq!(event["data"][0]["new_reward_per_session"], "1000");
+        assert_eq!(event["data"][0]["effective_round"], 2);
+        assert_eq!(
+            contract.get_farm_rate_changes(farm_id.clone()),
+            vec![crate::farm::RateChange {
+                old_reward_per_session: U128(5000),
+                new_reward_per_session: U128(1000),
+                effective_round: 2,
+            }]
+        );
+
+        // 2 rounds at the old rate, then 1 at the new one
+        testing_env!(context.block_timestamp(to_nano(250)).build());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id).0, 11000);
     }
 
-    fn storage_withdraw(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        farmer: ValidAccountId,
-    ) -> StorageBalance {
+    #[test]
+    fn test_claim_if_profitable() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 100, 10);
+
         testing_env!(context
-            .predecessor_account_id(farmer.clone())
-            .is_view(false)
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
             .attached_deposit(1)
             .build());
-        contract.storage_withdraw(None)
-    }
+        assert!(!contract.claim_if_profitable(farm_id.clone(), U128(6000)));
+        assert!(get_created_receipts().is_empty());
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 5000);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
 
-    fn deposit_seed(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        farmer: ValidAccountId,
-        time_stamp: u32,
-        amount: Balance,
-    ) {
         testing_env!(context
-            .predecessor_account_id(accounts(1))
-            .is_view(false)
-            .block_timestamp(to_nano(time_stamp))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(210))
             .attached_deposit(1)
             .build());
-        contract.ft_on_transfer(farmer, U128(amount), String::from(""));
+        assert!(contract.claim_if_profitable(farm_id.clone(), U128(6000)));
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id).0, 0);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 0);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains("callback_post_withdraw_reward"));
     }
 
-    fn withdraw_seed(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        farmer: ValidAccountId,
-        time_stamp: u32,
-        amount: Balance,
-    ) {
+    #[test]
+    fn test_claim_if_profitable_less_referral() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
         testing_env!(context
-            .predecessor_account_id(farmer)
-            .is_view(false)
-            .block_timestamp(to_nano(time_stamp))
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(0)
+            .build());
+        contract.set_referral_bps(1000);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
             .attached_deposit(1)
             .build());
-        contract.withdraw_seed(accounts(1).into(), U128(amount));
-    }
+        contract.set_referrer(accounts(3));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
 
-    fn claim_reward(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        farmer: ValidAccountId,
-        time_stamp: u32,
-    ) {
         testing_env!(context
-            .predecessor_account_id(farmer)
-            .is_view(false)
-            .block_timestamp(to_nano(time_stamp))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
             .attached_deposit(1)
             .build());
-        contract.claim_reward_by_farm(String::from("bob#0"));
+        // 5000 pending, 500 of it goes to the referrer
+        assert!(!contract.claim_if_profitable(farm_id.clone(), U128(4500)));
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0, 5000);
+        assert!(contract.claim_if_profitable(farm_id.clone(), U128(4499)));
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farm_id).0, 0);
+        assert_eq!(contract.get_reward(accounts(3), accounts(2)).0, 500);
     }
 
-    fn claim_reward_by_seed(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        farmer: ValidAccountId,
-        time_stamp: u32,
-    ) {
+    #[test]
+    fn test_get_config() {
+        let (mut context, mut contract) = setup_contract();
+        let defaults = view::ContractConfig {
+            owner_id: accounts(0).into(),
+            cleanup_grace_sec: 0,
+            default_min_rounds: 0,
+            referral_bps: 0,
+            paused_seed_count: U64(0),
+            max_fee_bps: FEE_DIVISOR,
+            max_referral_bps: MAX_REFERRAL_BPS,
+            max_farms_per_seed: MAX_FARMS_PER_SEED as u32,
+            max_reward_tokens_per_farm: MAX_REWARD_TOKENS_PER_FARM as u32,
+            max_farm_funders: MAX_FARM_FUNDERS as u32,
+            max_farm_beneficiaries: MAX_FARM_BENEFICIARIES as u32,
+            max_nft_tiers: MAX_NFT_TIERS as u32,
+            max_nft_weight_scale: MAX_NFT_WEIGHT_SCALE,
+            max_batch_accounts: U64(MAX_BATCH_ACCOUNTS),
+            max_batch_farms: U64(MAX_BATCH_FARMS),
+            default_min_deposit: U128(MIN_SEED_DEPOSIT),
+        };
+        assert_eq!(contract.get_config(), defaults);
+
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.set_cleanup_grace_sec(100);
+        contract.set_default_min_rounds(10);
+        contract.set_referral_bps(500);
+        contract.pause_seed(accounts(1).into());
+        // pausing twice counts once
+        contract.pause_seed(accounts(1).into());
+        contract.set_owner(accounts(5));
+        assert_eq!(
+            contract.get_config(),
+            view::ContractConfig {
+                owner_id: accounts(5).into(),
+                cleanup_grace_sec: 100,
+                default_min_rounds: 10,
+                referral_bps: 500,
+                paused_seed_count: U64(1),
+                ..defaults.clone()
+            }
+        );
+
+        testing_env!(context.predecessor_account_id(accounts(5)).build());
+        contract.resume_seed(accounts(1).into());
+        assert_eq!(contract.get_config().paused_seed_count, U64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "E58: reward token of a farm can not change")]
+    fn test_farm_reward_token_immutable() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        let mut farm = contract.data().farms.get(&farm_id).unwrap();
+        farm.terms.reward_token = accounts(3).into();
+        contract.internal_save_farm(&farm_id, &farm);
+    }
+
+    #[test]
+    fn test_seed_deposit_unregistered_refunded() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
         testing_env!(context
-            .predecessor_account_id(farmer)
-            .is_view(false)
-            .block_timestamp(to_nano(time_stamp))
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(to_nano(110))
             .attached_deposit(1)
             .build());
-        contract.claim_reward_by_seed(String::from("bob"));
+        let refund = match contract.ft_on_transfer(accounts(0), U128(20), String::from("")) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 20);
+        assert!(near_sdk::test_utils::get_logs()
+            .iter()
+            .any(|log| log.contains("call storage_deposit on the farming contract before staking")));
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 0);
+
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        testing_env!(context
+            .predecessor_account_id(accounts(3))
+            .signer_account_id(accounts(0))
+            .block_timestamp(to_nano(120))
+            .attached_deposit(0)
+            .build());
+        match contract.nft_on_transfer(accounts(0).into(), accounts(0).into(), "1".to_string(), "eugene".to_string()) {
+            near_sdk::PromiseOrValue::Value(returned) => assert!(returned),
+            _ => unreachable!(),
+        }
+        assert!(near_sdk::test_utils::get_logs()
+            .iter()
+            .any(|log| log.starts_with(ERR17_STAKER_NOT_REGISTERED)));
     }
 
-    fn remove_farm(context: &mut VMContextBuilder, contract: &mut Contract, time_stamp: u32) {
+    #[test]
+    fn test_seed_deposit_event() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        deposit_seed(&mut context, &mut contract, accounts(3), 100, 30);
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 20);
+        let logs = near_sdk::test_utils::get_logs();
+        let event = logs
+            .iter()
+            .find(|log| log.starts_with("EVENT_JSON:") && log.contains("\"seed_deposit\""))
+            .unwrap();
+        let event: near_sdk::serde_json::Value =
+            near_sdk::serde_json::from_str(&event["EVENT_JSON:".len()..]).unwrap();
+        assert_eq!(event["data"][0]["account_id"], accounts(0).to_string());
+        assert_eq!(event["data"][0]["seed_id"], accounts(1).to_string());
+        assert_eq!(event["data"][0]["amount"], "20");
+        assert_eq!(event["data"][0]["farmer_seed"], "20");
+        assert_eq!(event["data"][0]["seed_total"], "50");
+        assert!(event["data"][0].get("nft_token_id").is_none());
+
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 120);
+        let logs = near_sdk::test_utils::get_logs();
+        let event = logs
+            .iter()
+            .find(|log| log.starts_with("EVENT_JSON:") && log.contains("\"seed_deposit\""))
+            .unwrap();
+        assert!(event.contains("\"amount\":\"10\""));
+        assert!(event.contains("\"seed_total\":\"10\""));
+        assert!(event.contains(&format!("\"nft_token_id\":\"{}@1\"", accounts(3))));
+    }
+
+    #[test]
+    fn test_can_farm_accept_reward() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        assert!(contract.can_farm_accept_reward(farm_id.clone()));
+        deposit_reward(&mut context, &mut contract, 10000, 100);
+        testing_env!(context.block_timestamp(to_nano(150)).build());
+        assert!(contract.can_farm_accept_reward(farm_id.clone()));
+        // reward runs out after 2 rounds
+        testing_env!(context.block_timestamp(to_nano(200)).build());
+        assert!(!contract.can_farm_accept_reward(farm_id.clone()));
+        assert!(!contract.can_farm_accept_reward("unknown#0".to_string()));
+    }
+
+    #[test]
+    fn test_claim_reward_by_seed_paged() {
+        let (mut context, mut contract) = setup_contract();
+        let mut farms = vec![];
+        for _ in 0..5 {
+            let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+            testing_env!(context
+                .predecessor_account_id(accounts(2))
+                .block_timestamp(to_nano(100))
+                .attached_deposit(1)
+                .build());
+            contract.ft_on_transfer(accounts(0), U128(50000), farm_id.clone());
+            farms.push(farm_id);
+        }
+        register_farmer(&mut context, &mut contract, accounts(0));
+        // rps of 5 farms
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .is_view(false)
-            .block_timestamp(to_nano(time_stamp))
+            .attached_deposit(env::storage_byte_cost() * 200)
             .build());
-        contract.force_clean_farm(String::from("bob#0"));
-    }
+        contract.storage_deposit(None, None);
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
 
-    fn remove_user_rps(
-        context: &mut VMContextBuilder,
-        contract: &mut Contract,
-        farmer: ValidAccountId,
-        farm_id: String,
-        time_stamp: u32,
-    ) -> bool {
         testing_env!(context
-            .predecessor_account_id(farmer)
-            .is_view(false)
-            .block_timestamp(to_nano(time_stamp))
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
             .build());
-        contract.remove_user_rps_by_farm(farm_id)
+        let seed_id: SeedId = accounts(1).into();
+        let (claimed, next) =
+            contract.internal_claim_user_reward_by_seed_range(&accounts(0).into(), &seed_id, 0, 2);
+        assert_eq!(claimed.get(&accounts(2).to_string()), Some(&10000));
+        assert_eq!(next, Some(2));
+        assert_eq!(contract.get_unclaimed_reward(accounts(0), farms[2].clone()).0, 5000);
+        let (_, next) =
+            contract.internal_claim_user_reward_by_seed_range(&accounts(0).into(), &seed_id, 2, 2);
+        assert_eq!(next, Some(4));
+        let (claimed, next) =
+            contract.internal_claim_user_reward_by_seed_range(&accounts(0).into(), &seed_id, 4, 2);
+        assert_eq!(claimed.get(&accounts(2).to_string()), Some(&5000));
+        assert_eq!(next, None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 25000);
+
+        // out of gas for a page, caller gets where to continue from
+        testing_env!(context
+            .block_timestamp(to_nano(210))
+            .prepaid_gas(GAS_FOR_CLAIM_PAGE - 1)
+            .build());
+        assert_eq!(contract.claim_reward_by_seed(seed_id.clone()), Some(0));
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 25000);
+        testing_env!(context.prepaid_gas(300_000_000_000_000).build());
+        assert_eq!(contract.claim_reward_by_seed_from(seed_id, 0), None);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 50000);
     }
 
-    fn to_yocto(value: &str) -> u128 {
-        let vals: Vec<_> = value.split('.').collect();
-        let part1 = vals[0].parse::<u128>().unwrap() * 10u128.pow(24);
-        if vals.len() > 1 {
-            let power = vals[1].len() as u32;
-            let part2 = vals[1].parse::<u128>().unwrap() * 10u128.pow(24 - power);
-            part1 + part2
-        } else {
-            part1
+    #[test]
+    fn test_deposit_seed_at_max_farms() {
+        // settling a full seed takes at most 4 pages of gas
+        assert!(MAX_FARMS_PER_SEED <= 4 * CLAIM_FARMS_PER_PAGE as usize);
+
+        let (mut context, mut contract) = setup_contract();
+        let mut farms = vec![];
+        for _ in 0..MAX_FARMS_PER_SEED {
+            let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+            testing_env!(context
+                .predecessor_account_id(accounts(2))
+                .block_timestamp(to_nano(100))
+                .attached_deposit(1)
+                .build());
+            contract.ft_on_transfer(accounts(0), U128(50000), farm_id.clone());
+            farms.push(farm_id);
         }
+        register_farmer(&mut context, &mut contract, accounts(0));
+        // rps of all farms
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 6000)
+            .build());
+        contract.storage_deposit(None, None);
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+
+        // the stake change settles every farm of the seed first
+        testing_env!(context.block_timestamp(to_nano(160)).build());
+        assert!(farms.iter().all(|farm_id| contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0 == 5000));
+        deposit_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        assert!(farms.iter().all(|farm_id| contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0 == 0));
+        withdraw_seed(&mut context, &mut contract, accounts(0), 210, 20);
+        assert!(farms.iter().all(|farm_id| contract.get_unclaimed_reward(accounts(0), farm_id.clone()).0 == 0));
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 0);
     }
 
     #[test]
-    fn test_basics() {
+    fn test_user_last_claim_round() {
         let (mut context, mut contract) = setup_contract();
-        // seed is bob, reward is charlie
-        let farm_id = create_farm(
-            &mut context,
-            &mut contract,
-            accounts(1),
-            accounts(2),
-            5000,
-            50,
-        );
-        assert_eq!(farm_id, String::from("bob#0"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        // assert_eq!(farm_info.farm_kind, String::from("SIMPLE_FARM"));
-        assert_eq!(farm_info.farm_status, String::from("Created"));
-        assert_eq!(farm_info.seed_id, String::from("bob"));
-        assert_eq!(farm_info.reward_token, String::from("charlie"));
-        assert_eq!(farm_info.reward_per_session, U128(5000));
-        assert_eq!(farm_info.session_interval, 50);
-
-        // deposit 50k, can last 10 rounds from 0 to 9
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
         deposit_reward(&mut context, &mut contract, 50000, 100);
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.farm_status, String::from("Running"));
-        assert_eq!(farm_info.start_at, 100);
-
-        // Farmer accounts(0) come in round 1
         register_farmer(&mut context, &mut contract, accounts(0));
-        deposit_seed(&mut context, &mut contract, accounts(0), 160, 10);
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.beneficiary_reward, U128(5000));
-        assert_eq!(farm_info.cur_round, 1);
-        assert_eq!(farm_info.last_round, 1);
+        assert_eq!(contract.get_user_last_claim_round(accounts(0), farm_id.clone()), 0);
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
 
-        // move to round 2, 5k unclaimed for accounts(0)
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        assert_eq!(contract.get_user_last_claim_round(accounts(0), farm_id.clone()), 1);
+        claim_reward(&mut context, &mut contract, accounts(0), 260);
+        assert_eq!(contract.get_user_last_claim_round(accounts(0), farm_id.clone()), 3);
+        assert_eq!(contract.get_user_last_claim_round(accounts(3), farm_id), 0);
+    }
+
+    #[test]
+    fn test_storage_deposit_registration_only_refund() {
+        let (mut context, mut contract) = setup_contract();
+        let min = Contract::suggested_min_storage_usage();
+        let attached = min * 100;
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(210))
-            .is_view(true)
+            .attached_deposit(attached)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(5000));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 2);
-        assert_eq!(farm_info.last_round, 1);
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\"deposit\":{}", attached - min)));
 
-        // Farmer accounts(3) come in
-        register_farmer(&mut context, &mut contract, accounts(3));
-        // deposit seed
-        deposit_seed(&mut context, &mut contract, accounts(3), 260, 10);
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(10000));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 3);
-        assert_eq!(farm_info.last_round, 3);
+        // already registered, all of it comes back
+        testing_env!(context.attached_deposit(attached).build());
+        let balance = contract.storage_deposit(None, Some(true));
+        assert_eq!(balance.total.0, min);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\"deposit\":{}", attached)));
+    }
+
+    #[test]
+    fn test_farm_snapshots() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
 
-        // move to round 4,
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(320))
-            .is_view(true)
+            .block_timestamp(to_nano(120))
+            .attached_deposit(0)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(12500));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(2500));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 4);
-        assert_eq!(farm_info.last_round, 3);
-
-        // remove all seeds at round 5
-        println!("----> remove all seeds at round 5");
-        withdraw_seed(&mut context, &mut contract, accounts(0), 360, 10);
-        withdraw_seed(&mut context, &mut contract, accounts(3), 370, 10);
+        contract.snapshot_farm(farm_id.clone());
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(380))
-            .is_view(true)
+            .block_timestamp(to_nano(170))
+            .attached_deposit(0)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let rewarded = contract.get_reward(accounts(0), accounts(2));
-        assert_eq!(rewarded, U128(0));
-        let rewarded = contract.get_reward(accounts(3), accounts(2));
-        assert_eq!(rewarded, U128(0));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 5);
-        assert_eq!(farm_info.last_round, 5);
-
-        // move to round 7, account3 come in again
-        println!("----> move to round 7, account3 come in again");
-        deposit_seed(&mut context, &mut contract, accounts(3), 460, 10);
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.beneficiary_reward, U128(15000));
-        assert_eq!(farm_info.cur_round, 7);
-        assert_eq!(farm_info.last_round, 7);
+        contract.snapshot_farm(farm_id.clone());
 
-        // move to round 8, account0 come in again
-        println!("----> move to round 8, account0 come in again");
-        deposit_seed(&mut context, &mut contract, accounts(0), 520, 10);
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(5000));
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 8);
-        assert_eq!(farm_info.last_round, 8);
+        assert_eq!(
+            contract.get_farm_snapshots(farm_id),
+            vec![
+                crate::farm::FarmSnapshot {
+                    timestamp: 120,
+                    total_seed: U128(10),
+                    cumulative_claimed: U128(0),
+                },
+                crate::farm::FarmSnapshot {
+                    timestamp: 170,
+                    total_seed: U128(10),
+                    cumulative_claimed: U128(5000),
+                },
+            ]
+        );
+    }
 
-        // move to round 9,
+    #[test]
+    fn test_seed_alias() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(580))
-            .is_view(true)
+            .attached_deposit(0)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(2500));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(7500));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 9);
-        assert_eq!(farm_info.last_round, 8);
-        assert_eq!(farm_info.farm_status, String::from("Running"));
+        contract.add_seed_alias(accounts(1).into(), accounts(5));
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(4));
 
-        // move to round 10,
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        // fargo is the migrated token of bob
         testing_env!(context
-            .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(610))
-            .is_view(true)
+            .predecessor_account_id(accounts(5))
+            .block_timestamp(to_nano(110))
+            .attached_deposit(1)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(5000));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(10000));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 10);
-        assert_eq!(farm_info.last_round, 8);
-        assert_eq!(farm_info.farm_status, String::from("Ended"));
+        contract.ft_on_transfer(accounts(4), U128(10), String::from(""));
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 20);
+        assert_eq!(contract.get_seed_aliases(accounts(1).into()).get(&accounts(5).to_string()), Some(&U128(10)));
 
-        // claim reward
-        println!("----> accounts(0) and accounts(3) claim reward");
+        // each gets back the token it staked
+        withdraw_seed(&mut context, &mut contract, accounts(4), 160, 10);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\"receiver_id\":\"{}\"", accounts(5))));
+        assert!(!receipts.contains(&format!("\"receiver_id\":\"{}\"", accounts(1))));
+        assert_eq!(contract.get_seed_aliases(accounts(1).into()).get(&accounts(5).to_string()), Some(&U128(0)));
+
+        withdraw_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\"receiver_id\":\"{}\"", accounts(1))));
+        assert!(!receipts.contains(&format!("\"receiver_id\":\"{}\"", accounts(5))));
+        assert_eq!(contract.get_seed_info(accounts(1).into()).unwrap().amount.0, 0);
+    }
+
+    #[test]
+    fn test_claim_without_stake_event() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        let logs = near_sdk::test_utils::get_logs();
+        let event = logs.iter().find(|log| log.starts_with("EVENT_JSON:")).unwrap();
+        assert!(event.contains("\"event\":\"claim_no_stake\""));
+        assert!(event.contains("\"farm_id\":\"bob#0\""));
+
+        // a farmer with stake gets no such event
+        deposit_seed(&mut context, &mut contract, accounts(0), 170, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 180);
+        let logs = near_sdk::test_utils::get_logs();
+        assert!(!logs.iter().any(|log| log.contains("claim_no_stake")));
+    }
+
+    #[test]
+    fn test_referral_reward() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(710))
-            .is_view(true)
+            .attached_deposit(0)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(5000));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(10000));
-        claim_reward(&mut context, &mut contract, accounts(0), 720);
-        claim_reward(&mut context, &mut contract, accounts(3), 730);
+        contract.set_referral_bps(1000);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(740))
-            .is_view(true)
+            .attached_deposit(1)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let rewarded = contract.get_reward(accounts(0), accounts(2));
-        assert_eq!(rewarded, U128(5000));
-        let rewarded = contract.get_reward(accounts(3), accounts(2));
-        assert_eq!(rewarded, U128(10000));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 10);
-        assert_eq!(farm_info.last_round, 10);
+        contract.set_referrer(accounts(3));
 
-        // clean farm
-        println!("----> clean farm");
-        remove_farm(&mut context, &mut contract, 750);
-        assert!(contract.get_farm(farm_id.clone()).is_none());
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 4500);
+        assert_eq!(contract.get_reward(accounts(3), accounts(2)).0, 500);
+        assert_eq!(
+            contract.get_referral_rewards(accounts(3)).get(&accounts(2).to_string()),
+            Some(&U128(500))
+        );
+        assert!(contract.get_referral_rewards(accounts(0)).is_empty());
+    }
+
+    #[test]
+    fn test_get_farm_shares() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        assert!(contract.get_farm_shares(farm_id.clone(), 0, 10).is_empty());
+
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        register_farmer(&mut context, &mut contract, accounts(4));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 50);
+        deposit_seed(&mut context, &mut contract, accounts(3), 110, 30);
+        deposit_seed(&mut context, &mut contract, accounts(4), 110, 20);
+        assert_eq!(
+            contract.get_farm_shares(farm_id.clone(), 0, 10),
+            vec![
+                (accounts(0).to_string(), 5000),
+                (accounts(3).to_string(), 3000),
+                (accounts(4).to_string(), 2000),
+            ]
+        );
+        assert_eq!(contract.get_farm_shares(farm_id.clone(), 1, 1), vec![(accounts(3).to_string(), 3000)]);
+        assert!(contract.get_farm_shares(farm_id, u64::MAX, 10).is_empty());
+    }
+
+    #[test]
+    fn test_admin_return_nft() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(5));
+        deposit_nft(&mut context, &mut contract, accounts(5), accounts(3), "1", "eugene", 110);
 
-        // remove user rps
-        println!("----> remove user rps");
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(760))
-            .is_view(true)
+            .block_timestamp(to_nano(160))
+            .attached_deposit(0)
             .build());
-        let prev_available = contract
-            .storage_balance_of(accounts(0))
-            .expect("Error")
-            .available
-            .0;
-        let ret = remove_user_rps(
-            &mut context,
-            &mut contract,
-            accounts(0).into(),
-            String::from("bob#0"),
-            770,
-        );
-        assert!(ret);
+        contract.admin_return_nft(accounts(5), "eugene".to_string(), accounts(3).into(), "1".to_string());
+        assert_eq!(contract.get_seed_info("eugene".to_string()).unwrap().amount.0, 0);
+        assert!(contract.list_user_seeds(accounts(5)).is_empty());
+        let receipts = near_sdk::serde_json::to_string(&get_created_receipts()).unwrap();
+        assert!(receipts.contains(&format!("\\\"receiver_id\\\":\\\"{}\\\"", accounts(5))));
+        assert!(receipts.contains("callback_post_withdraw_nft"));
+    }
+
+    #[test]
+    fn test_claim_clamped_to_unclaimed_dust() {
+        let (mut context, mut contract) = setup_contract();
+        let farm_id = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(3));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        // settles the round up to 160 in the farm
+        deposit_seed(&mut context, &mut contract, accounts(3), 160, 10);
+
+        // a rounding shortfall of 1 yocto in the farm
+        let mut farm = contract.data().farms.get(&farm_id).unwrap();
+        farm.last_distribution.unclaimed -= 1;
+        contract.data_mut().farms.insert(&farm_id, &farm);
+
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, 4999);
+        assert!(near_sdk::test_utils::get_logs().iter().any(|log| log.contains("clamped to unclaimed 4999")));
+        assert_eq!(contract.data().farms.get(&farm_id).unwrap().last_distribution.unclaimed, 0);
+    }
+
+    #[test]
+    fn test_create_farm_with_args() {
+        let (mut context, mut contract) = setup_contract();
+        let positional = create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(780))
-            .is_view(true)
+            .attached_deposit(env::storage_byte_cost() * 800)
             .build());
-        let post_available = contract
-            .storage_balance_of(accounts(0))
-            .expect("Error")
-            .available
-            .0;
-        assert_eq!(post_available - prev_available, 165 * 10_u128.pow(19));
+        let args: crate::farm::CreateFarmArgs = near_sdk::serde_json::from_str(
+            r#"{
+                "terms": {
+                    "seed_id": "bob",
+                    "reward_token": "charlie",
+                    "start_at": 0,
+                    "reward_per_session": "5000",
+                    "session_interval": 50
+                },
+                "min_deposit": "10"
+            }"#,
+        )
+        .unwrap();
+        let by_args = contract.create_farm(args);
+        assert_eq!(by_args, "bob#1");
+
+        let positional = near_sdk::serde_json::to_string(&contract.get_farm(positional).unwrap()).unwrap();
+        let by_args = near_sdk::serde_json::to_string(&contract.get_farm(by_args).unwrap()).unwrap();
+        assert_eq!(by_args.replace("bob#1", "bob#0"), positional);
+    }
+
+    #[test]
+    fn test_seed_nft_count() {
+        let (mut context, mut contract) = setup_contract();
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        nft_balance.insert(format!("{}@2", accounts(3)), U128(20));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        register_farmer(&mut context, &mut contract, accounts(5));
+        assert_eq!(contract.get_seed_nft_count("eugene".to_string()), 0);
+        deposit_nft(&mut context, &mut contract, accounts(0), accounts(3), "1", "eugene", 110);
+        deposit_nft(&mut context, &mut contract, accounts(5), accounts(3), "2", "eugene", 111);
+        assert_eq!(contract.get_seed_nft_count("eugene".to_string()), 2);
 
-        // withdraw seed
-        println!("----> accounts(0) and accounts(3) withdraw seed");
-        withdraw_seed(&mut context, &mut contract, accounts(0), 800, 10);
-        withdraw_seed(&mut context, &mut contract, accounts(3), 810, 10);
         testing_env!(context
-            .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(820))
-            .is_view(true)
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(to_nano(160))
+            .attached_deposit(1)
             .build());
-        let rewarded = contract.get_reward(accounts(0), accounts(2));
-        assert_eq!(rewarded, U128(5000));
-        let rewarded = contract.get_reward(accounts(3), accounts(2));
-        assert_eq!(rewarded, U128(10000));
+        contract.withdraw_nft("eugene".to_string(), accounts(3).into(), "1".to_string(), None);
+        assert_eq!(contract.get_seed_nft_count("eugene".to_string()), 1);
+
+        // a failed transfer puts the nft back, with the seed's weight if none was passed
+        callback_env(&mut context, 161, PromiseResult::Failed);
+        contract.callback_post_withdraw_nft(
+            "eugene".to_string(),
+            accounts(0).into(),
+            accounts(3).into(),
+            "1".to_string(),
+            None,
+        );
+        assert_eq!(contract.get_user_nft_weight(accounts(0), "eugene".to_string()).0, 10);
+        assert_eq!(contract.get_seed_nft_count("eugene".to_string()), 2);
+        assert_eq!(contract.get_seed_farmer_count("eugene".to_string()), 2);
+        assert_eq!(contract.get_seed_nft_count("bob".to_string()), 0);
     }
 
     #[test]
-    fn test_unclaimed_rewards() {
+    fn test_get_nft_equivalent() {
         let (mut context, mut contract) = setup_contract();
-        // seed is bob, reward is charlie
-        let farm_id = create_farm(
-            &mut context,
-            &mut contract,
-            accounts(1),
-            accounts(2),
-            to_yocto("1"),
-            50,
+        let mut nft_balance = HashMap::new();
+        nft_balance.insert(format!("{}@1", accounts(3)), U128(10));
+        nft_balance.insert(format!("{}@5", accounts(5)), U128(30));
+        nft_balance.insert(accounts(2).to_string(), U128(5));
+        nft_balance.insert(format!("{}@7", accounts(3)), U128(u128::MAX / 5));
+        create_nft_farm(&mut context, &mut contract, accounts(4), accounts(2), nft_balance);
+        let seed_id = accounts(4).to_string();
+
+        // exact token
+        assert_eq!(
+            contract.get_nft_equivalent(seed_id.clone(), accounts(3).into(), "1".to_string()),
+            Some(U128(10))
         );
-        assert_eq!(farm_id, String::from("bob#0"));
+        // paras series
+        assert_eq!(
+            contract.get_nft_equivalent(seed_id.clone(), accounts(5).into(), "5:2".to_string()),
+            Some(U128(30))
+        );
+        // any token of the contract
+        assert_eq!(
+            contract.get_nft_equivalent(seed_id.clone(), accounts(2).into(), "9".to_string()),
+            Some(U128(5))
+        );
+        assert_eq!(contract.get_nft_equivalent(seed_id.clone(), accounts(3).into(), "2".to_string()), None);
+        assert_eq!(contract.get_nft_equivalent("bob".to_string(), accounts(3).into(), "1".to_string()), None);
 
-        // deposit 10, can last 10 rounds from 0 to 9
-        deposit_reward(&mut context, &mut contract, to_yocto("10"), 100);
+        // scaled weight overflowing
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        contract.modify_seed_weight_scale(seed_id.clone(), 1);
+        assert_eq!(contract.get_nft_equivalent(seed_id.clone(), accounts(3).into(), "1".to_string()), Some(U128(100)));
+        assert_eq!(contract.get_nft_equivalent(seed_id, accounts(3).into(), "7".to_string()), None);
+    }
 
-        // Farmer1 accounts(0) come in round 0
+    #[test]
+    fn test_claim_tops_up_storage_from_near_reward() {
+        let (mut context, mut contract) = setup_contract();
+        let near: ValidAccountId = NEAR_REWARD_TOKEN.try_into().unwrap();
+        let one_near = 10_u128.pow(24);
+        create_farm(&mut context, &mut contract, accounts(1), near.clone(), one_near, 50);
         register_farmer(&mut context, &mut contract, accounts(0));
-        deposit_seed(&mut context, &mut contract, accounts(0), 110, to_yocto("1"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed, U128(0));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 0);
-        assert_eq!(farm_info.last_round, 0);
-        assert_eq!(farm_info.claimed_reward.0, 0);
-        assert_eq!(farm_info.unclaimed_reward.0, 0);
-
-        // move to round 1,
         testing_env!(context
-            .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(160))
-            .is_view(true)
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(10 * one_near)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("1"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 1);
-        assert_eq!(farm_info.last_round, 0);
-        assert_eq!(farm_info.claimed_reward.0, to_yocto("0"));
-        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("1"));
+        contract.fund_farm_near(String::from("bob#0"));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        testing_env!(context.predecessor_account_id(accounts(0)).attached_deposit(1).build());
+        contract.set_storage_top_up(true);
 
-        // Farmer2 accounts(3) come in round 1
+        // storage deposit of the farmer is all used up
+        let account_id: AccountId = accounts(0).into();
+        let mut farmer = contract.get_farmer(&account_id);
+        farmer.get_ref_mut().amount = farmer.get_ref().storage_usage();
+        contract.data_mut().farmers.insert(&account_id, &farmer);
+        let storage_before = farmer.get_ref().amount;
+
+        // the new reward entry is paid from the NEAR claimed
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+        let storage = contract.storage_balance_of(accounts(0)).unwrap();
+        let top_up = storage.total.0 - storage_before;
+        assert_eq!(top_up, (4 + MAX_ACCOUNT_LENGTH + 16) * env::storage_byte_cost());
+        assert_eq!(storage.available.0, 0);
+        assert_eq!(contract.get_reward(accounts(0), near).0, one_near - top_up);
+    }
+
+    #[test]
+    #[should_panic(expected = "E52: not enough tokens beyond liabilities")]
+    fn test_recover_foreign_ft_excludes_pending_out() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        // 5000 reward on its way out, still in the real balance
+        withdraw_seed(&mut context, &mut contract, accounts(0), 160, 10);
+        recover_foreign_ft(&mut context, &mut contract, 53000, 3001);
+    }
+
+    #[test]
+    fn test_seed_farmer_count_failed_withdraw() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 120, 10);
+        assert_eq!(contract.get_seed_farmer_count(accounts(1).into()), 0);
+
+        // the seed comes back, so does the farmer
+        callback_env(&mut context, 121, PromiseResult::Failed);
+        contract.callback_post_withdraw_ft_seed(accounts(1).into(), accounts(0).into(), U128(10));
+        assert_eq!(contract.get_seed_farmer_count(accounts(1).into()), 1);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 130, 10);
+        assert_eq!(contract.get_seed_farmer_count(accounts(1).into()), 0);
+    }
+
+    #[test]
+    fn test_backfill_farmer_ids() {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
         register_farmer(&mut context, &mut contract, accounts(3));
-        // deposit seed
-        deposit_seed(&mut context, &mut contract, accounts(3), 180, to_yocto("1"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("1"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        deposit_seed(&mut context, &mut contract, accounts(3), 110, 10);
+        // as if registered before the farmer list existed
+        contract.data_mut().farmer_ids.remove(&accounts(3).to_string());
+        assert_eq!(contract.get_farm_shares(String::from("bob#0"), 0, 10).len(), 1);
 
-        // move to round 2,
+        testing_env!(context.predecessor_account_id(accounts(0)).build());
+        assert_eq!(contract.backfill_farmer_ids(vec![accounts(0), accounts(3), accounts(5)]), 1);
+        assert_eq!(contract.get_farm_shares(String::from("bob#0"), 0, 10).len(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "E11: insufficient $NEAR storage deposit")]
+    fn test_claim_no_storage_top_up_without_opt_in() {
+        let (mut context, mut contract) = setup_contract();
+        let near: ValidAccountId = NEAR_REWARD_TOKEN.try_into().unwrap();
+        let one_near = 10_u128.pow(24);
+        create_farm(&mut context, &mut contract, accounts(1), near, one_near, 50);
+        register_farmer(&mut context, &mut contract, accounts(0));
         testing_env!(context
-            .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(210))
-            .is_view(true)
+            .predecessor_account_id(accounts(3))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(10 * one_near)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("1.5"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0.5"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 2);
-        assert_eq!(farm_info.last_round, 1);
-        assert_eq!(farm_info.claimed_reward.0, to_yocto("0"));
-        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("2"));
+        contract.fund_farm_near(String::from("bob#0"));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
 
-        // farmer1 claim reward by farm_id at round 3
-        claim_reward(&mut context, &mut contract, accounts(0), 260);
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("1"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 3);
-        assert_eq!(farm_info.last_round, 3);
-        assert_eq!(farm_info.claimed_reward.0, to_yocto("2"));
-        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("1"));
+        let account_id: AccountId = accounts(0).into();
+        let mut farmer = contract.get_farmer(&account_id);
+        farmer.get_ref_mut().amount = farmer.get_ref().storage_usage();
+        contract.data_mut().farmers.insert(&account_id, &farmer);
 
-        // farmer2 claim reward by seed_id at round 4
-        claim_reward_by_seed(&mut context, &mut contract, accounts(3), 310);
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0.5"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 4);
-        assert_eq!(farm_info.last_round, 4);
-        assert_eq!(farm_info.claimed_reward.0, to_yocto("3.5"));
-        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("0.5"));
+        claim_reward(&mut context, &mut contract, accounts(0), 160);
+    }
 
-        // farmer1 unstake half lpt at round 5
-        withdraw_seed(
-            &mut context,
-            &mut contract,
-            accounts(0),
-            360,
-            to_yocto("0.4"),
+    #[test]
+    #[should_panic(expected = "E66: block height beyond u32 timeline")]
+    fn test_farm_by_block_height_overflow() {
+        let (mut context, mut contract) = setup_contract();
+        testing_env!(context
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(env::storage_byte_cost() * 800)
+            .build());
+        let farm_id = contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 0,
+                reward_per_session: U128(5000),
+                session_interval: 10,
+                by_block_height: true,
+                accrual_granularity: 0,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
         );
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0.5"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 5);
-        assert_eq!(farm_info.last_round, 5);
-        assert_eq!(farm_info.claimed_reward.0, to_yocto("4.5"));
-        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("0.5"));
 
-        // farmer2 unstake all his lpt at round 6
-        withdraw_seed(&mut context, &mut contract, accounts(3), 410, to_yocto("1"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0.375"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 6);
-        assert_eq!(farm_info.last_round, 6);
-        assert_eq!(farm_info.claimed_reward.0, to_yocto("5.625"));
-        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("0.375"));
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_index(u32::MAX as u64 + 1)
+            .attached_deposit(1)
+            .build());
+        contract.ft_on_transfer(accounts(0), U128(50000), farm_id);
+    }
 
-        // move to round 7
+    #[test]
+    fn test_min_rounds_by_reward_per_round() {
+        let (mut context, mut contract) = setup_contract();
         testing_env!(context
             .predecessor_account_id(accounts(0))
-            .block_timestamp(to_nano(460))
-            .is_view(true)
+            .attached_deposit(env::storage_byte_cost() * 800)
             .build());
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("1.374999999999999999999999"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 7);
-        assert_eq!(farm_info.last_round, 6);
-        assert_eq!(farm_info.claimed_reward.0, to_yocto("5.625"));
-        assert_eq!(farm_info.unclaimed_reward.0, to_yocto("1.375"));
-        withdraw_seed(
-            &mut context,
-            &mut contract,
-            accounts(0),
-            470,
-            to_yocto("0.6"),
-        );
-        let unclaimed = contract.get_unclaimed_reward(accounts(0), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
-        let unclaimed = contract.get_unclaimed_reward(accounts(3), farm_id.clone());
-        assert_eq!(unclaimed.0, to_yocto("0"));
-        let farm_info = contract.get_farm(farm_id.clone()).expect("Error");
-        assert_eq!(farm_info.cur_round, 7);
-        assert_eq!(farm_info.last_round, 7);
-        assert_eq!(
-            farm_info.claimed_reward.0,
-            to_yocto("6.999999999999999999999999")
+        contract.set_default_min_rounds(10);
+        // 5 rounds a session, 1000 per round
+        let farm_id = contract.create_simple_farm(
+            HRFarmTerms {
+                seed_id: accounts(1).into(),
+                reward_token: accounts(2),
+                start_at: 0,
+                reward_per_session: U128(5000),
+                session_interval: 50,
+                by_block_height: false,
+                accrual_granularity: 10,
+                decay_factor_bps: 0,
+                decay_period_rounds: 0,
+                end_at: 0,
+            },
+            Some(U128(10)),
+            None,
+            None,
         );
-        assert_eq!(farm_info.unclaimed_reward.0, 1);
+
+        testing_env!(context
+            .predecessor_account_id(accounts(2))
+            .block_timestamp(to_nano(100))
+            .attached_deposit(1)
+            .build());
+        let refund = match contract.ft_on_transfer(accounts(0), U128(9999), farm_id.clone()) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 9999);
+        let refund = match contract.ft_on_transfer(accounts(0), U128(10000), farm_id.clone()) {
+            near_sdk::PromiseOrValue::Value(refund) => refund.0,
+            _ => unreachable!(),
+        };
+        assert_eq!(refund, 0);
+        assert_eq!(contract.get_farm(farm_id).unwrap().total_reward.0, 10000);
     }
 
     #[test]
-    #[should_panic(expected = "E11: insufficient $NEAR storage deposit")]
-    fn test_storage_withdraw() {
+    fn test_upgrade_v101_seed() {
         let (mut context, mut contract) = setup_contract();
-        // Farmer1 accounts(0) come in round 0
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
         register_farmer(&mut context, &mut contract, accounts(0));
-        // println!("locked: {}, deposited: {}", sb.total.0, sb.available.0);
-        let sb = storage_withdraw(&mut context, &mut contract, accounts(0));
-        // println!("locked: {}, deposited: {}", sb.total.0, sb.available.0);
-        assert_eq!(sb.total.0, 920000000000000000000);
-        assert_eq!(sb.available.0, 0);
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        // as if stored before the seed got versioned
+        let seed_id: SeedId = accounts(1).into();
+        let farm_seed = contract.get_seed(&seed_id);
+        let farm_seed = farm_seed.get_ref();
+        let legacy = VersionedFarmSeed::V101(crate::legacy::FarmSeedV101 {
+            seed_id: farm_seed.seed_id.clone(),
+            seed_type: farm_seed.seed_type.clone(),
+            farms: farm_seed.farms.clone(),
+            next_index: farm_seed.next_index,
+            amount: farm_seed.amount,
+            min_deposit: farm_seed.min_deposit,
+            metadata: None,
+        });
+        contract.data_mut().seeds.insert(&seed_id, &legacy);
 
-        let farm_id = create_farm(
-            &mut context,
-            &mut contract,
-            accounts(1),
-            accounts(2),
-            5000,
-            50,
+        let seed_info = contract.get_seed_info(seed_id.clone()).unwrap();
+        assert_eq!(seed_info.amount.0, 10);
+        assert_eq!(seed_info.total_seed_deposited.0, 10);
+        assert_eq!(seed_info.farms, vec![String::from("bob#0")]);
+        assert_eq!(contract.get_seed_farmer_count(seed_id.clone()), 0);
+
+        // farmers staking before the upgrade are not counted, nor go below 0
+        withdraw_seed(&mut context, &mut contract, accounts(0), 120, 10);
+        assert_eq!(contract.get_seed_farmer_count(seed_id.clone()), 0);
+        deposit_seed(&mut context, &mut contract, accounts(0), 130, 10);
+        assert_eq!(contract.get_seed_farmer_count(seed_id.clone()), 1);
+        assert!(!contract.data().seeds.get(&seed_id).unwrap().need_upgrade());
+    }
+
+    /// Stores state as before farmers, seeds and farms got versioned,
+    /// with accounts(0) staking 10 of accounts(1) and having claimed some of accounts(2).
+    fn setup_v101_state() -> (VMContextBuilder, Balance) {
+        let (mut context, mut contract) = setup_contract();
+        create_farm(&mut context, &mut contract, accounts(1), accounts(2), 5000, 50);
+        deposit_reward(&mut context, &mut contract, 50000, 100);
+        register_farmer(&mut context, &mut contract, accounts(0));
+        deposit_seed(&mut context, &mut contract, accounts(0), 110, 10);
+        claim_reward(&mut context, &mut contract, accounts(0), 200);
+        let claimed = contract.get_reward(accounts(0), accounts(2)).0;
+        assert!(claimed > 0);
+
+        // rewrite the state as stored before farmers, seeds and farms got versioned
+        let farm_id = String::from("bob#0");
+        let seed_id: SeedId = accounts(1).into();
+        let account_id: AccountId = accounts(0).into();
+        let farm = contract.data().farms.get(&farm_id).unwrap();
+        let farm_seed = contract.get_seed(&seed_id);
+        let farm_seed = farm_seed.get_ref();
+        let legacy_seed = crate::legacy::FarmSeedV101 {
+            seed_id: farm_seed.seed_id.clone(),
+            seed_type: farm_seed.seed_type.clone(),
+            farms: farm_seed.farms.clone(),
+            next_index: farm_seed.next_index,
+            amount: farm_seed.amount,
+            min_deposit: farm_seed.min_deposit,
+            metadata: None,
+        };
+        let mut farmer = contract.get_farmer(&account_id).get();
+        // claim rounds were not recorded before
+        farmer.user_claim_rounds.remove(&farm_id);
+        let legacy_farmer = crate::legacy::FarmerV101 {
+            farmer_id: farmer.farmer_id,
+            amount: farmer.amount,
+            rewards: farmer.rewards,
+            seeds: farmer.seeds,
+            user_rps: farmer.user_rps,
+            rps_count: farmer.rps_count,
+            nft_seeds: farmer.nft_seeds,
+        };
+        let Contract { mut data } = contract;
+        data.farmer_ids.clear();
+        let mut seeds: UnorderedMap<SeedId, crate::legacy::FarmSeedV101> =
+            UnorderedMap::try_from_slice(&data.seeds.try_to_vec().unwrap()).unwrap();
+        seeds.insert_raw(&seed_id.try_to_vec().unwrap(), &legacy_seed.try_to_vec().unwrap());
+        let mut farms: UnorderedMap<FarmId, crate::legacy::FarmV101> =
+            UnorderedMap::try_from_slice(&data.farms.try_to_vec().unwrap()).unwrap();
+        farms.insert_raw(
+            &farm_id.try_to_vec().unwrap(),
+            &crate::legacy::FarmV101 {
+                farm_id: farm.farm_id,
+                terms: crate::legacy::FarmTermsV101 {
+                    seed_id: farm.terms.seed_id,
+                    reward_token: farm.terms.reward_token,
+                    start_at: farm.terms.start_at,
+                    reward_per_session: farm.terms.reward_per_session,
+                    session_interval: farm.terms.session_interval,
+                },
+                status: farm.status,
+                last_distribution: farm.last_distribution,
+                amount_of_reward: farm.amount_of_reward,
+                amount_of_claimed: farm.amount_of_claimed,
+                amount_of_beneficiary: farm.amount_of_beneficiary,
+            }
+            .try_to_vec()
+            .unwrap(),
         );
-        assert_eq!(farm_id, String::from("bob#0"));
+        let mut farmers = data.farmers;
+        farmers.insert(&account_id, &VersionedFarmer::V101(legacy_farmer));
+        let outdated_farms: UnorderedMap<FarmId, crate::legacy::FarmV101> =
+            UnorderedMap::try_from_slice(&data.outdated_farms.try_to_vec().unwrap()).unwrap();
+        env::state_write(&crate::legacy::ContractV101 {
+            data: crate::legacy::ContractDataV101 {
+                owner_id: data.owner_id,
+                seeds,
+                farmers,
+                farms,
+                outdated_farms,
+                nft_balance_seeds: data.nft_balance_seeds,
+                farmer_count: data.farmer_count,
+                reward_info: data.reward_info,
+            },
+        });
 
-        deposit_seed(&mut context, &mut contract, accounts(0), 60, 10);
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .attached_deposit(0)
+            .build());
+        (context, claimed)
+    }
+
+    #[test]
+    fn test_migrate_v101_state() {
+        let (mut context, claimed) = setup_v101_state();
+        let farm_id = String::from("bob#0");
+        let seed_id: SeedId = accounts(1).into();
+        let account_id: AccountId = accounts(0).into();
+        let mut contract = Contract::migrate(vec![(accounts(1), U128(10)), (accounts(2), U128(50000))]);
+        assert!(contract.check_invariants().is_empty());
+        assert_eq!(contract.internal_token_solvency(&accounts(2).into()), (50000, 50000));
+        assert_eq!(contract.internal_token_solvency(&seed_id), (10, 10));
+        assert_eq!(contract.data().farms.get(&farm_id).unwrap().creator_id, account_id);
+        assert_eq!(contract.get_reward(accounts(0), accounts(2)).0, claimed);
+        assert_eq!(contract.get_seed_info(seed_id.clone()).unwrap().amount.0, 10);
+        assert_eq!(contract.get_farmer(&account_id).get_ref().get_rps_farms(&seed_id), vec![farm_id.clone()]);
+
+        // legacy farmer and seed upgrade on use
+        claim_reward(&mut context, &mut contract, accounts(0), 300);
+        assert!(contract.get_reward(accounts(0), accounts(2)).0 > claimed);
+        withdraw_seed(&mut context, &mut contract, accounts(0), 310, 10);
+        assert!(!contract.data().farmers.get(&account_id).unwrap().need_upgrade());
+        assert!(!contract.data().seeds.get(&seed_id).unwrap().need_upgrade());
+        assert_eq!(contract.get_seed_info(seed_id).unwrap().amount.0, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "E67: no state to migrate")]
+    fn test_migrate_without_state() {
+        let (mut context, _) = setup_contract();
+        testing_env!(context
+            .current_account_id(accounts(0))
+            .predecessor_account_id(accounts(0))
+            .build());
+        Contract::migrate(vec![]);
+    }
+
+    #[test]
+    #[should_panic(expected = "E68: balance of every seed and reward token is needed to migrate: bob")]
+    fn test_migrate_without_token_balance() {
+        setup_v101_state();
+        Contract::migrate(vec![(accounts(2), U128(50000))]);
     }
 }
@@ -1,4 +1,7 @@
 use crate::*;
+use crate::events::{emit_farm_rate_changed, FarmRateChangedData};
+use crate::farm::FEE_DIVISOR;
+use crate::utils::{GAS_FOR_FT_METADATA, GAS_FOR_FT_METADATA_CALLBACK, MAX_FARM_BENEFICIARIES, MAX_NFT_TIERS, MAX_NFT_WEIGHT_SCALE, MAX_BATCH_ACCOUNTS, MAX_REFERRAL_BPS, MAX_REMAP_FARMERS, MFT_TAG, NEAR_DECIMALS};
 
 use near_sdk::json_types::U128;
 
@@ -9,10 +12,31 @@ impl Contract {
         self.data_mut().owner_id = owner_id.into();
     }
 
-    /// force clean 
-    pub fn force_clean_farm(&mut self, farm_id: String) -> bool {
+    /// force clean, only once the farm has ended for cleanup_grace_sec,
+    /// unless force is set for emergency.
+    pub fn force_clean_farm(&mut self, farm_id: String, force: Option<bool>) -> bool {
         self.assert_owner();
-        self.internal_remove_farm_by_farm_id(&farm_id)
+        let grace_sec = if force.unwrap_or(false) { 0 } else { self.data().cleanup_grace_sec };
+        self.internal_remove_farm_by_farm_id(&farm_id, grace_sec)
+    }
+
+    /// set how long an ended farm is kept for slow farmers to claim before it can be cleaned.
+    pub fn set_cleanup_grace_sec(&mut self, cleanup_grace_sec: u32) {
+        self.assert_owner();
+        self.data_mut().cleanup_grace_sec = cleanup_grace_sec;
+    }
+
+    /// set how many rounds the first reward deposit of each new farm must last, 0 to disable.
+    pub fn set_default_min_rounds(&mut self, min_rounds: u32) {
+        self.assert_owner();
+        self.data_mut().default_min_rounds = min_rounds;
+    }
+
+    /// set cut of each claim going to the farmer's referrer, at most MAX_REFERRAL_BPS, 0 to disable.
+    pub fn set_referral_bps(&mut self, referral_bps: u16) {
+        self.assert_owner();
+        assert!(referral_bps <= MAX_REFERRAL_BPS, "{}", ERR61_INVALID_REFERRAL_BPS);
+        self.data_mut().referral_bps = referral_bps;
     }
 
     pub fn modify_seed_min_deposit(&mut self, seed_id: String, min_deposit: U128) {
@@ -21,6 +45,508 @@ impl Contract {
         farm_seed.get_ref_mut().min_deposit = min_deposit.into();
     }
 
+    /// set seconds for a newly staked nft in given seed to reach its full weight,
+    /// 0 means full weight immediately.
+    pub fn modify_seed_nft_ramp(&mut self, seed_id: SeedId, ramp_sec: u32) {
+        self.assert_owner();
+        let mut farm_seed = self.get_seed(&seed_id);
+        farm_seed.get_ref_mut().nft_ramp_sec = ramp_sec;
+        self.data_mut().seeds.insert(&seed_id, &farm_seed);
+    }
+
+    /// set weight scale of given NFT seed, weights in its nft balance count as 10^weight_scale times,
+    /// only affects nfts staked from now on.
+    pub fn modify_seed_weight_scale(&mut self, seed_id: SeedId, weight_scale: u8) {
+        self.assert_owner();
+        assert!(weight_scale <= MAX_NFT_WEIGHT_SCALE, "{}", ERR36_INVALID_WEIGHT_SCALE);
+        let mut farm_seed = self.get_seed(&seed_id);
+        farm_seed.get_ref_mut().weight_scale = weight_scale;
+        self.data_mut().seeds.insert(&seed_id, &farm_seed);
+    }
+
+    /// replace accepted nfts of given NFT seed and their weights, dropping its tiers.
+    /// Only affects nfts staked from now on, staked ones keep their weight till withdrawn.
+    pub fn update_nft_balance(&mut self, seed_id: SeedId, nft_balance: NftBalance) {
+        self.assert_owner();
+        let farm_seed = self.get_seed(&seed_id);
+        assert_eq!(farm_seed.get_ref().seed_type, SeedType::NFT, "{}", ERR38_NOT_NFT_SEED);
+        self.data_mut().nft_balance_seeds.insert(&seed_id, &nft_balance);
+        self.data_mut().nft_tiers.remove(&seed_id);
+    }
+
+    /// replace accepted nfts of given NFT seed with named tiers,
+    /// each prefix of a tier takes the tier's weight. Only affects nfts staked from now on.
+    pub fn set_seed_nft_tiers(&mut self, seed_id: SeedId, tiers: HashMap<String, NftTier>) {
+        self.assert_owner();
+        let farm_seed = self.get_seed(&seed_id);
+        assert_eq!(farm_seed.get_ref().seed_type, SeedType::NFT, "{}", ERR38_NOT_NFT_SEED);
+        assert!(tiers.len() <= MAX_NFT_TIERS, "{}", ERR39_INVALID_NFT_TIERS);
+        let mut nft_balance = NftBalance::new();
+        for tier in tiers.values() {
+            for prefix in tier.prefixes.iter() {
+                assert!(
+                    nft_balance.insert(prefix.clone(), tier.weight).is_none(),
+                    "{}",
+                    ERR39_INVALID_NFT_TIERS
+                );
+            }
+        }
+        self.data_mut().nft_balance_seeds.insert(&seed_id, &nft_balance);
+        self.data_mut().nft_tiers.insert(&seed_id, &tiers);
+    }
+
+    /// set per farmer stake bounds of given FT seed, 0 means no limit.
+    pub fn modify_seed_stake_bounds(&mut self, seed_id: SeedId, min_stake: U128, max_stake: U128) {
+        self.assert_owner();
+        let mut farm_seed = self.get_seed(&seed_id);
+        assert_eq!(farm_seed.get_ref().seed_type, SeedType::FT, "{}", ERR37_NOT_FT_SEED);
+        assert!(max_stake.0 == 0 || min_stake.0 <= max_stake.0, "{}", ERR30_OUT_OF_STAKE_BOUNDS);
+        farm_seed.get_ref_mut().min_farmer_stake = min_stake.into();
+        farm_seed.get_ref_mut().max_farmer_stake = max_stake.into();
+        self.data_mut().seeds.insert(&seed_id, &farm_seed);
+    }
+
+    /// Move a seed whose token got a new address to new seed id, with its farms,
+    /// then move given farmers' stake and rps, paginated over farmers.
+    /// Call again with the same ids for the rest of farmers, then resume the new seed.
+    /// return number of farmers moved.
+    pub fn remap_seed(
+        &mut self,
+        old_seed_id: SeedId,
+        new_seed_id: SeedId,
+        farmer_ids: Vec<ValidAccountId>,
+    ) -> u64 {
+        self.assert_owner();
+        assert!(farmer_ids.len() as u64 <= MAX_REMAP_FARMERS, "{}", ERR15_INVALID_BATCH_SIZE);
+        match self.data().seed_remaps.get(&old_seed_id) {
+            Some(remapped) => assert_eq!(remapped, new_seed_id, "{}", ERR33_INVALID_SEED_ID),
+            None => self.internal_remap_seed(&old_seed_id, &new_seed_id),
+        }
+        let next_index = self.get_seed(&new_seed_id).get_ref().next_index;
+        let mut count = 0;
+        for farmer_id in farmer_ids {
+            if let Some(mut farmer) = self.get_farmer_wrapped(farmer_id.as_ref()) {
+                if farmer.get_ref_mut().remap_seed(&old_seed_id, &new_seed_id, next_index) {
+                    self.data_mut().farmers.insert(farmer_id.as_ref(), &farmer);
+                    count += 1;
+                }
+            }
+        }
+        env::log(
+            format!("seed {} remapped to {}, {} farmers moved", old_seed_id, new_seed_id, count)
+                .as_bytes(),
+        );
+        count
+    }
+
+    /// Add given registered farmers to the farmer list walked by paged views,
+    /// for farmers registered before the list existed, paginated over farmers.
+    /// return number of farmers added.
+    pub fn backfill_farmer_ids(&mut self, farmer_ids: Vec<ValidAccountId>) -> u64 {
+        self.assert_owner();
+        assert!(farmer_ids.len() as u64 <= MAX_BATCH_ACCOUNTS, "{}", ERR15_INVALID_BATCH_SIZE);
+        let mut count = 0;
+        for farmer_id in farmer_ids {
+            if self.data().farmers.contains_key(farmer_id.as_ref())
+                && self.data_mut().farmer_ids.insert(farmer_id.as_ref())
+            {
+                count += 1;
+            }
+        }
+        env::log(format!("{} farmers backfilled", count).as_bytes());
+        count
+    }
+
+    /// Accept deposits of another token as given FT seed, e.g. the new contract of a migrated LP token,
+    /// farmers get back the token they deposited on withdrawal.
+    pub fn add_seed_alias(&mut self, seed_id: SeedId, token_id: ValidAccountId) {
+        self.assert_owner();
+        let token_id: AccountId = token_id.into();
+        let mut farm_seed = self.get_seed(&seed_id);
+        assert!(
+            farm_seed.get_ref().seed_type == SeedType::FT
+                && !seed_id.contains(MFT_TAG)
+                && token_id != seed_id
+                && self.get_seed_wrapped(&token_id).is_none()
+                && self.data().seed_aliases.get(&token_id).is_none(),
+            "{}",
+            ERR59_INVALID_SEED_ALIAS
+        );
+        farm_seed.get_ref_mut().aliases.insert(token_id.clone(), 0);
+        self.data_mut().seeds.insert(&seed_id, &farm_seed);
+        self.data_mut().seed_aliases.insert(&token_id, &seed_id);
+        env::log(format!("{} staked as seed {}", token_id, seed_id).as_bytes());
+    }
+
+    /// For support cases, withdraw given staked nft on behalf of the farmer staking it,
+    /// the nft always goes back to that farmer.
+    pub fn admin_return_nft(
+        &mut self,
+        account_id: ValidAccountId,
+        seed_id: SeedId,
+        nft_contract_id: String,
+        nft_token_id: NFTTokenId,
+    ) {
+        self.assert_owner();
+        assert_eq!(
+            self.get_seed(&seed_id).get_ref().seed_type,
+            SeedType::NFT,
+            "{}",
+            ERR38_NOT_NFT_SEED
+        );
+        env::log(
+            format!(
+                "owner returns nft {}@{} of seed {} to {}",
+                nft_contract_id, nft_token_id, seed_id, account_id
+            )
+            .as_bytes(),
+        );
+        self.internal_execute_withdraw_nft(seed_id, account_id.into(), nft_contract_id, nft_token_id, None);
+    }
+
+    /// stop new deposits into given seed, existing farmers keep farming,
+    /// claiming and withdrawing.
+    pub fn pause_seed(&mut self, seed_id: SeedId) {
+        self.assert_owner();
+        let mut farm_seed = self.get_seed(&seed_id);
+        if !farm_seed.get_ref().paused {
+            farm_seed.get_ref_mut().paused = true;
+            self.data_mut().seeds.insert(&seed_id, &farm_seed);
+            self.data_mut().paused_seed_count += 1;
+        }
+    }
+
+    /// accept deposits into given seed again.
+    pub fn resume_seed(&mut self, seed_id: SeedId) {
+        self.assert_owner();
+        let mut farm_seed = self.get_seed(&seed_id);
+        if farm_seed.get_ref().paused {
+            farm_seed.get_ref_mut().paused = false;
+            self.data_mut().seeds.insert(&seed_id, &farm_seed);
+            self.data_mut().paused_seed_count -= 1;
+        }
+    }
+
+    /// set protocol fee of given farm, in bps of FEE_DIVISOR
+    pub fn modify_farm_fee(&mut self, farm_id: FarmId, fee_bps: u16) {
+        self.assert_owner();
+        assert!(fee_bps as u32 <= FEE_DIVISOR, "{}", ERR45_INVALID_FEE_BPS);
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        farm.fee_bps = fee_bps;
+        self.internal_save_farm(&farm_id, &farm);
+    }
+
+    /// cap reward of given farm going to beneficiary, in bps of its total reward, 0 to disable.
+    pub fn modify_farm_beneficiary_cap(&mut self, farm_id: FarmId, cap_bps: u16) {
+        self.assert_owner();
+        assert!(cap_bps as u32 <= FEE_DIVISOR, "{}", ERR45_INVALID_FEE_BPS);
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        // settle rounds passed under the previous cap first
+        let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+        farm.distribute(&farm.effective_seeds(seed_amount), true);
+        farm.beneficiary_cap_bps = cap_bps;
+        self.internal_save_farm(&farm_id, &farm);
+    }
+
+    /// Fetch and cache decimals of given farm's reward token from its metadata,
+    /// left unset if the token has no metadata.
+    pub fn fetch_reward_token_decimals(&mut self, farm_id: FarmId) {
+        self.assert_owner();
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        let reward_token = farm.get_reward_token();
+        if reward_token == NEAR_REWARD_TOKEN {
+            farm.reward_token_decimals = Some(NEAR_DECIMALS);
+            self.internal_save_farm(&farm_id, &farm);
+        } else {
+            ext_fungible_token::ft_metadata(&reward_token, 0, GAS_FOR_FT_METADATA).then(
+                ext_self::callback_post_ft_metadata(
+                    farm_id,
+                    &env::current_account_id(),
+                    0,
+                    GAS_FOR_FT_METADATA_CALLBACK,
+                ),
+            );
+        }
+    }
+
+    /// release reward of given farm at a new reward_per_session from the current round on.
+    pub fn modify_farm_reward_rate(&mut self, farm_id: FarmId, reward_per_session: U128) {
+        self.assert_owner();
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+        let change = farm
+            .change_reward_rate(&farm.effective_seeds(seed_amount), reward_per_session.into())
+            .expect(ERR43_INVALID_FARM_STATUS);
+        assert!(farm.terms.reward_per_round() > 0, "{}", ERR57_INVALID_REWARD_RATE);
+        self.assert_reward_tokens(&farm.terms.reward_tokens());
+        // a decaying farm at a lower rate may never release all its reward
+        assert!(farm.within_decayed_total(0), "{}", ERR63_ABOVE_DECAYED_TOTAL);
+        self.internal_save_farm(&farm_id, &farm);
+        emit_farm_rate_changed(FarmRateChangedData {
+            farm_id,
+            old_reward_per_session: change.old_reward_per_session,
+            new_reward_per_session: change.new_reward_per_session,
+            effective_round: change.effective_round,
+        });
+    }
+
+    /// record a snapshot of given farm for APR charts, meant to be called periodically by a keeper.
+    pub fn snapshot_farm(&mut self, farm_id: FarmId) {
+        self.assert_owner();
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+        farm.take_snapshot(farm.effective_seeds(seed_amount));
+        self.internal_save_farm(&farm_id, &farm);
+    }
+
+    /// defer claims from given farm below min_claim_amount, 0 to disable.
+    pub fn modify_farm_min_claim_amount(&mut self, farm_id: FarmId, min_claim_amount: U128) {
+        self.assert_owner();
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        farm.min_claim_amount = min_claim_amount.into();
+        self.internal_save_farm(&farm_id, &farm);
+    }
+
+    /// let the last staker of given farm take all unclaimed dust once the farm ended
+    pub fn modify_farm_sweep_dust(&mut self, farm_id: FarmId, sweep_dust: bool) {
+        self.assert_owner();
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        farm.sweep_dust = sweep_dust;
+        self.internal_save_farm(&farm_id, &farm);
+    }
+
+    /// Bring distribution of given farm up to date against current seed total,
+    /// no farmer is touched.
+    pub fn touch_farm(&mut self, farm_id: FarmId) -> FarmInfo {
+        self.assert_owner();
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+        farm.distribute(&farm.effective_seeds(seed_amount), false);
+        self.internal_save_farm(&farm_id, &farm);
+        env::log(
+            format!(
+                "farm {} touched, round {}, unclaimed {}, undistributed {}",
+                farm_id,
+                farm.last_distribution.rr,
+                farm.last_distribution.unclaimed,
+                farm.last_distribution.undistributed,
+            )
+            .as_bytes(),
+        );
+        (&farm).into()
+    }
+
+    /// set who reward of given farm goes to when nothing is staked,
+    /// reward went to the previous one stays attributed to it.
+    pub fn set_farm_beneficiary(&mut self, farm_id: FarmId, new_beneficiary: ValidAccountId) {
+        self.set_farm_beneficiaries(farm_id, vec![(new_beneficiary, FEE_DIVISOR as u16)]);
+    }
+
+    /// split reward of given farm when nothing is staked among beneficiaries,
+    /// by weights in bps of FEE_DIVISOR summing up to FEE_DIVISOR.
+    pub fn set_farm_beneficiaries(&mut self, farm_id: FarmId, beneficiaries: Vec<(ValidAccountId, u16)>) {
+        self.assert_owner();
+        assert!(
+            !beneficiaries.is_empty()
+                && beneficiaries.len() <= MAX_FARM_BENEFICIARIES
+                && beneficiaries.iter().map(|(_, bps)| *bps as u32).sum::<u32>() == FEE_DIVISOR,
+            "{}",
+            ERR53_INVALID_BENEFICIARIES
+        );
+        let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+        // settle rounds passed to the previous beneficiaries first
+        let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+        farm.distribute(&farm.effective_seeds(seed_amount), true);
+        farm.beneficiaries = beneficiaries
+            .into_iter()
+            .map(|(beneficiary_id, bps)| (beneficiary_id.into(), bps))
+            .collect();
+        self.internal_save_farm(&farm_id, &farm);
+    }
+
+    /// Emergency stop of reward accrual, settle and pause running farms in given range,
+    /// return number of farms paused.
+    pub fn freeze_all_farms(&mut self, from_index: u64, limit: u64) -> u64 {
+        self.assert_owner();
+        let keys = self.data().farms.keys_as_vector();
+        let farm_ids: Vec<FarmId> = (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .map(|index| keys.get(index).unwrap())
+            .collect();
+        let mut count = 0;
+        for farm_id in farm_ids {
+            let mut farm = self.data().farms.get(&farm_id).unwrap();
+            let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+            if farm.pause(&farm.effective_seeds(seed_amount)) {
+                self.internal_save_farm(&farm_id, &farm);
+                count += 1;
+            }
+        }
+        env::log(format!("{} farms frozen", count).as_bytes());
+        count
+    }
+
+    /// Resume paused farms in given range, return number of farms resumed.
+    pub fn unfreeze_all_farms(&mut self, from_index: u64, limit: u64) -> u64 {
+        self.assert_owner();
+        let keys = self.data().farms.keys_as_vector();
+        let farm_ids: Vec<FarmId> = (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .map(|index| keys.get(index).unwrap())
+            .collect();
+        let mut count = 0;
+        for farm_id in farm_ids {
+            let mut farm = self.data().farms.get(&farm_id).unwrap();
+            if farm.resume() {
+                self.internal_save_farm(&farm_id, &farm);
+                count += 1;
+            }
+        }
+        env::log(format!("{} farms unfrozen", count).as_bytes());
+        count
+    }
+
+    /// withdraw accumulated protocol fee of given token to owner,
+    /// if amount is None, withdraw all.
+    #[payable]
+    pub fn withdraw_protocol_fee(&mut self, token_id: ValidAccountId, amount: Option<U128>) {
+        assert_one_yocto();
+        self.assert_owner();
+        let token_id: AccountId = token_id.into();
+        let fee = self.data().protocol_fees.get(&token_id).expect(ERR21_TOKEN_NOT_REG);
+        let amount = amount.map(|a| a.0).unwrap_or(fee);
+        assert!(amount <= fee, "{}", ERR22_NOT_ENOUGH_TOKENS);
+        if amount == fee {
+            self.data_mut().protocol_fees.remove(&token_id);
+        } else {
+            self.data_mut().protocol_fees.insert(&token_id, &(fee - amount));
+        }
+        self.internal_ledger_sub_held(&token_id, amount);
+        self.internal_ledger_add_pending_out(&token_id, amount);
+        transfer_reward(&token_id, self.data().owner_id.clone(), amount, None)
+        .then(ext_self::callback_post_withdraw_protocol_fee(
+            token_id,
+            amount.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+    }
+
+    #[private]
+    pub fn callback_post_withdraw_protocol_fee(&mut self, token_id: AccountId, amount: U128) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        self.internal_ledger_sub_pending_out(&token_id, amount.0);
+        match env::promise_result(0) {
+            PromiseResult::NotReady => unreachable!(),
+            PromiseResult::Successful(_) => {
+                env::log(
+                    format!("withdraw protocol fee {} amount {}, Succeed.", token_id, amount.0)
+                        .as_bytes(),
+                );
+            }
+            PromiseResult::Failed => {
+                env::log(
+                    format!(
+                        "withdraw protocol fee {} amount {}, Callback Failed.",
+                        token_id, amount.0
+                    )
+                    .as_bytes(),
+                );
+                self.internal_add_protocol_fee(&token_id, amount.0);
+                self.internal_ledger_add_held(&token_id, amount.0);
+            }
+        };
+    }
+
+    /// Send out given token stuck in this contract, such as sent by plain ft_transfer,
+    /// only the part of real balance beyond what the contract owes to anyone can go.
+    #[payable]
+    pub fn recover_foreign_ft(&mut self, token_id: ValidAccountId, amount: U128, to: ValidAccountId) {
+        assert_one_yocto();
+        self.assert_owner();
+        let token_id: AccountId = token_id.into();
+        ext_fungible_token::ft_balance_of(
+            env::current_account_id(),
+            &token_id,
+            0,
+            GAS_FOR_FT_BALANCE_OF,
+        )
+        .then(ext_self::callback_recover_foreign_ft(
+            token_id,
+            amount,
+            to.into(),
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+    }
+
+    #[private]
+    pub fn callback_recover_foreign_ft(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+        receiver_id: AccountId,
+    ) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        let balance = match env::promise_result(0) {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice::<U128>(&value)
+                .expect(ERR500)
+                .0,
+            _ => env::panic(ERR500.as_bytes()),
+        };
+        // be conservative, take the larger of recorded deposits and computed liabilities,
+        // tokens of unresolved transfers out are still in the balance but not ours.
+        let (held, owed) = self.internal_token_solvency(&token_id);
+        let pending_out = self.data().token_ledger.get(&token_id).unwrap_or_default().pending_out;
+        let recoverable = balance.saturating_sub(std::cmp::max(held, owed) + pending_out);
+        assert!(amount.0 <= recoverable, "{}", ERR52_NOT_ENOUGH_RECOVERABLE);
+        env::log(
+            format!("recover {} amount {} to {}", token_id, amount.0, receiver_id).as_bytes(),
+        );
+        self.internal_ledger_add_pending_out(&token_id, amount.0);
+        ext_fungible_token::ft_transfer(
+            receiver_id,
+            amount,
+            None,
+            &token_id,
+            1,
+            GAS_FOR_FT_TRANSFER,
+        )
+        .then(ext_self::callback_post_recover_foreign_ft(
+            token_id,
+            amount,
+            &env::current_account_id(),
+            0,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+    }
+
+    #[private]
+    pub fn callback_post_recover_foreign_ft(&mut self, token_id: AccountId, amount: U128) {
+        assert_eq!(
+            env::promise_results_count(),
+            1,
+            "{}",
+            ERR25_CALLBACK_POST_WITHDRAW_INVALID
+        );
+        self.internal_ledger_sub_pending_out(&token_id, amount.0);
+        if let PromiseResult::Failed = env::promise_result(0) {
+            env::log(
+                format!("recover {} amount {}, Callback Failed.", token_id, amount.0).as_bytes(),
+            );
+        }
+    }
+
     pub(crate) fn assert_owner(&self) {
         assert_eq!(
             env::predecessor_account_id(),
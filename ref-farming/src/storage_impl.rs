@@ -10,7 +10,7 @@ use near_sdk::{assert_one_yocto, env, near_bindgen, Promise, Balance};
 use crate::errors::*;
 use crate::*;
 use crate::farmer::MIN_FARMER_LENGTH;
-use crate::utils::MAX_ACCOUNT_LENGTH;
+use crate::utils::{MAX_ACCOUNT_LENGTH, MAX_BATCH_ACCOUNTS, NEAR_REWARD_TOKEN};
 
 
 
@@ -46,7 +46,11 @@ impl StorageManagement for Contract {
             }
         } else {  // old account, only can complement storage fee
             if registration_only {
-                env::panic(format!("{}", ERR14_ACC_ALREADY_REGISTERED).as_bytes());
+                // as NEP-145 asks, refund the full deposit of an already registered account
+                env::log(ERR14_ACC_ALREADY_REGISTERED.as_bytes());
+                if amount > 0 {
+                    Promise::new(env::predecessor_account_id()).transfer(amount);
+                }
             } else {
                 if amount+deposited < locked {
                     env::panic(format!("{}", ERR11_INSUFFICIENT_STORAGE).as_bytes());
@@ -100,6 +104,7 @@ impl StorageManagement for Contract {
                 "{}", ERR13_STORAGE_UNREGISTER_SEED_NOT_EMPTY
             );
             self.data_mut().farmers.remove(&account_id);
+            self.data_mut().farmer_ids.remove(&account_id);
             self.data_mut().farmer_count -= 1;
             // TODO: should make sure tranfer is OK with a callback
             Promise::new(account_id.clone()).transfer(farmer.get_ref().amount);
@@ -129,6 +134,48 @@ impl StorageManagement for Contract {
     }
 }
 
+#[near_bindgen]
+impl Contract {
+    /// Register (or complement storage fee for) a batch of accounts in one call.
+    /// If amount_per_account is None, attached deposit is split evenly among accounts,
+    /// each share should cover the suggested min storage usage.
+    /// The left over is refunded to the caller.
+    #[payable]
+    pub fn storage_deposit_many(
+        &mut self,
+        accounts: Vec<ValidAccountId>,
+        amount_per_account: Option<U128>,
+    ) -> Vec<StorageBalance> {
+        assert!(
+            !accounts.is_empty() && accounts.len() as u64 <= MAX_BATCH_ACCOUNTS,
+            "{}",
+            ERR15_INVALID_BATCH_SIZE
+        );
+        let amount = env::attached_deposit();
+        let per_account = amount_per_account
+            .map(|a| a.0)
+            .unwrap_or(amount / accounts.len() as u128);
+        if per_account < Contract::suggested_min_storage_usage()
+            || per_account * accounts.len() as u128 > amount
+        {
+            env::panic(ERR11_INSUFFICIENT_STORAGE.as_bytes());
+        }
+
+        for account_id in accounts.iter() {
+            self.internal_register_account(account_id.as_ref(), per_account);
+        }
+
+        let refund = amount - per_account * accounts.len() as u128;
+        if refund > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(refund);
+        }
+        accounts
+            .into_iter()
+            .map(|account_id| self.storage_balance_of(account_id).unwrap())
+            .collect()
+    }
+}
+
 impl Contract {
 
     /// return storage used by given account, and his deposited storage fee 
@@ -159,6 +206,39 @@ impl Contract {
         );
     }
 
+    /// Same as assert_storage_usage, after covering a shortfall from near_claimed,
+    /// the native NEAR reward the account just claimed, if the account opted in
+    /// with set_storage_top_up, so a claim growing storage is not blocked by it.
+    pub(crate) fn assert_claim_storage_usage(&mut self, account_id: &AccountId, near_claimed: Balance) {
+        let (locked, deposited) = self.internal_farmer_storage(account_id);
+        let opted_in = self.get_farmer_wrapped(account_id).map_or(false, |farmer| farmer.get_ref().storage_top_up);
+        if opted_in && near_claimed > 0 && locked > deposited {
+            let near_token: AccountId = NEAR_REWARD_TOKEN.to_string();
+            let mut farmer = self.get_farmer(account_id);
+            let near_reward = *farmer.get_ref().rewards.get(&near_token).unwrap_or(&0);
+            let amount = std::cmp::min(locked - deposited, std::cmp::min(near_claimed, near_reward));
+            if amount > 0 {
+                farmer.get_ref_mut().sub_reward(&near_token, amount);
+                farmer.get_ref_mut().amount += amount;
+                self.data_mut().farmers.insert(account_id, &farmer);
+                self.internal_ledger_sub_farmer_rewards(&near_token, amount);
+                self.internal_ledger_sub_held(&near_token, amount);
+                env::log(
+                    format!("{} storage topped up with {} of NEAR reward", account_id, amount).as_bytes(),
+                );
+            }
+        }
+        self.assert_storage_usage(account_id);
+    }
+
+    /// claimed from given farm if its reward token is native NEAR, else 0.
+    pub(crate) fn internal_near_claimed(&self, farm_id: &FarmId, claimed: Balance) -> Balance {
+        match self.data().farms.get(farm_id) {
+            Some(farm) if farm.get_reward_token() == NEAR_REWARD_TOKEN => claimed,
+            _ => 0,
+        }
+    }
+
     /// Returns minimal storage usage possible.
     /// 5 reward tokens, 5 seed tokens, 10 farms as assumption.
     pub(crate) fn suggested_min_storage_usage() -> Balance {
@@ -177,6 +257,7 @@ impl Contract {
             self.data_mut().farmers.insert(&account_id, &farmer);
         } else {
             self.data_mut().farmers.insert(&account_id, &VersionedFarmer::new(account_id.clone(), amount));
+            self.data_mut().farmer_ids.insert(account_id);
             self.data_mut().farmer_count += 1;
         }
     }
@@ -1,4 +1,7 @@
 use crate::errors::*;
+use crate::events::{
+    emit_ft_transfer_routed, FtTransferRoutedData, ROUTE_REFUND, ROUTE_REWARD, ROUTE_SEED,
+};
 use crate::farm_seed::SeedType;
 use crate::utils::{MFT_TAG, FT_INDEX_TAG};
 use crate::*;
@@ -34,29 +37,92 @@ impl FungibleTokenReceiver for Contract {
 
         if msg.is_empty() {
             // ****** seed Token deposit in ********
+            let token_id = env::predecessor_account_id();
+            // an alias token is staked as the seed it stands for
+            let seed_id = self.data().seed_aliases.get(&token_id).unwrap_or_else(|| token_id.clone());
 
             // if seed not exist, it will panic
-            let seed_farm = self.get_seed(&env::predecessor_account_id());
+            let seed_farm = self.get_seed(&seed_id);
 
-            assert_eq!(seed_farm.get_ref().seed_type, SeedType::FT, "Cannot deposit FT to this seed");
+            if seed_farm.get_ref().seed_type != SeedType::FT {
+                return refund_ft_transfer(
+                    format!("seed {} is not a FT seed", seed_id),
+                    sender,
+                    amount,
+                    Some(seed_id),
+                    None,
+                );
+            }
+
+            if seed_farm.get_ref().paused {
+                return refund_ft_transfer(
+                    format!("seed {} is paused", seed_id),
+                    sender,
+                    amount,
+                    Some(seed_id),
+                    None,
+                );
+            }
 
             if amount < seed_farm.get_ref().min_deposit {
-                env::panic(
-                    format!(
-                        "{} {}",
-                        ERR34_BELOW_MIN_SEED_DEPOSITED,
-                        seed_farm.get_ref().min_deposit
-                    )
-                    .as_bytes(),
-                )
+                return refund_ft_transfer(
+                    format!("{} {}", ERR34_BELOW_MIN_SEED_DEPOSITED, seed_farm.get_ref().min_deposit),
+                    sender,
+                    amount,
+                    Some(seed_id),
+                    None,
+                );
             }
 
+            if self.get_farmer_wrapped(&sender).is_none() {
+                // transfers carry no NEAR to pay for registration
+                return refund_ft_transfer(
+                    format!("{}: {}", ERR17_STAKER_NOT_REGISTERED, sender),
+                    sender,
+                    amount,
+                    Some(seed_id),
+                    None,
+                );
+            }
+
+            let current = *self
+                .get_farmer(&sender)
+                .get_ref()
+                .seeds
+                .get(&seed_id)
+                .unwrap_or(&0);
+            let accepted = match seed_farm.get_ref().bound_farmer_stake(current, amount) {
+                Some(accepted) if accepted > 0 => accepted,
+                Some(_) => {
+                    return refund_ft_transfer(
+                        format!("{} reached max stake of seed {}", sender, seed_id),
+                        sender,
+                        amount,
+                        Some(seed_id),
+                        None,
+                    );
+                }
+                None => {
+                    return refund_ft_transfer(
+                        ERR30_OUT_OF_STAKE_BOUNDS.to_string(),
+                        sender,
+                        amount,
+                        Some(seed_id),
+                        None,
+                    );
+                }
+            };
+
             self.internal_seed_deposit(
-                &env::predecessor_account_id(),
+                &seed_id,
                 &sender,
-                amount.into(),
+                accepted,
                 SeedType::FT,
             );
+            if token_id != seed_id {
+                self.internal_alias_seed_deposit(&seed_id, &token_id, &sender, accepted);
+            }
+            self.internal_ledger_add_held(&token_id, accepted);
 
             self.assert_storage_usage(&sender);
 
@@ -64,52 +130,100 @@ impl FungibleTokenReceiver for Contract {
                 format!(
                     "{} deposit FT seed {} with amount {}.",
                     sender,
-                    env::predecessor_account_id(),
-                    amount,
+                    seed_id,
+                    accepted,
                 )
                 .as_bytes(),
             );
-            PromiseOrValue::Value(U128(0))
+            emit_ft_transfer_routed(FtTransferRoutedData {
+                route: ROUTE_SEED.to_string(),
+                token_id,
+                sender_id: sender,
+                amount: U128(amount),
+                refund: U128(amount - accepted),
+                seed_id: Some(seed_id),
+                farm_id: None,
+            });
+            PromiseOrValue::Value(U128(amount - accepted))
         } else {
             // ****** reward Token deposit in ********
             let farm_id = msg
                 .parse::<FarmId>()
                 .expect(&format!("{}", ERR42_INVALID_FARM_ID));
-            let mut farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
-
-            // update farm
-            assert_eq!(
-                farm.get_reward_token(),
-                env::predecessor_account_id(),
-                "{}",
-                ERR44_INVALID_FARM_REWARD
-            );
-            if let Some(cur_remain) = farm.add_reward(&amount) {
-                self.data_mut().farms.insert(&farm_id, &farm);
-                let old_balance = self
-                    .data()
-                    .reward_info
-                    .get(&env::predecessor_account_id())
-                    .unwrap_or(0);
-                self.data_mut()
-                    .reward_info
-                    .insert(&env::predecessor_account_id(), &(old_balance + amount));
-
-                env::log(
-                    format!(
-                        "{} added {} Reward Token, Now has {} left",
-                        sender, amount, cur_remain
-                    )
-                    .as_bytes(),
+            let farm = self.data().farms.get(&farm_id).expect(ERR41_FARM_NOT_EXIST);
+            if farm.get_reward_token() != env::predecessor_account_id() {
+                // only the farm's reward token contract itself can fund it
+                return refund_ft_transfer(
+                    format!("{}: {}", ERR44_INVALID_FARM_REWARD, farm_id),
+                    sender,
+                    amount,
+                    None,
+                    Some(farm_id),
+                );
+            }
+            if !farm.sustains_min_rounds(amount) {
+                return refund_ft_transfer(
+                    format!("{}: {}", ERR54_BELOW_MIN_ROUNDS, farm_id),
+                    sender,
+                    amount,
+                    None,
+                    Some(farm_id),
+                );
+            }
+            if !farm.within_decayed_total(amount) {
+                return refund_ft_transfer(
+                    format!("{}: {}", ERR63_ABOVE_DECAYED_TOTAL, farm_id),
+                    sender,
+                    amount,
+                    None,
+                    Some(farm_id),
                 );
-                PromiseOrValue::Value(U128(0))
-            } else {
-                env::panic(format!("{}", ERR43_INVALID_FARM_STATUS).as_bytes())
             }
+            self.internal_deposit_reward(&farm_id, &env::predecessor_account_id(), &sender, amount);
+            emit_ft_transfer_routed(FtTransferRoutedData {
+                route: ROUTE_REWARD.to_string(),
+                token_id: env::predecessor_account_id(),
+                sender_id: sender,
+                amount: U128(amount),
+                refund: U128(0),
+                seed_id: None,
+                farm_id: Some(farm_id),
+            });
+            PromiseOrValue::Value(U128(0))
         }
     }
 }
 
+/// Log why a transfer is refunded and return all of it as unused.
+fn refund_ft_transfer(
+    reason: String,
+    sender: AccountId,
+    amount: Balance,
+    seed_id: Option<SeedId>,
+    farm_id: Option<FarmId>,
+) -> PromiseOrValue<U128> {
+    env::log(
+        format!(
+            "{}, refund {} {} to {}.",
+            reason,
+            amount,
+            env::predecessor_account_id(),
+            sender,
+        )
+        .as_bytes(),
+    );
+    emit_ft_transfer_routed(FtTransferRoutedData {
+        route: ROUTE_REFUND.to_string(),
+        token_id: env::predecessor_account_id(),
+        sender_id: sender,
+        amount: U128(amount),
+        refund: U128(amount),
+        seed_id,
+        farm_id,
+    });
+    PromiseOrValue::Value(U128(amount))
+}
+
 enum TokenOrPool {
     Token(AccountId),
     Pool(u64),
@@ -160,6 +274,19 @@ impl NonFungibleTokenReceiver for Contract {
             "Paras(farming): owner_id should be signer_id"
         );
 
+        if self.get_seed_wrapped(&msg).map_or(false, |fs| fs.get_ref().paused) {
+            env::log(format!("seed {} is paused, return nft {}.", msg, token_id).as_bytes());
+            return PromiseOrValue::Value(true);
+        }
+
+        if self.get_farmer_wrapped(&previous_owner_id).is_none() {
+            env::log(
+                format!("{}: {}, return nft {}.", ERR17_STAKER_NOT_REGISTERED, previous_owner_id, token_id)
+                    .as_bytes(),
+            );
+            return PromiseOrValue::Value(true);
+        }
+
         let deposit_res = self.internal_nft_deposit(&msg, &previous_owner_id.to_string(), &nft_contract_id, &token_id);
         if !deposit_res {
             panic!("Paras(farming): nft token does not exist on seed");
@@ -1,6 +1,7 @@
 
 use near_sdk::json_types::{U128};
-use near_sdk::{Balance, env, ext_contract, Gas, Timestamp};
+use near_sdk::serde::Deserialize;
+use near_sdk::{AccountId, Balance, env, ext_contract, Gas, Promise, Timestamp};
 use uint::construct_uint;
 use crate::{SeedId, FarmId, NftBalance};
 use crate::errors::*;
@@ -12,15 +13,66 @@ pub type TimestampSec = u32;
 
 pub const MIN_SEED_DEPOSIT: u128 = 1_000_000_000_000_000_000;
 pub const MAX_ACCOUNT_LENGTH: u128 = 64;
+/// Max number of accounts handled in one batch call, to keep it in gas limit.
+pub const MAX_BATCH_ACCOUNTS: u64 = 50;
+/// Max number of farms handled in one batch call.
+pub const MAX_BATCH_FARMS: u64 = 50;
+/// Max number of farmers moved by one remap_seed call.
+pub const MAX_REMAP_FARMERS: u64 = 20;
+/// Max number of positions returned by get_farmer_positions_ranked.
+pub const MAX_RANKED_POSITIONS: usize = 50;
+/// Max number of farms returned by get_account_farms.
+pub const MAX_ACCOUNT_FARMS: usize = 50;
+/// Max number of farms returned by get_emissions_overview.
+pub const MAX_EMISSIONS_OVERVIEW: u64 = 50;
 /// Amount of gas for fungible token transfers.
 pub const GAS_FOR_FT_TRANSFER: Gas = 10_000_000_000_000;
 pub const GAS_FOR_NFT_TRANSFER: Gas = 50_000_000_000_000;
+/// Max nfts withdrawn by one withdraw_all_seed call,
+/// each takes GAS_FOR_NFT_TRANSFER and GAS_FOR_RESOLVE_TRANSFER.
+pub const MAX_NFT_WITHDRAW_PER_CALL: usize = 2;
+/// Amount of gas for notifying farmer's on_claim_receiver, kept small as it is best-effort.
+pub const GAS_FOR_CLAIM_NOTIFY: Gas = 10_000_000_000_000;
+/// Max number of farms claimed by one page of claim_reward_by_seed.
+pub const CLAIM_FARMS_PER_PAGE: u32 = 5;
+/// Gas left required to claim another page of farms in claim_reward_by_seed.
+pub const GAS_FOR_CLAIM_PAGE: Gas = 30_000_000_000_000;
+pub const GAS_FOR_FT_BALANCE_OF: Gas = 10_000_000_000_000;
+pub const GAS_FOR_FT_METADATA: Gas = 10_000_000_000_000;
+pub const GAS_FOR_FT_METADATA_CALLBACK: Gas = 10_000_000_000_000;
+/// Decimals of native NEAR, for farms rewarding in NEAR_REWARD_TOKEN.
+pub const NEAR_DECIMALS: u8 = 24;
 
 pub const GAS_FOR_RESOLVE_TRANSFER: Gas = 50_000_000_000_000;
 pub const MFT_TAG: &str = "@";
 pub const FT_INDEX_TAG: &str = "$";
 pub const NFT_DELIMETER: &str = "@";
 pub const PARAS_SERIES_DELIMETER: &str = ":";
+/// Max number of nft tiers per seed.
+pub const MAX_NFT_TIERS: usize = 10;
+/// Max number of reward tokens of a farm, as claims of the farm go through each of them.
+pub const MAX_REWARD_TOKENS_PER_FARM: usize = 5;
+/// Max number of farms in a seed at the same time,
+/// as deposits and withdraws on the seed settle all of them in one call,
+/// which takes at most 4 pages of GAS_FOR_CLAIM_PAGE.
+pub const MAX_FARMS_PER_SEED: usize = 16;
+/// Max number of funders recorded per farm, as their storage is not paid by them.
+pub const MAX_FARM_FUNDERS: usize = 5;
+/// funders beyond MAX_FARM_FUNDERS are recorded together under this key.
+pub const OTHER_FUNDERS: &str = "*";
+/// Max number of beneficiaries a farm splits its reward among.
+pub const MAX_FARM_BENEFICIARIES: usize = 5;
+/// Max number of reward rate changes kept per farm, older ones are dropped.
+pub const MAX_FARM_RATE_CHANGES: usize = 5;
+/// Max number of snapshots kept per farm, older ones are dropped.
+pub const MAX_FARM_SNAPSHOTS: usize = 24;
+/// Max shortfall of a farm's unclaimed reward against a claim, in yocto,
+/// taken as rounding dust and clamped instead of failing the claim.
+pub const CLAIM_DUST_TOLERANCE: Balance = 2;
+/// Max cut of claimed reward going to the farmer's referrer, in bps.
+pub const MAX_REFERRAL_BPS: u16 = 1000;
+/// reward token of farms distributing native NEAR, funded by fund_farm_near.
+pub const NEAR_REWARD_TOKEN: &str = "near";
 
 
 construct_uint! {
@@ -32,6 +84,15 @@ construct_uint! {
 #[ext_contract(ext_fungible_token)]
 pub trait FungibleToken {
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
+    fn ft_balance_of(&self, account_id: AccountId) -> U128;
+    fn ft_metadata(&self) -> FtMetadata;
+}
+
+/// The part of NEP-148 fungible token metadata this contract cares about.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FtMetadata {
+    pub decimals: u8,
 }
 
 #[ext_contract(ext_non_fungible_token)]
@@ -45,6 +106,12 @@ pub trait NonFungibleToken {
     );
 }
 
+/// Implemented by strategy contracts that react to a farmer's claimed rewards.
+#[ext_contract(ext_claim_receiver)]
+pub trait ClaimReceiver {
+    fn on_reward_claimed(&mut self, farmer_id: AccountId, claimed: HashMap<AccountId, U128>);
+}
+
 #[ext_contract(ext_self)]
 pub trait TokenPostActions {
     fn callback_post_withdraw_reward(
@@ -54,6 +121,46 @@ pub trait TokenPostActions {
         amount: U128,
     );
 
+    fn callback_post_withdraw_escrow(
+        &mut self,
+        token_id: AccountId,
+        sender_id: AccountId,
+        amount: U128,
+    );
+
+    fn callback_post_clawback(
+        &mut self,
+        farm_id: FarmId,
+        amount: U128,
+    );
+
+    fn callback_post_ft_metadata(&mut self, farm_id: FarmId);
+
+    fn callback_post_reclaim_leftover(
+        &mut self,
+        farm_id: FarmId,
+        amount: U128,
+    );
+
+    fn callback_post_withdraw_protocol_fee(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+    );
+
+    fn callback_recover_foreign_ft(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+        receiver_id: AccountId,
+    );
+
+    fn callback_post_recover_foreign_ft(
+        &mut self,
+        token_id: AccountId,
+        amount: U128,
+    );
+
     fn callback_post_withdraw_ft_seed(
         &mut self,
         seed_id: SeedId,
@@ -61,20 +168,56 @@ pub trait TokenPostActions {
         amount: U128,
     );
 
-    fn callback_post_withdraw_mft_seed(
+    fn callback_post_withdraw_alias_seed(
         &mut self,
         seed_id: SeedId,
+        token_id: AccountId,
         sender_id: AccountId,
         amount: U128,
     );
 
-    fn callback_post_withdraw_nft(
+    fn callback_post_withdraw_mft_seed(
         &mut self,
         seed_id: SeedId,
         sender_id: AccountId,
-        nft_contract_id: String,
-        nft_token_id: String
+        amount: U128,
     );
+
+}
+
+pub use self::nft_post_actions::ext_self_nft;
+
+// kept apart from ext_self, as its generated call takes account, deposit and gas
+// on top of these args, which is more than clippy allows.
+#[allow(clippy::too_many_arguments)]
+mod nft_post_actions {
+    use super::*;
+
+    #[ext_contract(ext_self_nft)]
+    pub trait NftPostActions {
+        fn callback_post_withdraw_nft(
+            &mut self,
+            seed_id: SeedId,
+            sender_id: AccountId,
+            nft_contract_id: String,
+            nft_token_id: String,
+            weight: Option<U128>,
+        );
+    }
+}
+
+/// Send reward out, native NEAR for NEAR_REWARD_TOKEN, else through ft_transfer.
+pub(crate) fn transfer_reward(
+    token_id: &AccountId,
+    receiver_id: AccountId,
+    amount: Balance,
+    memo: Option<String>,
+) -> Promise {
+    if token_id == NEAR_REWARD_TOKEN {
+        Promise::new(receiver_id).transfer(amount)
+    } else {
+        ext_fungible_token::ft_transfer(receiver_id, amount.into(), memo, token_id, 1, GAS_FOR_FT_TRANSFER)
+    }
 }
 
 /// Assert that 1 yoctoNEAR was attached.
@@ -104,6 +247,7 @@ pub fn gen_farm_id(seed_id: &SeedId, index: usize) -> FarmId {
     format!("{}#{}", seed_id, index)
 }
 
+#[allow(dead_code)]
 pub(crate) fn to_nano(timestamp: TimestampSec) -> Timestamp {
     Timestamp::from(timestamp) * 10u64.pow(9)
 }
@@ -112,36 +256,80 @@ pub(crate) fn to_sec(timestamp: Timestamp) -> TimestampSec {
     (timestamp / 10u64.pow(9)) as u32
 }
 
-pub fn get_nft_balance_equivalent(
-    nft_balance: NftBalance,
-    nft_staked: ContractNFTTokenId
-) -> Option<Balance> {
-    // split x.paras.near@1:1
-    // to "x.paras.near@1", ":1"
-    let mut result: Option<Balance> = None;
-
-    if let Some(nft_balance_equivalent) = nft_balance.get(&nft_staked.to_string()) {
-        result = Some(nft_balance_equivalent.0);
-    } else if nft_staked.contains(PARAS_SERIES_DELIMETER) {
-        let contract_token_series_id_split: Vec<&str> = nft_staked.split(PARAS_SERIES_DELIMETER).collect();
-        if let Some(nft_balance_equivalent) = nft_balance.get(&contract_token_series_id_split[0].to_string()) {
-            result = Some(nft_balance_equivalent.0);
-        } else {
-            let contract_token_series_id_split: Vec<&str> = nft_staked.split(NFT_DELIMETER).collect();
-            if let Some(nft_balance_equivalent) = nft_balance.get(&contract_token_series_id_split[0].to_string()) {
-                result = Some(nft_balance_equivalent.0);
-            } else {
-                result = None;
-            }
+/// Estimated bytes a new farm takes, assuming ids of max length.
+/// each storage record costs 40 bytes besides its key and value,
+/// an UnorderedMap entry takes 3 records: key to index, index to key and index to value.
+/// nft balance entries of a new NFT seed are not included.
+pub(crate) fn create_farm_storage_usage(seed_exists: bool, is_nft: bool) -> u128 {
+    let entry = |key_len: u128, value_len: u128| 3 * 40 + (1 + key_len + 8) + (9 + key_len) + (9 + value_len);
+    let account_len = 4 + MAX_ACCOUNT_LENGTH;
+    // {seed_id}#{index}
+    let farm_id_len = account_len + 1 + 10;
+    // farm_id, creator_id, terms, status, last_distribution, amounts, fee_bps, sweep_dust,
+    // opted_out_seed, paused_at, min_claim_amount, funders, beneficiaries, beneficiary_rewards,
+    // beneficiary_balances, beneficiary_cap_bps, min_rounds, reward_token_decimals, rate_changes, snapshots,
+    // then funders recorded on reward deposits, at most MAX_FARM_FUNDERS of them
+    let farm_len = farm_id_len + account_len + (2 * account_len + 4 + 16 + 4 + 1 + 4 + 2 + 4 + 4) + 1 + (16 + 16 + 32 + 4) + 3 * 16 + 2 + 1 + 16 + 4 + 16 + 4
+        + 4 + 4 + 4 + 2 + 4 + 2 + 4 + 4
+        + MAX_FARM_FUNDERS as u128 * (account_len + 16);
+    // farm entry, and farm_id in farms of the seed
+    let mut usage = entry(farm_id_len, farm_len) + farm_id_len;
+    if !seed_exists {
+        // seed_id, seed_type, farms, next_index, amount, min_deposit, metadata, nft_ramp_sec,
+        // total_seed_deposited, weight_scale, paused, min_farmer_stake, max_farmer_stake, farmer_count, aliases,
+        // nft_count
+        let seed_len = account_len + 1 + 4 + 4 + 16 + 16 + 1 + 4 + 16 + 1 + 1 + 16 + 16 + 8 + 4 + 8;
+        usage += entry(account_len, seed_len);
+        if is_nft {
+            usage += 40 + 1 + account_len + 4;
         }
+    }
+    usage
+}
+
+/// max weight_scale of NFT seed
+pub const MAX_NFT_WEIGHT_SCALE: u8 = 24;
+
+/// weight in nft balance multiplied by 10^weight_scale, None if it overflows
+pub(crate) fn scale_nft_weight(weight: Balance, weight_scale: u8) -> Option<Balance> {
+    10u128
+        .checked_pow(weight_scale as u32)
+        .and_then(|scale| weight.checked_mul(scale))
+}
+
+/// weight of a staked nft that linearly ramps up to full in ramp_sec after deposit
+pub(crate) fn ramped_nft_weight(
+    weight: Balance,
+    deposited_at: TimestampSec,
+    ramp_sec: TimestampSec,
+) -> Balance {
+    let elapsed = to_sec(env::block_timestamp()).saturating_sub(deposited_at);
+    if ramp_sec == 0 || elapsed >= ramp_sec {
+        weight
     } else {
-        let contract_token_series_id_split: Vec<&str> = nft_staked.split(NFT_DELIMETER).collect();
-        if let Some(nft_balance_equivalent) = nft_balance.get(&contract_token_series_id_split[0].to_string()) {
-            result = Some(nft_balance_equivalent.0);
-        } else {
-            result = None;
-        }
+        (U256::from(weight) * U256::from(elapsed) / U256::from(ramp_sec)).as_u128()
     }
+}
 
-    return result;
+/// keys an nft may be priced under in NftBalance, most specific first:
+/// the full token id, its paras series and its contract.
+pub(crate) fn nft_balance_keys(nft_staked: &str) -> Vec<String> {
+    // split x.paras.near@1:1
+    // to "x.paras.near@1", ":1"
+    let mut keys = vec![nft_staked.to_string()];
+    if nft_staked.contains(PARAS_SERIES_DELIMETER) {
+        keys.push(nft_staked.split(PARAS_SERIES_DELIMETER).next().unwrap().to_string());
+    }
+    keys.push(nft_staked.split(NFT_DELIMETER).next().unwrap().to_string());
+    keys
+}
+
+pub fn get_nft_balance_equivalent(
+    nft_balance: NftBalance,
+    nft_staked: ContractNFTTokenId
+) -> Option<Balance> {
+    nft_balance_keys(&nft_staked)
+        .iter()
+        .find_map(|key| nft_balance.get(key))
+        .map(|nft_balance_equivalent| nft_balance_equivalent.0)
 }
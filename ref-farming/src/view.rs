@@ -6,9 +6,13 @@ use near_sdk::json_types::{ValidAccountId, U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
 use near_sdk::{near_bindgen, AccountId};
 
-use crate::farm::DENOM;
+use crate::farm::{FarmSnapshot, FarmStatus, RateChange, DENOM, FEE_DIVISOR};
 use crate::farm_seed::SeedInfo;
-use crate::utils::{parse_farm_id, NFT_DELIMETER, PARAS_SERIES_DELIMETER};
+use crate::utils::{
+    create_farm_storage_usage, get_nft_balance_equivalent, parse_farm_id, ramped_nft_weight, scale_nft_weight, MAX_ACCOUNT_FARMS, MAX_BATCH_ACCOUNTS, MAX_BATCH_FARMS,
+    MAX_EMISSIONS_OVERVIEW, MAX_FARMS_PER_SEED, MAX_FARM_BENEFICIARIES, MAX_FARM_FUNDERS, MAX_NFT_TIERS, MAX_NFT_WEIGHT_SCALE, MAX_RANKED_POSITIONS,
+    MAX_REFERRAL_BPS, MAX_REWARD_TOKENS_PER_FARM, MIN_SEED_DEPOSIT, NFT_DELIMETER, PARAS_SERIES_DELIMETER,
+};
 use crate::*;
 
 use uint::construct_uint;
@@ -27,6 +31,48 @@ pub struct Metadata {
     pub farm_count: U64,
     pub seed_count: U64,
     pub reward_count: U64,
+    pub cleanup_grace_sec: u32,
+    pub default_min_rounds: u32,
+}
+
+/// Owner-settable parameters of the contract,
+/// every new contract-wide setting in owner.rs goes here too.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ContractConfig {
+    pub owner_id: AccountId,
+    /// an ended farm can only be cleaned after this long
+    pub cleanup_grace_sec: u32,
+    /// min_rounds given to each new farm
+    pub default_min_rounds: u32,
+    /// cut of each claim going to the farmer's referrer, in bps
+    pub referral_bps: u16,
+    /// number of seeds with deposits paused, see SeedInfo for which
+    pub paused_seed_count: U64,
+    /// upper bound of fee_bps settable on a farm, fee of each farm is in FarmInfo
+    pub max_fee_bps: u32,
+    /// upper bound of referral_bps
+    pub max_referral_bps: u16,
+    pub max_farms_per_seed: u32,
+    pub max_reward_tokens_per_farm: u32,
+    pub max_farm_funders: u32,
+    pub max_farm_beneficiaries: u32,
+    pub max_nft_tiers: u32,
+    pub max_nft_weight_scale: u8,
+    /// max accounts or farms handled by one batch call or paged view
+    pub max_batch_accounts: U64,
+    pub max_batch_farms: U64,
+    /// min_deposit of a new seed when none is given
+    pub default_min_deposit: U128,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct TokenSolvency {
+    /// token held by this contract, as tracked internally
+    pub deposited: U128,
+    /// staked seed, reward left in farms, farmers' reward and protocol fee
+    pub owed: U128,
 }
 
 #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
@@ -39,6 +85,11 @@ pub struct FarmInfo {
     pub start_at: u32,
     pub reward_per_session: U128,
     pub session_interval: u32,
+    pub by_block_height: bool,
+    pub accrual_granularity: u32,
+    pub decay_factor_bps: u16,
+    pub decay_period_rounds: u32,
+    pub end_at: u32,
 
     pub total_reward: U128,
     pub cur_round: u32,
@@ -46,13 +97,57 @@ pub struct FarmInfo {
     pub claimed_reward: U128,
     pub unclaimed_reward: U128,
     pub beneficiary_reward: U128,
+    pub fee_bps: u16,
+    pub sweep_dust: bool,
+    pub min_claim_amount: U128,
+    pub beneficiary_id: AccountId,
+    pub beneficiaries: Vec<(AccountId, u16)>,
+    pub beneficiary_cap_bps: u16,
+    pub min_rounds: u32,
+    pub reward_token_decimals: Option<u8>,
+}
+
+/// How reward of a farm is split so far.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmRewardBreakdown {
+    /// total reward deposited
+    pub amount_of_reward: U128,
+    /// claimed by farmers and beneficiary
+    pub amount_of_claimed: U128,
+    /// went to beneficiary when nothing was staked
+    pub amount_of_beneficiary: U128,
+    pub unclaimed: U128,
+    pub undistributed: U128,
+}
+
+/// Reward emission of a farm as of now, for dashboards.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub struct FarmEmission {
+    pub farm_id: FarmId,
+    pub reward_token: AccountId,
+    /// per block for farms counting block height, 0 once reward runs out
+    pub emission_per_second: U128,
+    pub total_seed: U128,
+    pub undistributed: U128,
+}
+
+/// Where a farm lives, so clients can route claims accordingly.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+#[serde(crate = "near_sdk::serde")]
+pub enum FarmLookup {
+    Active(FarmInfo),
+    Outdated(FarmInfo),
+    NotFound,
 }
 
 impl From<&Farm> for FarmInfo {
     fn from(farm: &Farm) -> Self {
         if let Some(dis) = farm.try_distribute(&DENOM) {
             let mut farm_status: String = (&farm.status).into();
-            if farm_status == "Running".to_string() && dis.undistributed == 0 {
+            if farm_status == "Running".to_string()
+                && (dis.undistributed == 0 || farm.terms.ended_by_date(dis.rr)) {
                 farm_status = "Ended".to_string();
             }
             Self {
@@ -63,6 +158,11 @@ impl From<&Farm> for FarmInfo {
                 start_at: farm.terms.start_at,
                 reward_per_session: farm.terms.reward_per_session.into(),
                 session_interval: farm.terms.session_interval,
+                by_block_height: farm.terms.by_block_height,
+                accrual_granularity: farm.terms.accrual_granularity,
+                decay_factor_bps: farm.terms.decay_factor_bps,
+                decay_period_rounds: farm.terms.decay_period_rounds,
+                end_at: farm.terms.end_at,
 
                 total_reward: farm.amount_of_reward.into(),
                 cur_round: dis.rr.into(),
@@ -70,6 +170,14 @@ impl From<&Farm> for FarmInfo {
                 claimed_reward: farm.amount_of_claimed.into(),
                 unclaimed_reward: dis.unclaimed.into(),
                 beneficiary_reward: farm.amount_of_beneficiary.into(),
+                fee_bps: farm.fee_bps,
+                sweep_dust: farm.sweep_dust,
+                min_claim_amount: farm.min_claim_amount.into(),
+                beneficiary_id: farm.get_beneficiary_id(),
+                beneficiaries: farm.get_beneficiaries(),
+                beneficiary_cap_bps: farm.beneficiary_cap_bps,
+                min_rounds: farm.min_rounds,
+                reward_token_decimals: farm.reward_token_decimals,
             }
         } else {
             Self {
@@ -80,6 +188,11 @@ impl From<&Farm> for FarmInfo {
                 start_at: farm.terms.start_at.into(),
                 reward_per_session: farm.terms.reward_per_session.into(),
                 session_interval: farm.terms.session_interval.into(),
+                by_block_height: farm.terms.by_block_height,
+                accrual_granularity: farm.terms.accrual_granularity,
+                decay_factor_bps: farm.terms.decay_factor_bps,
+                decay_period_rounds: farm.terms.decay_period_rounds,
+                end_at: farm.terms.end_at,
 
                 total_reward: farm.amount_of_reward.into(),
                 cur_round: farm.last_distribution.rr.into(),
@@ -88,6 +201,14 @@ impl From<&Farm> for FarmInfo {
                 // unclaimed_reward: (farm.amount_of_reward - farm.amount_of_claimed).into(),
                 unclaimed_reward: farm.last_distribution.unclaimed.into(),
                 beneficiary_reward: farm.amount_of_beneficiary.into(),
+                fee_bps: farm.fee_bps,
+                sweep_dust: farm.sweep_dust,
+                min_claim_amount: farm.min_claim_amount.into(),
+                beneficiary_id: farm.get_beneficiary_id(),
+                beneficiaries: farm.get_beneficiaries(),
+                beneficiary_cap_bps: farm.beneficiary_cap_bps,
+                min_rounds: farm.min_rounds,
+                reward_token_decimals: farm.reward_token_decimals,
             }
         }
     }
@@ -103,6 +224,29 @@ impl Contract {
             farm_count: self.data().farms.len().into(),
             seed_count: self.data().seeds.len().into(),
             reward_count: self.data().reward_info.len().into(),
+            cleanup_grace_sec: self.data().cleanup_grace_sec,
+            default_min_rounds: self.data().default_min_rounds,
+        }
+    }
+
+    pub fn get_config(&self) -> ContractConfig {
+        ContractConfig {
+            owner_id: self.data().owner_id.clone(),
+            cleanup_grace_sec: self.data().cleanup_grace_sec,
+            default_min_rounds: self.data().default_min_rounds,
+            referral_bps: self.data().referral_bps,
+            paused_seed_count: self.data().paused_seed_count.into(),
+            max_fee_bps: FEE_DIVISOR,
+            max_referral_bps: MAX_REFERRAL_BPS,
+            max_farms_per_seed: MAX_FARMS_PER_SEED as u32,
+            max_reward_tokens_per_farm: MAX_REWARD_TOKENS_PER_FARM as u32,
+            max_farm_funders: MAX_FARM_FUNDERS as u32,
+            max_farm_beneficiaries: MAX_FARM_BENEFICIARIES as u32,
+            max_nft_tiers: MAX_NFT_TIERS as u32,
+            max_nft_weight_scale: MAX_NFT_WEIGHT_SCALE,
+            max_batch_accounts: MAX_BATCH_ACCOUNTS.into(),
+            max_batch_farms: MAX_BATCH_FARMS.into(),
+            default_min_deposit: MIN_SEED_DEPOSIT.into(),
         }
     }
 
@@ -124,6 +268,63 @@ impl Contract {
             .collect()
     }
 
+    /// Returns ids of running farms, within given range of farms,
+    /// whose reward would run out before given timestamp in sec.
+    /// Farms counting in block height are skipped.
+    pub fn list_farms_ending_before(
+        &self,
+        timestamp_sec: u32,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<FarmId> {
+        let keys = self.data().farms.keys_as_vector();
+
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .map(|index| keys.get(index).unwrap())
+            .filter(|farm_id| {
+                let farm = self.data().farms.get(farm_id).unwrap();
+                !farm.terms.by_block_height
+                    && farm.projected_end_at().map_or(false, |end_at| end_at < timestamp_sec)
+            })
+            .collect()
+    }
+
+    /// Returns emission of farms of given length from given start index,
+    /// at most MAX_EMISSIONS_OVERVIEW farms.
+    pub fn get_emissions_overview(&self, from_index: u64, limit: u64) -> Vec<FarmEmission> {
+        let keys = self.data().farms.keys_as_vector();
+        let limit = std::cmp::min(limit, MAX_EMISSIONS_OVERVIEW);
+
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .map(|index| {
+                let farm = self.data().farms.get(&keys.get(index).unwrap()).unwrap();
+                let total_seed = self
+                    .get_seed_wrapped(&farm.get_seed_id())
+                    .map_or(0, |fs| fs.get_ref().amount);
+                let undistributed = farm
+                    .try_distribute(&farm.effective_seeds(total_seed))
+                    .map_or(farm.last_distribution.undistributed, |dis| dis.undistributed);
+                let emission_per_second = if undistributed > 0 && farm.terms.decays() {
+                    let (cur_round, _) = self.get_farm_rounds(farm.farm_id.clone());
+                    farm.terms.reward_in_rounds(cur_round, cur_round + 1)
+                        / farm.terms.round_interval() as u128
+                } else if undistributed > 0 {
+                    farm.terms.reward_per_session / farm.terms.session_interval as u128
+                } else {
+                    0
+                };
+                FarmEmission {
+                    farm_id: farm.farm_id.clone(),
+                    reward_token: farm.get_reward_token(),
+                    emission_per_second: emission_per_second.into(),
+                    total_seed: total_seed.into(),
+                    undistributed: undistributed.into(),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns list of cleared farms of given length from given start index.
     pub fn list_outdated_farms(&self, from_index: u64, limit: u64) -> Vec<FarmInfo> {
         let keys = self.data().outdated_farms.keys_as_vector();
 
@@ -139,6 +340,25 @@ impl Contract {
             .collect()
     }
 
+    /// Returns distinct seeds having farms in given range that reward given token.
+    pub fn get_seeds_by_reward_token(
+        &self,
+        token_id: ValidAccountId,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<SeedId> {
+        let keys = self.data().farms.keys_as_vector();
+        let mut seed_ids: Vec<SeedId> = vec![];
+        for index in from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()) {
+            let farm = self.data().farms.get(&keys.get(index).unwrap()).unwrap();
+            let seed_id = farm.get_seed_id();
+            if &farm.get_reward_token() == token_id.as_ref() && !seed_ids.contains(&seed_id) {
+                seed_ids.push(seed_id);
+            }
+        }
+        seed_ids
+    }
+
     pub fn list_farms_by_seed(&self, seed_id: SeedId) -> Vec<FarmInfo> {
         self.get_seed(&seed_id)
             .get_ref()
@@ -157,6 +377,175 @@ impl Contract {
         }
     }
 
+    /// Returns (current round, last distributed round) of given farm,
+    /// (0, 0) for unknown or not yet started farms.
+    pub fn get_farm_rounds(&self, farm_id: FarmId) -> (u32, u32) {
+        match self.data().farms.get(&farm_id) {
+            Some(farm) if !matches!(farm.status, FarmStatus::Created) => {
+                let cur = farm
+                    .try_distribute(&DENOM)
+                    .map_or(farm.last_distribution.rr, |dis| dis.rr);
+                (cur, farm.last_distribution.rr)
+            }
+            _ => (0, 0),
+        }
+    }
+
+    /// Returns whether reward can be deposited into given farm now,
+    /// false for unknown or outdated farm.
+    pub fn can_farm_accept_reward(&self, farm_id: FarmId) -> bool {
+        self.data()
+            .farms
+            .get(&farm_id)
+            .map_or(false, |farm| farm.can_accept_reward())
+    }
+
+    /// Returns true if given farm is running with nothing staked,
+    /// so that its reward goes to beneficiary.
+    pub fn is_farm_idle(&self, farm_id: FarmId) -> bool {
+        match self.data().farms.get(&farm_id) {
+            Some(farm) => {
+                let seed_amount = self.get_seed(&farm.get_seed_id()).get_ref().amount;
+                farm.is_idle(&farm.effective_seeds(seed_amount))
+            }
+            None => false,
+        }
+    }
+
+    /// Returns reward split of given farm as of now, active or outdated.
+    pub fn get_farm_reward_breakdown(&self, farm_id: FarmId) -> Option<FarmRewardBreakdown> {
+        let farm = self
+            .data()
+            .farms
+            .get(&farm_id)
+            .or_else(|| self.data().outdated_farms.get(&farm_id))?;
+        let (seed_id, _) = parse_farm_id(&farm_id);
+        let seed_amount = self.get_seed_wrapped(&seed_id).map_or(0, |fs| fs.get_ref().amount);
+        let total_seeds = farm.effective_seeds(seed_amount);
+
+        let mut amount_of_claimed = farm.amount_of_claimed;
+        let mut amount_of_beneficiary = farm.amount_of_beneficiary;
+        let mut dis = farm.last_distribution.clone();
+        if let Some(cur) = farm.try_distribute(&total_seeds) {
+            dis.undistributed = cur.undistributed;
+            if total_seeds == 0 {
+                // newly distributed reward would go to beneficiary, up to its cap
+                let to_beneficiary = cur.unclaimed - dis.unclaimed;
+                let room = farm.beneficiary_room();
+                if to_beneficiary > room {
+                    dis.undistributed += to_beneficiary - room;
+                }
+                let to_beneficiary = std::cmp::min(to_beneficiary, room);
+                amount_of_claimed += to_beneficiary;
+                amount_of_beneficiary += to_beneficiary;
+            } else {
+                dis.unclaimed = cur.unclaimed;
+            }
+        }
+        Some(FarmRewardBreakdown {
+            amount_of_reward: farm.amount_of_reward.into(),
+            amount_of_claimed: amount_of_claimed.into(),
+            amount_of_beneficiary: amount_of_beneficiary.into(),
+            unclaimed: dis.unclaimed.into(),
+            undistributed: dis.undistributed.into(),
+        })
+    }
+
+    /// Returns reward deposited into given farm by each funder.
+    pub fn get_farm_funders(&self, farm_id: FarmId) -> HashMap<AccountId, U128> {
+        if let Some(farm) = self.data().farms.get(&farm_id) {
+            farm.funders.into_iter().map(|(k, v)| (k, v.into())).collect()
+        } else {
+            HashMap::new()
+        }
+    }
+
+    /// Returns broken accounting invariants of the first MAX_BATCH_FARMS farms,
+    /// empty when healthy.
+    pub fn check_invariants(&self) -> Vec<String> {
+        self.check_invariants_in_range(0, MAX_BATCH_FARMS)
+    }
+
+    /// Same as check_invariants, for farms in given range, at most MAX_BATCH_FARMS of them.
+    pub fn check_invariants_in_range(&self, from_index: u64, limit: u64) -> Vec<String> {
+        let keys = self.data().farms.keys_as_vector();
+        let to_index = std::cmp::min(from_index.saturating_add(std::cmp::min(limit, MAX_BATCH_FARMS)), keys.len());
+        (from_index..to_index)
+            .flat_map(|index| self.data().farms.get(&keys.get(index).unwrap()).unwrap().invariant_violations())
+            .collect()
+    }
+
+    /// Returns latest reward rate changes of given farm, active or outdated, oldest first.
+    pub fn get_farm_rate_changes(&self, farm_id: FarmId) -> Vec<RateChange> {
+        self.data()
+            .farms
+            .get(&farm_id)
+            .or_else(|| self.data().outdated_farms.get(&farm_id))
+            .map_or_else(Vec::new, |farm| farm.rate_changes)
+    }
+
+    /// Returns latest snapshots of given farm, active or outdated, oldest first.
+    pub fn get_farm_snapshots(&self, farm_id: FarmId) -> Vec<FarmSnapshot> {
+        self.data()
+            .farms
+            .get(&farm_id)
+            .or_else(|| self.data().outdated_farms.get(&farm_id))
+            .map_or_else(Vec::new, |farm| farm.snapshots)
+    }
+
+    /// Returns funders of given farm, active or outdated, with the reward each deposited,
+    /// largest first, funders beyond MAX_FARM_FUNDERS are summed up under OTHER_FUNDERS.
+    pub fn get_farm_funding_history(&self, farm_id: FarmId) -> Vec<(AccountId, U128)> {
+        let mut history: Vec<(AccountId, U128)> = self
+            .data()
+            .farms
+            .get(&farm_id)
+            .or_else(|| self.data().outdated_farms.get(&farm_id))
+            .map_or_else(Vec::new, |farm| {
+                farm.funders.into_iter().map(|(k, v)| (k, v.into())).collect()
+            });
+        history.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+        history
+    }
+
+    /// Returns reward went to each beneficiary of given farm.
+    pub fn get_farm_beneficiary_rewards(&self, farm_id: FarmId) -> HashMap<AccountId, U128> {
+        if let Some(farm) = self.data().farms.get(&farm_id) {
+            farm.beneficiary_rewards.into_iter().map(|(k, v)| (k, v.into())).collect()
+        } else {
+            HashMap::new()
+        }
+    }
+
+    /// Returns reward of given farm, active or outdated,
+    /// each beneficiary can still withdraw.
+    pub fn get_farm_beneficiary_balances(&self, farm_id: FarmId) -> HashMap<AccountId, U128> {
+        self.data()
+            .farms
+            .get(&farm_id)
+            .or_else(|| self.data().outdated_farms.get(&farm_id))
+            .map_or_else(HashMap::new, |farm| {
+                farm.beneficiary_balances.into_iter().map(|(k, v)| (k, v.into())).collect()
+            })
+    }
+
+    /// Returns number of rounds the undistributed reward of given farm can still cover,
+    /// including a tail round with less than reward of a round,
+    /// u32::MAX if its decaying reward would never run out.
+    pub fn get_farm_rounds_remaining(&self, farm_id: FarmId) -> u32 {
+        if let Some(farm) = self.data().farms.get(&farm_id) {
+            let dis = farm
+                .try_distribute(&DENOM)
+                .unwrap_or_else(|| farm.last_distribution.clone());
+            farm.terms
+                .rounds_to_release(dis.rr, dis.undistributed)
+                .unwrap_or(u32::MAX)
+        } else {
+            0
+        }
+    }
+
+    /// Returns information about specified cleared farm.
     pub fn get_outdated_farm(&self, farm_id: FarmId) -> Option<FarmInfo> {
         if let Some(farm) = self.data().outdated_farms.get(&farm_id) {
             Some((&farm).into())
@@ -165,9 +554,20 @@ impl Contract {
         }
     }
 
+    /// Returns state of specified farm, distinguishes cleared farm from missing one.
+    pub fn get_farm_state(&self, farm_id: FarmId) -> FarmLookup {
+        if let Some(farm) = self.data().farms.get(&farm_id) {
+            FarmLookup::Active((&farm).into())
+        } else if let Some(farm) = self.data().outdated_farms.get(&farm_id) {
+            FarmLookup::Outdated((&farm).into())
+        } else {
+            FarmLookup::NotFound
+        }
+    }
+
     pub fn list_rewards_info(&self, from_index: u64, limit: u64) -> HashMap<AccountId, U128> {
         let keys = self.data().reward_info.keys_as_vector();
-        (from_index..std::cmp::min(from_index + limit, keys.len()))
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
             .map(|index| {
                 (
                     keys.get(index).unwrap(),
@@ -181,6 +581,57 @@ impl Contract {
             .collect()
     }
 
+    /// Returns deposited vs owed of given token, deposited should never be below owed.
+    pub fn get_token_solvency(&self, token_id: ValidAccountId) -> TokenSolvency {
+        let (deposited, owed) = self.internal_token_solvency(token_id.as_ref());
+        TokenSolvency {
+            deposited: deposited.into(),
+            owed: owed.into(),
+        }
+    }
+
+    /// Returns escrowed reward of given account per reward token.
+    pub fn get_escrow(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
+        self.data()
+            .escrow
+            .get(account_id.as_ref())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(k, v)| (k, v.into()))
+            .collect()
+    }
+
+    /// Returns reward owed per reward token, farmers' reward balances
+    /// plus reward distributed in farms but not yet claimed.
+    pub fn get_outstanding_liabilities(&self) -> HashMap<AccountId, U128> {
+        self.data()
+            .reward_info
+            .keys()
+            .map(|token_id| {
+                let ledger = self.data().token_ledger.get(&token_id).unwrap_or_default();
+                (token_id, (ledger.farmer_rewards + ledger.farm_unclaimed).into())
+            })
+            .collect()
+    }
+
+    /// Returns estimated storage fee to attach to create_simple_farm,
+    /// nft balance entries of a new NFT seed cost extra.
+    pub fn get_create_farm_storage_cost(&self, seed_exists: bool, is_nft: bool) -> U128 {
+        (create_farm_storage_usage(seed_exists, is_nft) * env::storage_byte_cost()).into()
+    }
+
+    /// Returns accumulated protocol fee of each reward token.
+    pub fn list_protocol_fees(&self, from_index: u64, limit: u64) -> HashMap<AccountId, U128> {
+        let keys = self.data().protocol_fees.keys_as_vector();
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
+            .map(|index| {
+                let token_id = keys.get(index).unwrap();
+                let fee = self.data().protocol_fees.get(&token_id).unwrap_or(0);
+                (token_id, fee.into())
+            })
+            .collect()
+    }
+
     /// Returns reward token claimed for given user outside of any farms.
     /// Returns empty list if no rewards claimed.
     pub fn list_rewards(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
@@ -198,20 +649,22 @@ impl Contract {
             .into()
     }
 
-    pub fn get_unclaimed_reward(&self, account_id: ValidAccountId, farm_id: FarmId) -> U128 {
+    /// Returns rps growth of given farm since given rps, as of now.
+    /// Reward of a farmer since then = delta * user_seeds / DENOM.
+    pub fn get_rps_delta(&self, farm_id: FarmId, since_rps: U128) -> U128 {
         let (seed_id, _) = parse_farm_id(&farm_id);
-
-        if let (Some(farmer), Some(farm_seed)) = (
-            self.get_farmer_wrapped(account_id.as_ref()),
+        if let (Some(farm), Some(farm_seed)) = (
+            self.data().farms.get(&farm_id),
             self.get_seed_wrapped(&seed_id),
         ) {
-            if let Some(farm) = self.data().farms.get(&farm_id) {
-                let reward_amount = farm.view_farmer_unclaimed_reward(
-                    &farmer.get_ref().get_rps(&farm.get_farm_id()),
-                    farmer.get_ref().seeds.get(&seed_id).unwrap_or(&0_u128),
-                    &farm_seed.get_ref().amount,
-                );
-                reward_amount.into()
+            let rps = match farm.try_distribute(&farm.effective_seeds(farm_seed.get_ref().amount)) {
+                Some(dis) => dis.rps,
+                None => farm.last_distribution.rps,
+            };
+            let cur_rps = U256::from_little_endian(&rps);
+            let since_rps = U256::from(since_rps.0);
+            if cur_rps > since_rps {
+                (cur_rps - since_rps).as_u128().into()
             } else {
                 0.into()
             }
@@ -220,10 +673,129 @@ impl Contract {
         }
     }
 
+    pub fn get_unclaimed_reward(&self, account_id: ValidAccountId, farm_id: FarmId) -> U128 {
+        self.internal_unclaimed_reward(account_id.as_ref(), &farm_id).into()
+    }
+
+    /// Returns what unclaimed reward of given farmer in given farm would be at given time,
+    /// assuming both the farmer's seed and total seed stay as now.
+    /// It stops growing once the reward deposited so far runs out.
+    /// For farms counting block height, at_timestamp_sec is a block height.
+    pub fn estimate_unclaimed_at(
+        &self,
+        account_id: ValidAccountId,
+        farm_id: FarmId,
+        at_timestamp_sec: u32,
+    ) -> U128 {
+        let (seed_id, _) = parse_farm_id(&farm_id);
+        if let (Some(farmer), Some(farm_seed), Some(farm)) = (
+            self.get_farmer_wrapped(account_id.as_ref()),
+            self.get_seed_wrapped(&seed_id),
+            self.data().farms.get(&farm_id),
+        ) {
+            if farmer.get_ref().opted_out_farms.contains(&farm_id) {
+                return 0.into();
+            }
+            farm.view_farmer_unclaimed_reward_at(
+                &farmer.get_ref().get_rps(&farm_id),
+                farmer.get_ref().seeds.get(&seed_id).unwrap_or(&0_u128),
+                &farm.effective_seeds(farm_seed.get_ref().amount),
+                at_timestamp_sec,
+            )
+            .into()
+        } else {
+            0.into()
+        }
+    }
+
+    /// Returns whether the storage given farmer paid for would still cover it
+    /// after claiming given seed, which may add reward tokens and rps entries.
+    pub fn will_claim_fit(&self, account_id: ValidAccountId, seed_id: SeedId) -> bool {
+        let farmer = match self.get_farmer_wrapped(account_id.as_ref()) {
+            Some(farmer) => farmer,
+            None => return false,
+        };
+        let mut new_rewards: Vec<AccountId> = vec![];
+        let mut new_rps = 0;
+        if let Some(farm_seed) = self.get_seed_wrapped(&seed_id) {
+            for farm_id in farm_seed.get_ref().farms.iter() {
+                if farmer.get_ref().user_rps.get(farm_id).is_none() {
+                    new_rps += 1;
+                }
+                let reward_token = self.data().farms.get(farm_id).unwrap().get_reward_token();
+                if !farmer.get_ref().rewards.contains_key(&reward_token)
+                    && !new_rewards.contains(&reward_token)
+                    && self.get_unclaimed_reward(account_id.clone(), farm_id.clone()).0 > 0
+                {
+                    new_rewards.push(reward_token);
+                }
+            }
+        }
+        farmer.get_ref().projected_storage_usage(new_rewards.len(), new_rps) <= farmer.get_ref().amount
+    }
+
+    /// Returns unclaimed reward of given farms in the same order,
+    /// unknown or malformed farm id gives 0.
+    pub fn get_unclaimed_rewards(&self, account_id: ValidAccountId, farm_ids: Vec<FarmId>) -> Vec<U128> {
+        farm_ids
+            .into_iter()
+            .map(|farm_id| {
+                if self.data().farms.get(&farm_id).is_some() {
+                    self.get_unclaimed_reward(account_id.clone(), farm_id)
+                } else {
+                    0.into()
+                }
+            })
+            .collect()
+    }
+
+    /// Returns farms the user stakes in with unclaimed reward of each,
+    /// sorted by unclaimed reward descending, at most MAX_RANKED_POSITIONS of them.
+    pub fn get_farmer_positions_ranked(&self, account_id: ValidAccountId) -> Vec<(FarmId, U128)> {
+        let mut positions: Vec<(FarmId, U128)> = vec![];
+        if let Some(farmer) = self.get_farmer_wrapped(account_id.as_ref()) {
+            for seed_id in farmer.get_ref().seeds.keys() {
+                if let Some(farm_seed) = self.get_seed_wrapped(seed_id) {
+                    for farm_id in farm_seed.get_ref().farms.iter() {
+                        let unclaimed = self.get_unclaimed_reward(account_id.clone(), farm_id.clone());
+                        positions.push((farm_id.clone(), unclaimed));
+                    }
+                }
+            }
+        }
+        positions.sort_by(|a, b| b.1 .0.cmp(&a.1 .0).then_with(|| a.0.cmp(&b.0)));
+        positions.truncate(MAX_RANKED_POSITIONS);
+        positions
+    }
+
+    /// Returns sorted ids of farms the user stakes in and has not opted out of,
+    /// at most MAX_ACCOUNT_FARMS of them.
+    pub fn get_account_farms(&self, account_id: ValidAccountId) -> Vec<FarmId> {
+        let mut farm_ids: Vec<FarmId> = vec![];
+        if let Some(farmer) = self.get_farmer_wrapped(account_id.as_ref()) {
+            for seed_id in farmer.get_ref().seeds.keys() {
+                if let Some(farm_seed) = self.get_seed_wrapped(seed_id) {
+                    farm_ids.extend(
+                        farm_seed
+                            .get_ref()
+                            .farms
+                            .iter()
+                            .filter(|farm_id| !farmer.get_ref().opted_out_farms.contains(*farm_id))
+                            .cloned(),
+                    );
+                }
+            }
+        }
+        farm_ids.sort();
+        farm_ids.dedup();
+        farm_ids.truncate(MAX_ACCOUNT_FARMS);
+        farm_ids
+    }
+
     /// return all seed and its amount staked in this contract in a hashmap
     pub fn list_seeds(&self, from_index: u64, limit: u64) -> HashMap<SeedId, U128> {
         let keys = self.data().seeds.keys_as_vector();
-        (from_index..std::cmp::min(from_index + limit, keys.len()))
+        (from_index..std::cmp::min(from_index.saturating_add(limit), keys.len()))
             .map(|index| {
                 (
                     keys.get(index).unwrap(),
@@ -265,6 +837,103 @@ impl Contract {
         }
     }
 
+    /// Returns farmers staking nfts in given NFT seed, with the nfts each staked,
+    /// among registered farmers in given range, at most MAX_BATCH_ACCOUNTS of them.
+    pub fn list_seed_nft_stakers(
+        &self,
+        seed_id: SeedId,
+        from_index: u64,
+        limit: u64,
+    ) -> Vec<(AccountId, Vec<ContractNFTTokenId>)> {
+        let farmer_ids = self.data().farmer_ids.as_vector();
+        let to_index = std::cmp::min(from_index.saturating_add(std::cmp::min(limit, MAX_BATCH_ACCOUNTS)), farmer_ids.len());
+        (from_index..to_index)
+            .filter_map(|index| {
+                let farmer_id = farmer_ids.get(index).unwrap();
+                let farmer = self.get_farmer_wrapped(&farmer_id)?;
+                let token_ids = farmer.get_ref().nft_seeds.get(&seed_id)?.to_vec();
+                if token_ids.is_empty() {
+                    None
+                } else {
+                    Some((farmer_id, token_ids))
+                }
+            })
+            .collect()
+    }
+
+    /// Returns share of each farmer in given farm, in bps of the seed farming on it,
+    /// among registered farmers in given range, at most MAX_BATCH_ACCOUNTS of them.
+    /// Farmers with no share are left out, so is everyone if nothing is staked.
+    pub fn get_farm_shares(&self, farm_id: FarmId, from_index: u64, limit: u64) -> Vec<(AccountId, u16)> {
+        let farm = match self.data().farms.get(&farm_id) {
+            Some(farm) => farm,
+            None => return vec![],
+        };
+        let seed_id = farm.get_seed_id();
+        let total = farm.effective_seeds(self.get_seed(&seed_id).get_ref().amount);
+        if total == 0 {
+            return vec![];
+        }
+        let farmer_ids = self.data().farmer_ids.as_vector();
+        let to_index = std::cmp::min(from_index.saturating_add(std::cmp::min(limit, MAX_BATCH_ACCOUNTS)), farmer_ids.len());
+        (from_index..to_index)
+            .filter_map(|index| {
+                let farmer_id = farmer_ids.get(index).unwrap();
+                let farmer = self.get_farmer_wrapped(&farmer_id)?;
+                if farmer.get_ref().opted_out_farms.contains(&farm_id) {
+                    return None;
+                }
+                let amount = *farmer.get_ref().seeds.get(&seed_id)?;
+                let bps = (U256::from(amount) * U256::from(FEE_DIVISOR) / U256::from(total)).as_u32() as u16;
+                Some((farmer_id, bps))
+            })
+            .collect()
+    }
+
+    /// Returns total staked in given seed, 0 for unknown seed,
+    /// for NFT seed it is the sum of applied weights of staked nfts.
+    pub fn get_effective_seed_total(&self, seed_id: SeedId) -> U128 {
+        self.get_seed_wrapped(&seed_id)
+            .map_or(0, |farm_seed| farm_seed.get_ref().amount)
+            .into()
+    }
+
+    /// Returns total of the seed behind given farm that farms on it,
+    /// that is the seed total less seed opted out of the farm, 0 for unknown farm.
+    /// Farms of a seed share its total, apart from opted out seed.
+    pub fn get_farm_effective_total(&self, farm_id: FarmId) -> U128 {
+        match self.data().farms.get(&farm_id) {
+            Some(farm) => farm
+                .effective_seeds(self.get_effective_seed_total(farm.get_seed_id()).0)
+                .into(),
+            None => U128(0),
+        }
+    }
+
+    /// Returns number of farmers staking in given seed, 0 for unknown seed.
+    pub fn get_seed_farmer_count(&self, seed_id: SeedId) -> u64 {
+        self.get_seed_wrapped(&seed_id)
+            .map_or(0, |farm_seed| farm_seed.get_ref().farmer_count)
+    }
+
+    /// Returns number of nfts staked in given NFT seed, 0 for unknown seed.
+    pub fn get_seed_nft_count(&self, seed_id: SeedId) -> u64 {
+        self.get_seed_wrapped(&seed_id)
+            .map_or(0, |farm_seed| farm_seed.get_ref().nft_count)
+    }
+
+    /// Returns alias tokens of given seed with the amount staked through each.
+    pub fn get_seed_aliases(&self, seed_id: SeedId) -> HashMap<AccountId, U128> {
+        self.get_seed_wrapped(&seed_id).map_or_else(HashMap::new, |farm_seed| {
+            farm_seed
+                .get_ref()
+                .aliases
+                .iter()
+                .map(|(token_id, amount)| (token_id.clone(), (*amount).into()))
+                .collect()
+        })
+    }
+
     pub fn get_seed_info(&self, seed_id: SeedId) -> Option<SeedInfo> {
         if let Some(farm_seed) = self.get_seed_wrapped(&seed_id) {
             let mut seed_info: SeedInfo = farm_seed.get_ref().into();
@@ -276,6 +945,29 @@ impl Contract {
         }
     }
 
+    /// Returns "FT" or "NFT", to pick withdraw_seed or withdraw_nft for given seed.
+    pub fn get_seed_type(&self, seed_id: SeedId) -> String {
+        match self.get_seed(&seed_id).get_ref().seed_type {
+            SeedType::FT => "FT".to_string(),
+            SeedType::NFT => "NFT".to_string(),
+        }
+    }
+
+    /// Returns accepted nfts of given NFT seed and their weights, None for FT seed.
+    pub fn get_nft_balance(&self, seed_id: SeedId) -> Option<NftBalance> {
+        self.data().nft_balance_seeds.get(&seed_id)
+    }
+
+    /// Returns named tiers of given NFT seed, empty if it has none.
+    pub fn get_seed_nft_tiers(&self, seed_id: SeedId) -> HashMap<String, NftTier> {
+        self.data().nft_tiers.get(&seed_id).unwrap_or_default()
+    }
+
+    /// Returns number of currently staked nfts in each tier of given seed.
+    pub fn get_nft_tier_counts(&self, seed_id: SeedId) -> HashMap<String, u64> {
+        self.data().nft_tier_counts.get(&seed_id).unwrap_or_default()
+    }
+
     pub fn list_seeds_info(&self, from_index: u64, limit: u64) -> HashMap<SeedId, SeedInfo> {
         let keys = self.data().seeds.keys_as_vector();
         (from_index..std::cmp::min(from_index + limit, keys.len()))
@@ -289,6 +981,39 @@ impl Contract {
             .collect()
     }
 
+    /// Returns current ramped weight of the user's staked nfts in given seed,
+    /// it is applied to the seed balance at the user's next claim on this seed.
+    pub fn get_user_nft_weight(&self, account_id: ValidAccountId, seed_id: SeedId) -> U128 {
+        if let (Some(farmer), Some(farm_seed)) = (
+            self.get_farmer_wrapped(account_id.as_ref()),
+            self.get_seed_wrapped(&seed_id),
+        ) {
+            let farmer = farmer.get();
+            let ramp_sec = farm_seed.get_ref().nft_ramp_sec;
+            if let Some(nft_contract_seed) = farmer.nft_seeds.get(&seed_id) {
+                return nft_contract_seed
+                    .iter()
+                    .filter_map(|token_id| farmer.nft_stakes.get(&token_id))
+                    .map(|stake| ramped_nft_weight(stake.weight, stake.deposited_at, ramp_sec))
+                    .sum::<Balance>()
+                    .into();
+            }
+        }
+        0.into()
+    }
+
+    /// Returns referral reward given account accrued by far, by token.
+    pub fn get_referral_rewards(&self, account_id: ValidAccountId) -> HashMap<AccountId, U128> {
+        self.get_farmer_wrapped(account_id.as_ref()).map_or_else(HashMap::new, |farmer| {
+            farmer
+                .get()
+                .referral_rewards
+                .into_iter()
+                .map(|(token_id, amount)| (token_id, amount.into()))
+                .collect()
+        })
+    }
+
     pub fn get_user_rps(&self, account_id: ValidAccountId, farm_id: FarmId) -> String {
         let farmer = self.get_farmer(account_id.as_ref());
         if let Some(rps) = farmer.get().user_rps.get(&farm_id) {
@@ -298,6 +1023,12 @@ impl Contract {
         }
     }
 
+    /// Farm round at which the user last claimed given farm, 0 if never or unregistered.
+    pub fn get_user_last_claim_round(&self, account_id: ValidAccountId, farm_id: FarmId) -> u32 {
+        self.get_farmer_wrapped(account_id.as_ref())
+            .map_or(0, |farmer| farmer.get_ref().get_claim_round(&farm_id))
+    }
+
     pub fn get_nft_balance_equivalent(
         &self,
         seed_id: SeedId,
@@ -339,4 +1070,21 @@ impl Contract {
         }
         return result;
     }
+
+    /// Returns seed weight given nft would get in given NFT seed once fully ramped,
+    /// by its token, its paras series or its contract, most specific first,
+    /// None if the seed does not accept it or the scaled weight overflows.
+    pub fn get_nft_equivalent(
+        &self,
+        seed_id: SeedId,
+        nft_contract_id: AccountId,
+        nft_token_id: String,
+    ) -> Option<U128> {
+        let farm_seed = self.get_seed_wrapped(&seed_id)?;
+        let nft_balance = self.data().nft_balance_seeds.get(&seed_id)?;
+        let contract_nft_token_id = format!("{}{}{}", nft_contract_id, NFT_DELIMETER, nft_token_id);
+        get_nft_balance_equivalent(nft_balance, contract_nft_token_id)
+            .and_then(|weight| scale_nft_weight(weight, farm_seed.get_ref().weight_scale))
+            .map(|weight| weight.into())
+    }
 }